@@ -0,0 +1,2127 @@
+/* logmerge - A program to merge files like tail -f
+ * Copyright (C) 2021 Torbjørn Birch Moltu
+ *
+ * licenced under the terms of the GNU Lesser General Public License
+ * as published by the Free Software Foundation,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Command line parsing.
+//!
+//! All options are described by `OPTIONS`, which is used both for parsing
+//! and for generating the `--help` output, so the two can't disagree.
+
+use std::ffi::{OsString, OsStr};
+#[cfg(unix)]
+use std::os::unix::ffi::{OsStringExt, OsStrExt};
+#[cfg(target_os="wasi")]
+use std::os::wasi::ffi::{OsStringExt, OsStrExt};
+use std::io::{stdin, stdout, stderr, Read, Write, Error as IoError, ErrorKind};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::process::exit;
+use std::borrow::Cow;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::collate;
+use crate::color;
+use crate::completions;
+use crate::glob;
+use crate::config;
+use crate::regex::Regex;
+use crate::json;
+use crate::timestamp::{self, TimeFormat, Pattern, YearGuess};
+use crate::zone::Zone;
+use crate::encoding::Encoding;
+use crate::heading::HeadingFormat;
+use crate::compare::{line_content, Comparator, Mode, KeySpec, Folding, Normalization};
+
+pub const NAME: &str = env!("CARGO_PKG_NAME");
+
+const DESCRIPTION: &str = "\
+\"Sorts\" the files but prints the file name above each group of lines from a file, like `tail -f`.
+Files are merged by sorting the next unprinted line from each file,
+without reordering lines from the same file or keeping everything in RAM.
+(Memory usage is linear with the number of files, not with the file sizes.)
+Directories are replaced by the files in them, sorted by name.
+A file of - means standard input, which can only be given once.
+Arguments after -- are always treated as files, even - and names starting with -.
+An argument @FILE is replaced by the whitespace-separated arguments in FILE,
+where double quotes can be used around arguments containing spaces.
+Use @@ to start an argument that isn't a response file with @.
+";
+
+pub struct OptionSpec {
+    pub long: &'static str,
+    pub short: Option<char>,
+    /// name of the argument in the help text, or None if the option is a flag
+    pub value: Option<&'static str>,
+    pub help: &'static str,
+    /// the value can only be given as --long=VALUE, and is None otherwise
+    pub optional: bool,
+    /// not shown in --help
+    pub hidden: bool,
+}
+
+pub const OPTIONS: &[OptionSpec] = &[
+    OptionSpec {
+        long: "help",  short: None,  value: None,
+        help: "Print this help and exit",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "version",  short: None,  value: None,
+        help: "Print the version and exit",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "verbose",  short: Some('v'),  value: None,
+        help: "Describe what is being done on stderr (-vv for more)",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "stats",  short: None,  value: None,
+        help: "Print how many lines, bytes and groups each file contributed on stderr when done",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "time",  short: None,  value: None,
+        help: "Print how long the merge took and how many bytes were read and written on stderr when done",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "memory-report",  short: None,  value: None,
+        help: "Print how big the buffer of each file got and when it grew on stderr when done",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "check",  short: Some('c'),  value: Some("quiet"),
+        help: "Check that each file is sorted instead of merging, and report the first unsorted line",
+        optional: true,  hidden: false,
+    },
+    OptionSpec {
+        long: "self-check",  short: None,  value: None,
+        help: "Exit with status 5 if the merged output would not be sorted, because an input isn't",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "debug",  short: None,  value: None,
+        help: "Underline the part of each line that was compared, and show parsed numbers",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "numeric-sort",  short: Some('n'),  value: None,
+        help: "Compare the number at the start of lines numerically",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "general-numeric-sort",  short: Some('g'),  value: None,
+        help: "Compare the floating-point number at the start of lines",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "human-numeric-sort",  short: Some('h'),  value: None,
+        help: "Compare numbers with a unit suffix such as 2K or 1G",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "version-sort",  short: Some('V'),  value: None,
+        help: "Compare version numbers such as 1.10.0 and 1.0~rc1",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "month-sort",  short: Some('M'),  value: None,
+        help: "Compare lines starting with English month abbreviations such as Jan",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "ignore-ansi",  short: None,  value: None,
+        help: "Ignore terminal escape sequences such as colors when comparing",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "natural-sort",  short: None,  value: None,
+        help: "Compare numbers anywhere in lines numerically, so that build-2 is before build-10",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "by-time",  short: None,  value: None,
+        help: "Compare ISO 8601 timestamps at the start of lines; lines without one get the time of the line before",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "time-format",  short: None,  value: Some("FORMAT"),
+        help: "Compare timestamps in FORMAT: iso8601, syslog, epoch, clf or a pattern like '[%d/%b/%Y:%H:%M:%S %z]' (implies --by-time)",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "tz",  short: None,  value: Some("ZONE"),
+        help: "Timestamps without an offset from UTC are in ZONE, such as Europe/Oslo, +02:00 or local",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "since",  short: None,  value: Some("TIME"),
+        help: "Only print lines from TIME, which is a timestamp or relative to now like -15min (implies --by-time)",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "until",  short: None,  value: Some("TIME"),
+        help: "Only print lines before TIME, and stop reading files when they reach it",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "rewrite-time",  short: None,  value: Some("FORMAT"),
+        help: "Replace timestamps in the output with FORMAT in UTC, which is like for --time-format (default iso8601)",
+        optional: true,  hidden: false,
+    },
+    OptionSpec {
+        long: "strict-time",  short: None,  value: None,
+        help: "Exit with status 6 at lines without a timestamp instead of giving them the time of the line before",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "assume-year",  short: None,  value: Some("YEAR"),
+        help: "Syslog timestamps at the start of files are in YEAR instead of the year the file was modified",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "ignore-case",  short: Some('f'),  value: Some("HOW"),
+        help: "Compare letters case-insensitively, HOW is ascii (the default), unicode or turkic",
+        optional: true,  hidden: false,
+    },
+    OptionSpec {
+        long: "normalize",  short: None,  value: Some("FORM"),
+        help: "Compare text after Unicode normalization to FORM, which is nfc or nfd",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "ignore-leading-blanks",  short: Some('b'),  value: None,
+        help: "Ignore spaces and tabs at the start of lines",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "dictionary-order",  short: Some('d'),  value: None,
+        help: "Only compare blanks, letters and digits",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "ignore-nonprinting",  short: Some('i'),  value: None,
+        help: "Ignore control characters such as escape when comparing",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "key",  short: Some('k'),  value: Some("KEYDEF"),
+        help: "Compare fields START[,END][bdfghinMrV] instead of entire lines, like sort -k 2,2n",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "field-separator",  short: Some('t'),  value: Some("SEP"),
+        help: "Fields for --key are separated by SEP instead of runs of blanks",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "skip-timestamp",  short: None,  value: None,
+        help: "Don't compare an ISO 8601 or syslog timestamp at the start of lines",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "key-regex",  short: None,  value: Some("REGEX"),
+        help: "Only compare what the first group in REGEX matches, or the entire match if it has no groups",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "logfmt-key",  short: None,  value: Some("NAME"),
+        help: "Only compare the value of the logfmt field NAME, as a timestamp unless another mode is given",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "json-key",  short: None,  value: Some("POINTER"),
+        help: "Only compare the value at POINTER like /meta/ts in lines of JSON, as a timestamp unless another mode is given",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "raw-key",  short: None,  value: None,
+        help: "Compare the value of --logfmt-key or --json-key as bytes instead of as a timestamp",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "warn-unmatched",  short: None,  value: None,
+        help: "Warn about lines that --key-regex doesn't match or that lack --logfmt-key or --json-key, which are compared as empty",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "locale",  short: None,  value: Some("LOCALE"),
+        help: "Compare text in the order of LOCALE or of LC_ALL, LC_COLLATE or LANG, instead of by bytes",
+        optional: true,  hidden: false,
+    },
+    OptionSpec {
+        long: "unique",  short: Some('u'),  value: None,
+        help: "Only print the first of consecutive lines with equal keys",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "ignore-blank-lines",  short: None,  value: None,
+        help: "Skip empty lines in the files",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "ignore-whitespace-lines",  short: None,  value: None,
+        help: "Skip lines that are empty or only contain whitespace",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "squeeze-blank",  short: None,  value: None,
+        help: "Only print the first of consecutive empty lines, even if they are from different files",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "grep",  short: None,  value: Some("REGEX"),
+        help: "Only print lines that REGEX matches, or that any of them match if repeated",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "invert-grep",  short: None,  value: Some("REGEX"),
+        help: "Leave out lines that REGEX matches, even if a --grep matches them (can be repeated)",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "file-grep",  short: None,  value: Some("REGEX"),
+        help: "Use REGEX instead of any --grep for the next file (can be repeated)",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "file-invert-grep",  short: None,  value: Some("REGEX"),
+        help: "Use REGEX instead of any --invert-grep for the next file (can be repeated)",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "max-lines",  short: None,  value: Some("N"),
+        help: "Stop after printing N lines, not counting headers or lines left out by --unique and similar",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "max-bytes",  short: None,  value: Some("N"),
+        help: "Stop before the first line that would make the output longer than N bytes, including headers",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "crlf",  short: None,  value: None,
+        help: "End lines, headers and markers with \\r\\n, the same as --line-ending=crlf",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "line-ending",  short: None,  value: Some("ENDING"),
+        help: "End lines in the output with lf or crlf, or preserve their ending (the default)",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "no-add-newline",  short: None,  value: None,
+        help: "Print the last line of files that don't end with a newline without one, so that a header after it has no empty line before it",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "zero-terminated",  short: Some('z'),  value: None,
+        help: "Lines end with NUL instead of newline, also in the output, where headers end with NUL too",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "record-separator",  short: None,  value: Some("STRING"),
+        help: "Lines end with STRING instead of newline, which can contain the escapes \\n, \\t, \\0 and \\xNN",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "max-line-length",  short: None,  value: Some("BYTES"),
+        help: "Exit with an error at lines longer than BYTES, which can end with K, M or G, or be unlimited (default 16M)",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "truncate-long-lines",  short: None,  value: None,
+        help: "Cut lines longer than --max-line-length and mark them with [truncated] instead of exiting",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "record-size",  short: None,  value: Some("N"),
+        help: "Merge binary records of N bytes instead of lines, without headers",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "join-continuation",  short: None,  value: Some("REGEX"),
+        help: "Treat lines matching REGEX (default ^\\s) as part of the line before, to keep stack traces together",
+        optional: true,  hidden: false,
+    },
+    OptionSpec {
+        long: "paragraph",  short: None,  value: None,
+        help: "Treat lines up to and including the next blank line as one line, such as for mbox files",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "csv",  short: None,  value: None,
+        help: "Don't end lines at newlines inside double quotes, as in CSV files",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "csv-key",  short: None,  value: Some("N"),
+        help: "Only compare field N of --csv lines, where fields are separated by commas or the -t separator",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "window",  short: None,  value: Some("N"),
+        help: "Merge the smallest of the next N lines in each file, to fix lines less than N lines out of place",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "unsorted",  short: None,  value: Some("WHAT"),
+        help: "When a line is smaller than the previous line in its file: allow, warn or error (exit 6)",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "stable",  short: Some('s'),  value: None,
+        help: "Print lines with equal keys in the order the files were given, even if that adds headers",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "skip-lines",  short: None,  value: Some("N"),
+        help: "Ignore the first N lines of each file, such as the header of CSV files",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "skip-chars",  short: None,  value: Some("N"),
+        help: "Ignore the first N bytes of lines",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "compare-bytes",  short: None,  value: Some("N"),
+        help: "Only compare the first N bytes of lines, after those skipped",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "reverse",  short: Some('r'),  value: None,
+        help: "Merge files that are sorted in descending order",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "output",  short: Some('o'),  value: Some("FILE"),
+        help: "Write to FILE instead of stdout",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "tee",  short: None,  value: Some("FILE"),
+        help: "Also write to FILE (can be repeated), and only warn if writing to it fails with 'best-effort:FILE'",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "output-format",  short: None,  value: Some("FORMAT"),
+        help: "Write text with headers, tsv (path, line number and line) or json (objects with source, line_number and line, where invalid UTF-8 is replaced)",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "no-headings",  short: Some('q'),  value: None,
+        help: "Don't print a header with the path before lines from a different file, like sort -m",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "line-numbers",  short: Some('N'),  value: None,
+        help: "Start each line with its line number in its file and ':', padded with zeros to what the file size allows",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "line-number-width",  short: None,  value: Some("DIGITS"),
+        help: "Pad --line-numbers to DIGITS digits instead of depending on the file",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "eof-markers",  short: None,  value: None,
+        help: "Print '<<< path (N lines)' after the last line of each file, also with --no-headings",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "escape-headings",  short: None,  value: None,
+        help: "Add a \\ before lines that start with what headers or --eof-markers start with (such as '>>> '), after any \\s",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "raw-paths",  short: None,  value: None,
+        help: "Show paths and labels as they are in headers and messages, without escaping control characters",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "pager",  short: None,  value: None,
+        help: "Show the merged lines in $PAGER or less if stdout is a terminal",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "no-pager",  short: None,  value: None,
+        help: "Don't use a pager, even if --pager was given before",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "color",  short: None,  value: Some("WHEN"),
+        help: "Color headers by file: auto (if stdout is a terminal and NO_COLOR isn't set), always or never",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "color-lines",  short: None,  value: None,
+        help: "Also color lines like the header of their file, when --color colors headers",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "palette",  short: None,  value: Some("LABEL=COLOR"),
+        help: "Color the file shown as LABEL with red, green, yellow, blue, magenta, cyan, 0-255 or #RRGGBB",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "basename-headers",  short: None,  value: None,
+        help: "Show only the file name in headers, or as many of the last directories as needed to tell files apart",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "heading-format",  short: None,  value: Some("TEMPLATE"),
+        help: "Print headers like TEMPLATE with {path}, {label}, {index} or {total_lines_so_far} (default '\\n>>> {label}\\n'), the first without a leading newline",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "group-separator",  short: None,  value: Some("STRING"),
+        help: "What to print before the headers after the first, instead of a blank line (can be empty)",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "min-group",  short: None,  value: Some("N"),
+        help: "Start the lines of groups with fewer than N lines with the path instead of printing a header",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "quiet-small-groups",  short: None,  value: None,
+        help: "Print groups that are too small for --min-group without anything before the lines",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "heading-range",  short: None,  value: None,
+        help: "Show the times of the first and last line after the path in headers, which delays each group until it ends",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "heading-style",  short: None,  value: Some("STYLE"),
+        help: "Print headers like tailmerge ('>>> path') or like tail ('==> path <==')",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "prefix-lines",  short: None,  value: Some("SEP"),
+        help: "Start each line with the path and SEP (default ': ') instead of printing headers",
+        optional: true,  hidden: false,
+    },
+    OptionSpec {
+        long: "legend",  short: None,  value: None,
+        help: "Start the output with a number for each file ('#1 = path'), and show those numbers in headers and before lines",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "align-prefixes",  short: None,  value: None,
+        help: "Pad the paths before lines with --prefix-lines so that the lines start in the same column",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "max-prefix-width",  short: None,  value: Some("COLUMNS"),
+        help: "Shorten the paths before lines with --prefix-lines to at most COLUMNS, ending with '…' (or '~' if they aren't UTF-8)",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "label",  short: None,  value: Some("NAME"),
+        help: "Show NAME instead of the path in headers for the next file",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "priority",  short: None,  value: Some("N"),
+        help: "When lines are equal, print those from the next file before files with lower N (default 0)",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "format",  short: None,  value: Some("FORMAT"),
+        help: "Read the next file as FORMAT, which is text (the default) or journal-export (from journalctl -o export)",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "encoding",  short: None,  value: Some("ENCODING"),
+        help: "The next file is in ENCODING: utf-8 (the default), utf-16le, utf-16be, auto (UTF-16 if it has a BOM), latin-1 or windows-1252",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "from-encoding",  short: None,  value: Some("ENCODING"),
+        help: "Same as --encoding",
+        optional: false,  hidden: true,
+    },
+    OptionSpec {
+        long: "file-skip-lines",  short: None,  value: Some("N"),
+        help: "Ignore the first N lines of the next file instead of the number given to --skip-lines",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "file-time-format",  short: None,  value: Some("FORMAT"),
+        help: "Timestamps in the next file are in FORMAT instead of the one given to --time-format",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "file-tz",  short: None,  value: Some("ZONE"),
+        help: "Timestamps without an offset in the next file are in ZONE instead of the one given to --tz",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "keep-bom",  short: None,  value: None,
+        help: "Don't skip a UTF-8 byte order mark at the start of files",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "glob",  short: None,  value: None,
+        help: "Expand *, ?, [...] and ** in file arguments (always done on Windows)",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "exclude",  short: None,  value: Some("GLOB"),
+        help: "Skip files matching GLOB when expanding directories and wildcards",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "include",  short: None,  value: Some("GLOB"),
+        help: "Don't skip files matching GLOB even if they match an --exclude",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "recursive",  short: Some('R'),  value: None,
+        help: "Also merge files in subdirectories of directory arguments",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "allow-special",  short: None,  value: None,
+        help: "Read from character devices, block devices and sockets",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "skip-nonfiles",  short: None,  value: None,
+        help: "Warn about and skip special files instead of exiting",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "files-from",  short: None,  value: Some("FILE"),
+        help: "Also merge the files listed in FILE, one per line (- for stdin)",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "no-config",  short: None,  value: None,
+        help: "Ignore TAILMERGE_OPTS and ~/.config/tailmerge/config.toml",
+        optional: false,  hidden: false,
+    },
+    OptionSpec {
+        long: "completions",  short: None,  value: Some("SHELL"),
+        help: "Print a completion script for bash, zsh or fish and exit",
+        optional: false,  hidden: true,
+    },
+    OptionSpec {
+        long: "files0-from",  short: None,  value: Some("FILE"),
+        help: "Also merge the files listed in FILE, separated by NUL bytes",
+        optional: false,  hidden: false,
+    },
+];
+
+pub enum Location {
+    Path(OsString),
+    Stdin,
+    /// a path from a --files-from or --files0-from list,
+    /// with the name of the list and where in it the path was
+    Listed(OsString, Rc<[u8]>, String),
+}
+
+pub struct Input {
+    pub location: Location,
+    /// shown in headers instead of the path
+    pub label: Option<Vec<u8>>,
+    /// for files found by expanding a directory or wildcard,
+    /// the path relative to the argument, which --exclude and --include apply to
+    pub relative: Option<PathBuf>,
+    /// larger means printed first when lines are equal
+    pub priority: i64,
+    /// from --file-time-format
+    pub time_format: Option<TimeFormat>,
+    /// from --file-tz
+    pub time_zone: Option<Rc<Zone>>,
+    pub format: InputFormat,
+    /// from --encoding
+    pub encoding: Encoding,
+    /// from --file-skip-lines
+    pub skip_lines: Option<u64>,
+    /// from --file-grep
+    pub grep: Option<Rc<Vec<Regex>>>,
+    /// from --file-invert-grep
+    pub invert_grep: Option<Rc<Vec<Regex>>>,
+}
+
+/// How to read a file, from --format.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum InputFormat {
+    #[default]
+    Text,
+    /// the export format of systemd's journal
+    JournalExport,
+}
+
+/// How to write the merged lines, from --output-format.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum OutputFormat {
+    /// with headers
+    #[default]
+    Text,
+    /// path, line number and the line, separated by tabs
+    Tsv,
+    /// an object with the path, line number and the line on each line
+    Json,
+}
+
+/// Which lines to skip when reading the files.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum BlankLines {
+    #[default]
+    Keep,
+    /// --ignore-blank-lines
+    Empty,
+    /// --ignore-whitespace-lines
+    Whitespace,
+}
+impl BlankLines {
+    /// `line` includes the terminator.
+    pub fn skips(self,  line: &[u8],  terminator: &[u8]) -> bool {
+        match self {
+            BlankLines::Keep => false,
+            BlankLines::Empty => line_content(line, terminator).is_empty(),
+            BlankLines::Whitespace => line_content(line, terminator).iter().all(|b| b.is_ascii_whitespace() ),
+        }
+    }
+}
+
+/// --line-ending
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum LineEnding {
+    #[default]
+    Preserve,
+    Lf,
+    Crlf,
+}
+impl LineEnding {
+    /// What to end lines with in the output, or None to keep their ending.
+    pub fn replacement(self) -> Option<&'static [u8]> {
+        match self {
+            LineEnding::Preserve => None,
+            LineEnding::Lf => Some(b"\n"),
+            LineEnding::Crlf => Some(b"\r\n"),
+        }
+    }
+}
+
+/// Which lines are merged together as one.
+#[derive(Default)]
+pub enum Records {
+    #[default]
+    Lines,
+    /// --join-continuation: lines matching the regex are part of the line before
+    Continuation(Regex),
+    /// --paragraph: lines up to and including one or more blank lines
+    Paragraphs,
+    /// --record-size: records of this many bytes
+    Fixed(usize),
+    /// --csv: lines up to a terminator that isn't inside double quotes
+    Csv,
+}
+
+/// --color
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum ColorWhen {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// --heading-style
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+enum HeadingStyle {
+    #[default]
+    Tailmerge,
+    Tail,
+}
+
+/// What to do when a file turns out to not be sorted.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum Unsorted {
+    #[default]
+    Allow,
+    Warn,
+    Error,
+}
+
+pub struct Options {
+    pub inputs: Vec<Input>,
+    pub output: Option<OsString>,
+    /// --tee: the files and whether they are best-effort
+    pub tee: Vec<(OsString, bool)>,
+    pub verbose: u8,
+    /// --stats
+    pub stats: bool,
+    /// --pager, unless followed by --no-pager
+    pub pager: bool,
+    /// --memory-report
+    pub memory_report: bool,
+    /// --time
+    pub time: bool,
+    pub debug: bool,
+    pub self_check: bool,
+    /// --check: only check that each file is sorted
+    pub check: bool,
+    /// --check=quiet: only report through the exit code
+    pub check_quiet: bool,
+    pub recursive: bool,
+    pub allow_special: bool,
+    pub skip_nonfiles: bool,
+    pub filters: glob::Filters,
+    pub comparator: Comparator,
+    pub unique: bool,
+    pub blank_lines: BlankLines,
+    pub line_ending: LineEnding,
+    pub squeeze_blank: bool,
+    /// --grep
+    pub grep: Vec<Regex>,
+    /// --invert-grep
+    pub invert_grep: Vec<Regex>,
+    /// --max-lines
+    pub max_lines: Option<u64>,
+    /// --max-bytes
+    pub max_bytes: Option<u64>,
+    /// false with --no-add-newline
+    pub add_newline: bool,
+    pub records: Records,
+    /// print a header before lines from a different file than the last,
+    /// false with --no-headings, --prefix-lines or --record-size
+    pub headers: bool,
+    /// --basename-headers
+    pub basename_headers: bool,
+    /// --raw-paths
+    pub raw_paths: bool,
+    /// --escape-headings
+    pub escape_headings: bool,
+    /// --eof-markers
+    pub eof_markers: bool,
+    /// --line-numbers
+    pub line_numbers: bool,
+    pub output_format: OutputFormat,
+    /// --line-number-width
+    pub line_number_width: Option<usize>,
+    pub color: ColorWhen,
+    /// --color-lines
+    pub color_lines: bool,
+    /// --palette: the labels or paths and colors, as escape sequences
+    pub palette: Vec<(Vec<u8>, Vec<u8>)>,
+    /// --prefix-lines: what to put between the path and each line
+    pub prefix: Option<Vec<u8>>,
+    /// --legend
+    pub legend: bool,
+    /// --align-prefixes
+    pub align_prefixes: bool,
+    /// --max-prefix-width
+    pub max_prefix_width: Option<usize>,
+    /// --heading-format, or None for the default headers
+    pub heading_format: Option<HeadingFormat>,
+    /// --heading-range
+    pub heading_range: bool,
+    /// --min-group
+    pub min_group: Option<usize>,
+    /// --quiet-small-groups
+    pub quiet_small_groups: bool,
+    /// --group-separator, or None for a blank line
+    pub group_separator: Option<Vec<u8>>,
+    /// --keep-bom, or --record-size
+    pub keep_bom: bool,
+    /// --max-line-length, or None if unlimited
+    pub max_line_length: Option<usize>,
+    pub truncate_long_lines: bool,
+    /// --window
+    pub window: usize,
+    /// --skip-lines
+    pub skip_lines: u64,
+    pub unsorted: Unsorted,
+    pub warn_unmatched: bool,
+    /// --tz
+    pub time_zone: Option<Rc<Zone>>,
+    /// --rewrite-time
+    pub rewrite_time: Option<TimeFormat>,
+    /// --since, in nanoseconds since 1970
+    pub since: Option<i64>,
+    /// --until, in nanoseconds since 1970
+    pub until: Option<i64>,
+    /// name of the locale used for --locale
+    pub locale: Option<String>,
+    glob: bool,
+    stdin_used: bool,
+    /// --raw-key
+    raw_key: bool,
+    /// -z, which --record-separator is incompatible with
+    zero_terminated: bool,
+    /// --heading-style, which --heading-format is incompatible with
+    heading_style: HeadingStyle,
+    /// --crlf or --line-ending, for error messages
+    line_ending_option: &'static str,
+    /// the option that set comparator.mode
+    mode_option: Option<&'static str>,
+    /// settings that came from TAILMERGE_OPTS or the configuration file,
+    /// which conflicting command-line options replace instead of being incompatible with
+    default_mode: bool,
+    default_blank_lines: bool,
+    default_records: bool,
+    /// options that apply to the next file argument
+    next_label: Option<Vec<u8>>,
+    next_priority: Option<i64>,
+    next_time_format: Option<TimeFormat>,
+    next_time_zone: Option<Rc<Zone>>,
+    next_format: Option<InputFormat>,
+    next_encoding: Option<Encoding>,
+    next_skip_lines: Option<u64>,
+    next_grep: Option<Vec<Regex>>,
+    next_invert_grep: Option<Vec<Regex>>,
+    /// --since and --until, which are parsed after --tz and --time-format
+    time_range: [Option<Vec<u8>>; 2],
+}
+impl Options {
+    fn add_argument(&mut self,  location: Location) {
+        let label = self.next_label.take();
+        let priority = self.next_priority.take().unwrap_or(0);
+        let time_format = self.next_time_format.take();
+        let time_zone = self.next_time_zone.take();
+        let format = self.next_format.take().unwrap_or_default();
+        let encoding = self.next_encoding.take().unwrap_or_default();
+        let skip_lines = self.next_skip_lines.take();
+        let grep = self.next_grep.take().map(Rc::new);
+        let invert_grep = self.next_invert_grep.take().map(Rc::new);
+        self.inputs.push(Input {
+                location,  label,  relative: None,  priority,  time_format,  time_zone,  format,  encoding,
+                skip_lines,  grep,  invert_grep,
+        });
+    }
+
+    fn add_listed(&mut self,  location: Location) {
+        self.inputs.push(Input {
+                location,  label: None,  relative: None,  priority: 0,
+                time_format: None,  time_zone: None,  format: InputFormat::Text,  encoding: Encoding::Utf8,
+                skip_lines: None,  grep: None,  invert_grep: None,
+        });
+    }
+
+    fn set_label(&mut self,  label: Vec<u8>) {
+        if self.next_label.is_some() {
+            usage_error("--label given twice for the same file");
+        }
+        let taken = self.inputs.iter().any(|input| input.label.as_ref() == Some(&label) );
+        if taken {
+            usage_error(&format!("duplicate label '{}'", String::from_utf8_lossy(&label)));
+        }
+        self.next_label = Some(label);
+    }
+
+    fn set_mode(&mut self,  mode: Mode,  option: &'static str) {
+        self.replace_default_mode();
+        match self.mode_option {
+            Some(previous) if previous != option => incompatible(previous, option),
+            _ if self.comparator.global.dictionary_order => incompatible("dictionary-order", option),
+            _ if self.comparator.global.ignore_nonprinting => incompatible("ignore-nonprinting", option),
+            _ => {}
+        }
+        self.mode_option = Some(option);
+        self.comparator.global.mode = mode;
+    }
+
+    /// Forget the ordering from the defaults when the command line has its own.
+    fn replace_default_mode(&mut self) {
+        if self.default_mode {
+            self.default_mode = false;
+            self.mode_option = None;
+            self.comparator.global.mode = Mode::default();
+            self.comparator.global.dictionary_order = false;
+            self.comparator.global.ignore_nonprinting = false;
+        }
+    }
+
+    /// Forget how the defaults split records when the command line says how.
+    fn replace_default_records(&mut self) {
+        if self.default_records {
+            self.default_records = false;
+            self.records = Records::Lines;
+            self.comparator.csv = false;
+            self.comparator.csv_key = None;
+            self.comparator.binary = false;
+        }
+    }
+
+    fn use_stdin(&mut self) {
+        if self.stdin_used {
+            usage_error("standard input ('-') can only be used once");
+        }
+        self.stdin_used = true;
+    }
+}
+
+/// Get the bytes of an argument without going through String on platforms where that is possible.
+pub fn os_to_bytes(arg: OsString) -> Vec<u8> {
+    #[cfg(any(unix, target_os="wasi"))]
+    return arg.into_vec();
+    #[cfg(not(any(unix, target_os="wasi")))]
+    return arg.into_string()
+        .unwrap_or_else(|bad| bad.to_string_lossy().into_owned() )
+        .into_bytes();
+}
+
+pub fn os_as_bytes(arg: &OsStr) -> Cow<'_, [u8]> {
+    #[cfg(any(unix, target_os="wasi"))]
+    return Cow::Borrowed(arg.as_bytes());
+    #[cfg(not(any(unix, target_os="wasi")))]
+    return match arg.to_string_lossy() {
+        Cow::Borrowed(s) => Cow::Borrowed(s.as_bytes()),
+        Cow::Owned(s) => Cow::Owned(s.into_bytes()),
+    };
+}
+
+pub fn bytes_to_os(bytes: &[u8]) -> OsString {
+    #[cfg(any(unix, target_os="wasi"))]
+    return OsString::from_vec(bytes.to_vec());
+    #[cfg(not(any(unix, target_os="wasi")))]
+    return OsString::from(String::from_utf8_lossy(bytes).into_owned());
+}
+
+fn parse_count(option: &OptionSpec,  value: OsString) -> usize {
+    match value.to_str().and_then(|value| value.parse().ok() ) {
+        Some(count) => count,
+        None => usage_error(&format!(
+                "--{} expects a non-negative number, not '{}'", option.long, value.to_string_lossy()
+        )),
+    }
+}
+
+fn parse_time_format(option: &OptionSpec,  format: &[u8]) -> TimeFormat {
+    match format {
+        b"iso8601" | b"iso" => TimeFormat::Iso8601,
+        b"syslog" => TimeFormat::Syslog,
+        b"epoch" => TimeFormat::Epoch,
+        b"clf" => TimeFormat::Clf,
+        pattern if pattern.contains(&b'%') => match Pattern::new(pattern) {
+            Ok(pattern) => TimeFormat::Pattern(pattern),
+            Err(problem) => usage_error(&format!(
+                    "invalid --{} '{}': {}", option.long, String::from_utf8_lossy(pattern), problem
+            )),
+        },
+        other => usage_error(&format!(
+                "invalid --{} '{}' (expected iso8601, syslog, epoch, clf or a pattern with %)",
+                option.long, String::from_utf8_lossy(other)
+        )),
+    }
+}
+
+/// Parse the value of --since or --until.
+fn parse_time(option: &str,  time: &[u8],  options: &Options) -> i64 {
+    let now = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_nanos() as i64,
+        Err(_) => 0,
+    };
+    if let Some(time) = timestamp::parse_relative(time, now) {
+        return time;
+    }
+    let format = &options.comparator.time_format;
+    let parsed = TimeFormat::Iso8601.parse(time)
+            .or_else(|| TimeFormat::Epoch.parse(time).filter(|_| time.iter().all(|&b| b.is_ascii_digit() || b == b'.' ) ) )
+            .or_else(|| format.parse(time).map(|mut time| {
+                if format.yearless() {
+                    time.nanoseconds = YearGuess::default().apply(time.nanoseconds, options.comparator.assume_year, now);
+                }
+                time
+            }));
+    match (parsed, &options.time_zone) {
+        (Some(time), Some(zone)) if time.naive => zone.to_utc(time.nanoseconds),
+        (Some(time), _) => time.nanoseconds,
+        (None, _) => usage_error(&format!(
+                "invalid --{} '{}' (expected a timestamp, or a time relative to now like -15min)",
+                option, String::from_utf8_lossy(time)
+        )),
+    }
+}
+
+/// Replace the escape sequences \\, \n, \r, \t, \0 and \xNN in the value of --record-separator,
+/// --prefix-lines or --heading-format.
+fn unescape(escaped: &[u8]) -> Result<Vec<u8>, String> {
+    let mut unescaped = Vec::with_capacity(escaped.len());
+    let mut i = 0;
+    while i < escaped.len() {
+        if escaped[i] != b'\\' {
+            unescaped.push(escaped[i]);
+            i += 1;
+            continue;
+        }
+        let (byte, length) = match escaped.get(i+1) {
+            Some(b'\\') => (b'\\', 2),
+            Some(b'n') => (b'\n', 2),
+            Some(b'r') => (b'\r', 2),
+            Some(b't') => (b'\t', 2),
+            Some(b'0') => (b'\0', 2),
+            Some(b'x') => {
+                let digits = escaped.get(i+2..i+4)
+                    .filter(|digits| digits.iter().all(u8::is_ascii_hexdigit) )
+                    .and_then(|digits| u8::from_str_radix(std::str::from_utf8(digits).unwrap(), 16).ok() );
+                match digits {
+                    Some(byte) => (byte, 4),
+                    None => return Err("\\x must be followed by two hexadecimal digits".to_string()),
+                }
+            }
+            Some(&other) => return Err(format!("unknown escape \\{}", other as char)),
+            None => return Err("ends with a lone \\".to_string()),
+        };
+        unescaped.push(byte);
+        i += length;
+    }
+    Ok(unescaped)
+}
+
+fn parse_zone(option: &OptionSpec,  name: &[u8]) -> Zone {
+    match Zone::new(name) {
+        Ok(zone) => zone,
+        Err(problem) => usage_error(&format!(
+                "invalid --{} '{}': {}", option.long, String::from_utf8_lossy(name), problem
+        )),
+    }
+}
+
+fn incompatible(first: &str,  second: &str) -> ! {
+    usage_error(&format!("options '--{}' and '--{}' are incompatible", first, second))
+}
+
+/// Print an error about the command line and exit with status 1.
+pub fn usage_error(message: &str) -> ! {
+    let stderr = stderr();
+    let mut stderr = stderr.lock();
+    let _ = writeln!(stderr, "{}: {}", NAME, message);
+    let _ = writeln!(stderr, "Try '{} --help' for more information.", NAME);
+    exit(1);
+}
+
+fn write_help(to: &mut dyn Write) -> std::io::Result<()> {
+    writeln!(to, "Usage: {} [OPTION]... [--] file1 [file2]...", NAME)?;
+    writeln!(to)?;
+    to.write_all(DESCRIPTION.as_bytes())?;
+    writeln!(to)?;
+    writeln!(to, "Options:")?;
+    let names = OPTIONS.iter().map(|option| {
+        let mut name = match option.short {
+            Some(short) => format!("-{}, --{}", short, option.long),
+            None => format!("    --{}", option.long),
+        };
+        match option.value {
+            Some(value) if option.optional => name.push_str(&format!("[={}]", value)),
+            Some(value) => {
+                name.push(' ');
+                name.push_str(value);
+            }
+            None => {}
+        }
+        name
+    }).collect::<Vec<String>>();
+    let width = OPTIONS.iter().zip(&names)
+        .filter(|(option, _)| !option.hidden )
+        .map(|(_, name)| name.len() )
+        .max()
+        .unwrap_or(0);
+    for (option, name) in OPTIONS.iter().zip(&names) {
+        if option.hidden {
+            continue;
+        }
+        writeln!(to, "  {:width$}  {}", name, option.help, width=width)?;
+    }
+    Ok(())
+}
+
+/// Print the help message to stderr and exit with an error, for when no files are given.
+pub fn print_usage_and_fail() -> ! {
+    let _ = write_help(&mut stderr().lock());
+    exit(1);
+}
+
+fn find_long(name: &[u8]) -> Option<&'static OptionSpec> {
+    OPTIONS.iter().find(|option| option.long.as_bytes() == name )
+}
+
+fn find_short(short: char) -> Option<&'static OptionSpec> {
+    OPTIONS.iter().find(|option| option.short == Some(short) )
+}
+
+/// Add the paths in a --files-from or --files0-from list.
+fn read_list(options: &mut Options,  list: OsString,  separator: u8) {
+    let mut contents = Vec::new();
+    let list = if os_as_bytes(&list).as_ref() == b"-" {
+        options.use_stdin();
+        if let Err(e) = stdin().lock().read_to_end(&mut contents) {
+            crate::error("Error reading from", b"(standard input)", e, 3);
+        }
+        Rc::<[u8]>::from(&b"(standard input)"[..])
+    } else {
+        let path = os_to_bytes(list.clone());
+        let result = File::open(&list).and_then(|mut file| file.read_to_end(&mut contents) );
+        if let Err(e) = result {
+            crate::error("Cannot read file list", &path, e, 2);
+        }
+        Rc::<[u8]>::from(path)
+    };
+    if separator == b'\n' {
+        // skip empty lines and comments
+        for (i, line) in contents.split(|&b| b == b'\n' ).enumerate() {
+            if !line.is_empty()  &&  line[0] != b'#' {
+                let position = format!("line {}", i+1);
+                options.add_listed(Location::Listed(bytes_to_os(line), list.clone(), position));
+            }
+        }
+    } else {
+        // file names can contain anything, so only the trailing separator is optional
+        let mut offset = 0;
+        while offset < contents.len() {
+            let length = contents[offset..].iter()
+                .position(|&b| b == separator )
+                .unwrap_or(contents.len()-offset);
+            if length == 0 {
+                let e = IoError::new(ErrorKind::InvalidData, format!("empty file name at offset {}", offset));
+                crate::error("Invalid file list", &list, e, 2);
+            }
+            let entry = &contents[offset..offset+length];
+            let position = format!("offset {}", offset);
+            options.add_listed(Location::Listed(bytes_to_os(entry), list.clone(), position));
+            offset += length + 1;
+        }
+    }
+}
+
+fn apply(options: &mut Options,  option: &OptionSpec,  value: Option<OsString>) {
+    match option.long {
+        "help" => {
+            if write_help(&mut stdout().lock()).is_err() {
+                exit(4);
+            }
+            exit(0);
+        }
+        "version" => {
+            if writeln!(stdout().lock(), "{} {}", NAME, env!("CARGO_PKG_VERSION")).is_err() {
+                exit(4);
+            }
+            exit(0);
+        }
+        "completions" => {
+            let shell = value.unwrap();
+            let result = match os_as_bytes(&shell).as_ref() {
+                b"bash" => completions::write_bash(&mut stdout().lock()),
+                b"zsh" => completions::write_zsh(&mut stdout().lock()),
+                b"fish" => completions::write_fish(&mut stdout().lock()),
+                _ => usage_error(&format!(
+                        "unsupported shell '{}' for --completions, expected bash, zsh or fish",
+                        shell.to_string_lossy()
+                )),
+            };
+            exit(if result.is_ok() {0} else {4});
+        }
+        "no-config" => {}, // handled before parsing
+        "numeric-sort" => options.set_mode(Mode::Numeric, option.long),
+        "general-numeric-sort" => options.set_mode(Mode::General, option.long),
+        "human-numeric-sort" => options.set_mode(Mode::Human, option.long),
+        "version-sort" => options.set_mode(Mode::Version, option.long),
+        "month-sort" => options.set_mode(Mode::Month, option.long),
+        "ignore-ansi" => options.comparator.global.ignore_ansi = true,
+        "natural-sort" => options.set_mode(Mode::Natural, option.long),
+        "by-time" => options.set_mode(Mode::Time, option.long),
+        "time-format" => {
+            options.comparator.time_format = parse_time_format(option, &os_to_bytes(value.unwrap()));
+            options.set_mode(Mode::Time, "by-time");
+        }
+        "file-time-format" => {
+            if options.next_time_format.is_some() {
+                usage_error("--file-time-format given twice for the same file");
+            }
+            options.next_time_format = Some(parse_time_format(option, &os_to_bytes(value.unwrap())));
+            options.set_mode(Mode::Time, "by-time");
+        }
+        "tz" => {
+            options.time_zone = Some(Rc::new(parse_zone(option, &os_to_bytes(value.unwrap()))));
+            options.set_mode(Mode::Time, "by-time");
+        }
+        "format" => {
+            if options.next_format.is_some() {
+                usage_error("--format given twice for the same file");
+            }
+            options.next_format = Some(match &os_to_bytes(value.unwrap())[..] {
+                b"text" => InputFormat::Text,
+                b"journal-export" => {
+                    // the rendered lines start with the time
+                    options.set_mode(Mode::Time, "by-time");
+                    InputFormat::JournalExport
+                }
+                other => usage_error(&format!(
+                        "invalid --format '{}' (expected text or journal-export)", String::from_utf8_lossy(other)
+                )),
+            });
+        }
+        "encoding" | "from-encoding" => {
+            if options.next_encoding.is_some() {
+                usage_error(&format!("--{} given twice for the same file", option.long));
+            }
+            options.next_encoding = Some(match &os_to_bytes(value.unwrap()).to_ascii_lowercase()[..] {
+                b"utf-8" | b"utf8" => Encoding::Utf8,
+                b"utf-16le" | b"utf16le" => Encoding::Utf16Le,
+                b"utf-16be" | b"utf16be" => Encoding::Utf16Be,
+                b"auto" => Encoding::Auto,
+                b"latin-1" | b"latin1" | b"iso-8859-1" => Encoding::Latin1,
+                b"windows-1252" | b"cp1252" => Encoding::Windows1252,
+                other => usage_error(&format!(
+                        "invalid --{} '{}' (expected utf-8, utf-16le, utf-16be, auto, latin-1 or windows-1252)",
+                        option.long, String::from_utf8_lossy(other)
+                )),
+            });
+        }
+        "file-tz" => {
+            if options.next_time_zone.is_some() {
+                usage_error("--file-tz given twice for the same file");
+            }
+            options.next_time_zone = Some(Rc::new(parse_zone(option, &os_to_bytes(value.unwrap()))));
+            options.set_mode(Mode::Time, "by-time");
+        }
+        "since" | "until" => {
+            options.time_range[(option.long == "until") as usize] = Some(os_to_bytes(value.unwrap()));
+            options.set_mode(Mode::Time, "by-time");
+        }
+        "min-group" => options.min_group = Some(parse_count(option, value.unwrap())),
+        "quiet-small-groups" => options.quiet_small_groups = true,
+        "group-separator" => {
+            let separator = os_to_bytes(value.unwrap());
+            match unescape(&separator) {
+                Ok(unescaped) => options.group_separator = Some(unescaped),
+                Err(problem) => usage_error(&format!(
+                        "invalid --group-separator '{}': {}", String::from_utf8_lossy(&separator), problem
+                )),
+            }
+        }
+        "heading-range" => options.heading_range = true,
+        "rewrite-time" => {
+            options.rewrite_time = Some(match value {
+                Some(format) => parse_time_format(option, &os_to_bytes(format)),
+                None => TimeFormat::Iso8601,
+            });
+            options.set_mode(Mode::Time, "by-time");
+        }
+        "strict-time" => options.comparator.strict_time = true,
+        "assume-year" => {
+            let value = value.unwrap();
+            match value.to_str().and_then(|value| value.parse().ok() ) {
+                Some(year) => options.comparator.assume_year = Some(year),
+                None => usage_error(&format!("invalid year '{}'", value.to_string_lossy())),
+            }
+        }
+        "ignore-case" => {
+            options.comparator.global.fold_case = match value.as_ref().map(|how| os_as_bytes(how) ).as_deref() {
+                None | Some(b"ascii") => Folding::Ascii,
+                Some(b"unicode") => Folding::Unicode,
+                Some(b"turkic") => Folding::Turkic,
+                Some(other) => usage_error(&format!(
+                        "--ignore-case must be ascii, unicode or turkic, not '{}'", String::from_utf8_lossy(other)
+                )),
+            };
+        }
+        "normalize" => {
+            if !cfg!(feature="normalize") {
+                usage_error("--normalize is not supported by this build (it needs the normalize feature)");
+            }
+            options.comparator.global.normalize = match os_as_bytes(&value.unwrap()).as_ref() {
+                b"nfc" | b"NFC" => Normalization::Nfc,
+                b"nfd" | b"NFD" => Normalization::Nfd,
+                other => usage_error(&format!(
+                        "--normalize must be nfc or nfd, not '{}'", String::from_utf8_lossy(other)
+                )),
+            };
+        }
+        "ignore-leading-blanks" => options.comparator.global.ignore_leading_blanks = true,
+        "dictionary-order" | "ignore-nonprinting" => {
+            options.replace_default_mode();
+            if let Some(mode_option) = options.mode_option {
+                incompatible(mode_option, option.long);
+            }
+            match option.long {
+                "dictionary-order" => options.comparator.global.dictionary_order = true,
+                _ => options.comparator.global.ignore_nonprinting = true,
+            }
+        }
+        "key" => {
+            let definition = os_to_bytes(value.unwrap());
+            match KeySpec::parse(&definition) {
+                Ok(key) => options.comparator.keys.push(key),
+                Err(problem) => usage_error(&format!(
+                        "invalid --key '{}': {}", String::from_utf8_lossy(&definition), problem
+                )),
+            }
+        }
+        "field-separator" => match os_to_bytes(value.unwrap())[..] {
+            [separator] => options.comparator.separator = Some(separator),
+            _ => usage_error("the field separator must be a single byte"),
+        },
+        "key-regex" => {
+            let pattern = os_to_bytes(value.unwrap());
+            match Regex::new(&pattern) {
+                Ok(regex) => options.comparator.key_regex = Some(regex),
+                Err(problem) => usage_error(&format!(
+                        "invalid --key-regex '{}': {}", String::from_utf8_lossy(&pattern), problem
+                )),
+            }
+        }
+        "logfmt-key" => {
+            let name = os_to_bytes(value.unwrap());
+            if name.is_empty()  ||  name.iter().any(|&b| matches!(b, b' ' | b'\t' | b'=' | b'"') ) {
+                usage_error(&format!("invalid --logfmt-key '{}'", String::from_utf8_lossy(&name)));
+            }
+            options.comparator.logfmt_key = Some(name);
+        }
+        "json-key" => {
+            let pointer = os_to_bytes(value.unwrap());
+            match json::Pointer::new(&pointer) {
+                Ok(pointer) => options.comparator.json_key = Some(pointer),
+                Err(problem) => usage_error(&format!(
+                        "invalid --json-key '{}': {}", String::from_utf8_lossy(&pointer), problem
+                )),
+            }
+        }
+        "raw-key" => options.raw_key = true,
+        "skip-timestamp" => options.comparator.skip_timestamp = true,
+        "warn-unmatched" => options.warn_unmatched = true,
+        "locale" => {
+            let name = value.map_or(Vec::new(), os_to_bytes);
+            let name = if &name[..] == b"env" {Vec::new()} else {name};
+            match collate::set_locale(&name) {
+                Some(locale) => options.locale = Some(locale),
+                None => usage_error(&format!("unknown locale '{}'", String::from_utf8_lossy(&name))),
+            }
+            options.comparator.collate = true;
+        }
+        "unique" => options.unique = true,
+        "ignore-blank-lines" | "ignore-whitespace-lines" => {
+            if options.default_blank_lines {
+                options.default_blank_lines = false;
+                options.blank_lines = BlankLines::Keep;
+            }
+            match option.long {
+                "ignore-blank-lines" if options.blank_lines == BlankLines::Keep => {
+                    options.blank_lines = BlankLines::Empty;
+                }
+                "ignore-blank-lines" => {}
+                _ => options.blank_lines = BlankLines::Whitespace,
+            }
+        }
+        "stable" => options.comparator.stable = true,
+        "no-add-newline" => options.add_newline = false,
+        "squeeze-blank" => options.squeeze_blank = true,
+        "grep" | "invert-grep" => {
+            let pattern = os_to_bytes(value.unwrap());
+            let regex = match Regex::new(&pattern) {
+                Ok(regex) => regex,
+                Err(problem) => usage_error(&format!(
+                        "invalid --{} '{}': {}", option.long, String::from_utf8_lossy(&pattern), problem
+                )),
+            };
+            match option.long {
+                "grep" => options.grep.push(regex),
+                _ => options.invert_grep.push(regex),
+            }
+        }
+        "file-grep" | "file-invert-grep" => {
+            let pattern = os_to_bytes(value.unwrap());
+            let regex = match Regex::new(&pattern) {
+                Ok(regex) => regex,
+                Err(problem) => usage_error(&format!(
+                        "invalid --{} '{}': {}", option.long, String::from_utf8_lossy(&pattern), problem
+                )),
+            };
+            let next = match option.long {
+                "file-grep" => &mut options.next_grep,
+                _ => &mut options.next_invert_grep,
+            };
+            next.get_or_insert_with(Vec::new).push(regex);
+        }
+        "max-lines" => options.max_lines = Some(parse_count(option, value.unwrap()) as u64),
+        "max-bytes" => options.max_bytes = Some(parse_count(option, value.unwrap()) as u64),
+        "zero-terminated" => {
+            options.zero_terminated = true;
+            options.comparator.record_separator = Some(vec![b'\0']);
+        }
+        "record-separator" => {
+            let separator = os_to_bytes(value.unwrap());
+            match unescape(&separator) {
+                Ok(unescaped) if unescaped.is_empty() => usage_error("--record-separator cannot be empty"),
+                Ok(unescaped) => options.comparator.record_separator = Some(unescaped),
+                Err(problem) => usage_error(&format!(
+                        "invalid --record-separator '{}': {}", String::from_utf8_lossy(&separator), problem
+                )),
+            }
+        }
+        "crlf" => {
+            options.line_ending = LineEnding::Crlf;
+            options.line_ending_option = option.long;
+        }
+        "line-ending" => {
+            options.line_ending = match &os_to_bytes(value.unwrap())[..] {
+                b"preserve" => LineEnding::Preserve,
+                b"lf" => LineEnding::Lf,
+                b"crlf" => LineEnding::Crlf,
+                other => usage_error(&format!(
+                        "invalid value for --line-ending: '{}' (expected preserve, lf or crlf)",
+                        String::from_utf8_lossy(other)
+                )),
+            };
+            options.line_ending_option = option.long;
+        }
+        "heading-style" => options.heading_style = match &os_to_bytes(value.unwrap())[..] {
+            b"tailmerge" => HeadingStyle::Tailmerge,
+            b"tail" => HeadingStyle::Tail,
+            other => usage_error(&format!(
+                    "invalid value for --heading-style: '{}' (expected tailmerge or tail)",
+                    String::from_utf8_lossy(other)
+            )),
+        },
+        "join-continuation" => {
+            options.replace_default_records();
+            match options.records {
+                Records::Paragraphs => incompatible("paragraph", option.long),
+                Records::Fixed(_) => incompatible("record-size", option.long),
+                Records::Csv => incompatible("csv", option.long),
+                _ => {}
+            }
+            let pattern = value.map_or(b"^\\s".to_vec(), os_to_bytes);
+            match Regex::new(&pattern) {
+                Ok(regex) => options.records = Records::Continuation(regex),
+                Err(problem) => usage_error(&format!(
+                        "invalid --join-continuation '{}': {}", String::from_utf8_lossy(&pattern), problem
+                )),
+            }
+        }
+        "paragraph" => {
+            options.replace_default_records();
+            match options.records {
+                Records::Continuation(_) => incompatible("join-continuation", option.long),
+                Records::Fixed(_) => incompatible("record-size", option.long),
+                Records::Csv => incompatible("csv", option.long),
+                _ => options.records = Records::Paragraphs,
+            }
+        }
+        "csv" | "csv-key" => {
+            options.replace_default_records();
+            match options.records {
+                Records::Continuation(_) => incompatible("join-continuation", option.long),
+                Records::Paragraphs => incompatible("paragraph", option.long),
+                Records::Fixed(_) => incompatible("record-size", option.long),
+                _ => options.records = Records::Csv,
+            }
+            options.comparator.csv = true;
+            if let Some(value) = value {
+                match parse_count(option, value) {
+                    0 => usage_error("--csv-key counts fields from 1"),
+                    field => options.comparator.csv_key = Some(field),
+                }
+            }
+        }
+        "record-size" => {
+            options.replace_default_records();
+            match options.records {
+                Records::Continuation(_) => incompatible("join-continuation", option.long),
+                Records::Paragraphs => incompatible("paragraph", option.long),
+                Records::Csv => incompatible("csv", option.long),
+                _ => {}
+            }
+            match parse_count(option, value.unwrap()) {
+                0 => usage_error("--record-size must be at least 1"),
+                size => options.records = Records::Fixed(size),
+            }
+            options.comparator.binary = true;
+            options.headers = false;
+            // binary records can start with anything
+            options.keep_bom = true;
+        }
+        "unsorted" => options.unsorted = match &os_to_bytes(value.unwrap())[..] {
+            b"allow" => Unsorted::Allow,
+            b"warn" => Unsorted::Warn,
+            b"error" => Unsorted::Error,
+            other => usage_error(&format!(
+                    "invalid value for --unsorted: '{}' (expected allow, warn or error)",
+                    String::from_utf8_lossy(other)
+            )),
+        },
+        "window" => match parse_count(option, value.unwrap()) {
+            0 => usage_error("--window must be at least 1"),
+            lines => options.window = lines,
+        },
+        "skip-lines" => options.skip_lines = parse_count(option, value.unwrap()) as u64,
+        "file-skip-lines" => {
+            if options.next_skip_lines.is_some() {
+                usage_error("--file-skip-lines given twice for the same file");
+            }
+            options.next_skip_lines = Some(parse_count(option, value.unwrap()) as u64);
+        }
+        "skip-chars" => options.comparator.skip_chars = parse_count(option, value.unwrap()),
+        "compare-bytes" => options.comparator.compare_bytes = Some(parse_count(option, value.unwrap())),
+        "reverse" => options.comparator.global.reverse = true,
+        "verbose" => options.verbose = options.verbose.saturating_add(1),
+        "stats" => options.stats = true,
+        "memory-report" => options.memory_report = true,
+        "time" => options.time = true,
+        "debug" => options.debug = true,
+        "self-check" => options.self_check = true,
+        "check" => {
+            options.check = true;
+            match value.map(os_to_bytes).as_deref() {
+                None => {}
+                Some(b"quiet") => options.check_quiet = true,
+                Some(other) => usage_error(&format!(
+                        "invalid value for --check: '{}' (expected quiet)", String::from_utf8_lossy(other)
+                )),
+            }
+        }
+        "output" => options.output = value,
+        "tee" => {
+            let path = os_to_bytes(value.unwrap());
+            options.tee.push(match path.strip_prefix(b"best-effort:") {
+                Some(path) => (bytes_to_os(path), true),
+                None => (bytes_to_os(&path), false),
+            });
+        }
+        "output-format" => {
+            options.output_format = match &os_to_bytes(value.unwrap())[..] {
+                b"text" => OutputFormat::Text,
+                b"tsv" => OutputFormat::Tsv,
+                b"json" => OutputFormat::Json,
+                other => usage_error(&format!(
+                        "invalid value for --output-format: '{}' (expected text, tsv or json)",
+                        String::from_utf8_lossy(other)
+                )),
+            };
+            options.headers = options.output_format == OutputFormat::Text;
+        }
+        "no-headings" => options.headers = false,
+        "basename-headers" => options.basename_headers = true,
+        "raw-paths" => options.raw_paths = true,
+        "escape-headings" => options.escape_headings = true,
+        "eof-markers" => options.eof_markers = true,
+        "line-numbers" => options.line_numbers = true,
+        "line-number-width" => options.line_number_width = Some(parse_count(option, value.unwrap())),
+        "palette" => {
+            let pin = os_to_bytes(value.unwrap());
+            let parsed = match pin.iter().rposition(|&b| b == b'=' ) {
+                Some(equals) => color::parse(&pin[equals+1..]).map(|color| (pin[..equals].to_vec(), color) ),
+                None => Err("expected LABEL=COLOR".to_string()),
+            };
+            match parsed {
+                Ok(pinned) => options.palette.push(pinned),
+                Err(problem) => usage_error(&format!(
+                        "invalid --palette '{}': {}", String::from_utf8_lossy(&pin), problem
+                )),
+            }
+        }
+        "pager" => options.pager = true,
+        "no-pager" => options.pager = false,
+        "color" => options.color = match &os_to_bytes(value.unwrap())[..] {
+            b"auto" => ColorWhen::Auto,
+            b"always" => ColorWhen::Always,
+            b"never" => ColorWhen::Never,
+            other => usage_error(&format!(
+                    "invalid value for --color: '{}' (expected auto, always or never)",
+                    String::from_utf8_lossy(other)
+            )),
+        },
+        "color-lines" => options.color_lines = true,
+        "heading-format" => {
+            let template = os_to_bytes(value.unwrap());
+            match unescape(&template).and_then(|unescaped| HeadingFormat::parse(&unescaped) ) {
+                Ok(format) => options.heading_format = Some(format),
+                Err(problem) => usage_error(&format!(
+                        "invalid --heading-format '{}': {}", String::from_utf8_lossy(&template), problem
+                )),
+            }
+        }
+        "prefix-lines" => {
+            let separator = value.map_or(b": ".to_vec(), os_to_bytes);
+            match unescape(&separator) {
+                Ok(unescaped) => options.prefix = Some(unescaped),
+                Err(problem) => usage_error(&format!(
+                        "invalid --prefix-lines '{}': {}", String::from_utf8_lossy(&separator), problem
+                )),
+            }
+            options.headers = false;
+        }
+        "legend" => options.legend = true,
+        "align-prefixes" => options.align_prefixes = true,
+        "max-prefix-width" => match parse_count(option, value.unwrap()) {
+            0 => usage_error("--max-prefix-width must be at least 1"),
+            columns => options.max_prefix_width = Some(columns),
+        },
+        "label" => options.set_label(os_to_bytes(value.unwrap())),
+        "priority" => {
+            if options.next_priority.is_some() {
+                usage_error("--priority given twice for the same file");
+            }
+            let value = value.unwrap();
+            match value.to_str().and_then(|value| value.parse().ok() ) {
+                Some(priority) => options.next_priority = Some(priority),
+                None => usage_error(&format!("invalid priority '{}'", value.to_string_lossy())),
+            }
+        }
+        "keep-bom" => options.keep_bom = true,
+        "max-line-length" => {
+            let value = os_to_bytes(value.unwrap());
+            let (number, unit) = match value.last() {
+                Some(b'K') | Some(b'k') => (&value[..value.len()-1], 1 << 10),
+                Some(b'M') | Some(b'm') => (&value[..value.len()-1], 1 << 20),
+                Some(b'G') | Some(b'g') => (&value[..value.len()-1], 1 << 30),
+                _ => (&value[..], 1),
+            };
+            let bytes = std::str::from_utf8(number).ok()
+                .and_then(|number| number.parse::<usize>().ok() )
+                .and_then(|number| number.checked_mul(unit) );
+            options.max_line_length = match (&value[..], bytes) {
+                (b"unlimited", _) => None,
+                (_, Some(0)) => usage_error("--max-line-length must be at least 1"),
+                (_, Some(bytes)) => Some(bytes),
+                (_, None) => usage_error(&format!(
+                        "invalid --max-line-length '{}' (expected a number of bytes or unlimited)",
+                        String::from_utf8_lossy(&value)
+                )),
+            };
+        }
+        "truncate-long-lines" => options.truncate_long_lines = true,
+        "glob" => options.glob = true,
+        "recursive" => options.recursive = true,
+        "allow-special" => options.allow_special = true,
+        "skip-nonfiles" => options.skip_nonfiles = true,
+        "exclude" => options.filters.exclude.push(os_to_bytes(value.unwrap())),
+        "include" => options.filters.include.push(os_to_bytes(value.unwrap())),
+        "files-from" => read_list(options, value.unwrap(), b'\n'),
+        "files0-from" => read_list(options, value.unwrap(), b'\0'),
+        unhandled => unreachable!("option --{} is in OPTIONS but not handled", unhandled),
+    }
+}
+
+/// How deeply response files can include other response files.
+const MAX_RESPONSE_FILE_DEPTH: usize = 8;
+
+/// Split the contents of a response file into arguments.
+/// Arguments are separated by whitespace, and double quotes can be used to
+/// include whitespace in an argument. Inside double quotes, \" and \\ are
+/// escapes for " and \, but outside of them \ has no special meaning,
+/// so that Windows paths can be written naturally.
+fn split_response_file(contents: &[u8],  path: &[u8]) -> Vec<Vec<u8>> {
+    let mut arguments = Vec::new();
+    let mut current = None::<Vec<u8>>;
+    let mut quoted = false;
+    let mut i = 0;
+    while i < contents.len() {
+        let b = contents[i];
+        if quoted {
+            let argument = current.as_mut().unwrap();
+            match b {
+                b'"' => quoted = false,
+                b'\\' if i+1 < contents.len()  &&  matches!(contents[i+1], b'"' | b'\\') => {
+                    argument.push(contents[i+1]);
+                    i += 1;
+                }
+                _ => argument.push(b),
+            }
+        } else if b.is_ascii_whitespace() {
+            arguments.extend(current.take());
+        } else if b == b'"' {
+            quoted = true;
+            current.get_or_insert_with(Vec::new);
+        } else {
+            current.get_or_insert_with(Vec::new).push(b);
+        }
+        i += 1;
+    }
+    if quoted {
+        let e = IoError::new(ErrorKind::InvalidData, "unterminated double quote");
+        crate::error("Invalid response file", path, e, 2);
+    }
+    arguments.extend(current);
+    arguments
+}
+
+/// Replace arguments of the form @file with the arguments in that file.
+/// @@ at the start of an argument is replaced with a single @ without expanding.
+fn expand_response_files(args: Vec<OsString>,  expanded: &mut Vec<OsString>,  open_files: &mut Vec<PathBuf>) {
+    for arg in args {
+        let bytes = os_as_bytes(&arg);
+        if bytes.starts_with(b"@@") {
+            expanded.push(bytes_to_os(&bytes[1..]));
+        } else if bytes.len() > 1  &&  bytes[0] == b'@' {
+            let path = bytes_to_os(&bytes[1..]);
+            let path_bytes = os_to_bytes(path.clone());
+            let mut contents = Vec::new();
+            let result = File::open(&path).and_then(|mut file| file.read_to_end(&mut contents) );
+            if let Err(e) = result {
+                crate::error("Cannot read response file", &path_bytes, e, 2);
+            }
+            let identity = fs::canonicalize(&path).unwrap_or_else(|_| PathBuf::from(&path) );
+            if open_files.contains(&identity) {
+                let e = IoError::new(ErrorKind::InvalidInput, "response file includes itself");
+                crate::error("Invalid response file", &path_bytes, e, 2);
+            } else if open_files.len() >= MAX_RESPONSE_FILE_DEPTH {
+                let e = IoError::new(ErrorKind::InvalidInput, "response files are nested too deeply");
+                crate::error("Invalid response file", &path_bytes, e, 2);
+            }
+            open_files.push(identity);
+            let arguments = split_response_file(&contents, &path_bytes).iter()
+                .map(|argument| bytes_to_os(argument) )
+                .collect();
+            expand_response_files(arguments, expanded, open_files);
+            open_files.pop();
+        } else {
+            drop(bytes);
+            expanded.push(arg);
+        }
+    }
+}
+
+/// Replace file arguments containing wildcards with the files they match.
+/// Arguments naming an existing file are not expanded, even if they contain wildcards.
+fn expand_patterns(inputs: Vec<Input>,  filters: &glob::Filters) -> Vec<Input> {
+    let mut expanded = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        let pattern = match &input.location {
+            Location::Path(pattern) => pattern,
+            _ => {
+                expanded.push(input);
+                continue;
+            }
+        };
+        let pattern_bytes = os_as_bytes(pattern);
+        if !glob::has_wildcards(&pattern_bytes)  ||  Path::new(pattern).exists() {
+            drop(pattern_bytes);
+            expanded.push(input);
+            continue;
+        }
+        let prefix = glob::literal_prefix(Path::new(pattern));
+        let matches = glob::expand(Path::new(pattern));
+        if matches.is_empty() {
+            let e = IoError::new(ErrorKind::NotFound, "no files match the pattern");
+            crate::error("Cannot open", &pattern_bytes, e, 2);
+        } else if matches.len() > 1  &&  input.label.is_some() {
+            usage_error(&format!("--label given for '{}' which matches several files",
+                    String::from_utf8_lossy(&pattern_bytes)
+            ));
+        }
+        drop(pattern_bytes);
+        for path in matches {
+            let relative = path.strip_prefix(&prefix).unwrap_or(&path).to_path_buf();
+            if filters.allows(&relative) {
+                let location = Location::Path(path.into_os_string());
+                let label = input.label.clone();
+                let priority = input.priority;
+                let time_format = input.time_format.clone();
+                let time_zone = input.time_zone.clone();
+                expanded.push(Input {
+                        location,  label,  relative: Some(relative),  priority,  time_format,  time_zone,
+                        format: input.format,  encoding: input.encoding,  skip_lines: input.skip_lines,
+                        grep: input.grep.clone(),  invert_grep: input.invert_grep.clone(),
+                });
+            }
+        }
+    }
+    expanded
+}
+
+/// Parse options and file arguments,
+/// or only options if `defaults` (from the config file or environment variable).
+fn parse_into(options: &mut Options,  args: Vec<OsString>,  defaults: bool) {
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let bytes = os_as_bytes(&arg);
+        if defaults  &&  (&bytes[..] == b"--"  ||  bytes.len() < 2  ||  bytes[0] != b'-') {
+            usage_error(&format!("{} and the configuration file can only contain options, not '{}'",
+                    config::ENVIRONMENT_VARIABLE, String::from_utf8_lossy(&bytes)
+            ));
+        } else if &bytes[..] == b"--" {
+            for arg in args {
+                options.add_argument(Location::Path(arg));
+            }
+            break;
+        } else if bytes.starts_with(b"--") {
+            let (name, inline_value) = match bytes.iter().position(|&b| b == b'=' ) {
+                Some(eq) => (&bytes[2..eq], Some(bytes_to_os(&bytes[eq+1..]))),
+                None => (&bytes[2..], None),
+            };
+            let option = find_long(name).unwrap_or_else(|| {
+                usage_error(&format!("unrecognized option '{}'", String::from_utf8_lossy(&bytes)));
+            });
+            let value = match (option.value, inline_value) {
+                (None, None) => None,
+                (None, Some(_)) => {
+                    usage_error(&format!("option '--{}' doesn't allow an argument", option.long));
+                }
+                (Some(_), Some(value)) => Some(value),
+                (Some(_), None) if option.optional => None,
+                (Some(_), None) => match args.next() {
+                    Some(value) => Some(value),
+                    None => usage_error(&format!("option '--{}' requires an argument", option.long)),
+                },
+            };
+            apply(options, option, value);
+        } else if bytes.len() > 1  &&  bytes[0] == b'-' {
+            // one or more short options, the last of which might take a value
+            for i in 1..bytes.len() {
+                let short = bytes[i] as char;
+                let option = find_short(short).filter(|_| bytes[i].is_ascii() ).unwrap_or_else(|| {
+                    let shown = match bytes[i].is_ascii() {
+                        true => short.to_string(),
+                        false => String::from_utf8_lossy(&bytes[i..]).into_owned(),
+                    };
+                    usage_error(&format!("invalid option -- '{}'", shown));
+                });
+                if option.value.is_some()  &&  !option.optional {
+                    let value = if i+1 < bytes.len() {
+                        bytes_to_os(&bytes[i+1..])
+                    } else if let Some(value) = args.next() {
+                        value
+                    } else {
+                        usage_error(&format!("option requires an argument -- '{}'", short));
+                    };
+                    apply(options, option, Some(value));
+                    break;
+                }
+                apply(options, option, None);
+            }
+        } else if &bytes[..] == b"-" {
+            options.use_stdin();
+            options.add_argument(Location::Stdin);
+        } else {
+            options.add_argument(Location::Path(arg));
+        }
+    }
+}
+
+pub fn parse_args(args: impl Iterator<Item=OsString>) -> Options {
+    let mut expanded = Vec::new();
+    expand_response_files(args.collect(), &mut expanded, &mut Vec::new());
+    let mut options = Options {
+        inputs: Vec::new(),
+        output: None,
+        tee: Vec::new(),
+        verbose: 0,
+        stats: false,
+        pager: false,
+        memory_report: false,
+        time: false,
+        debug: false,
+        self_check: false,
+        check: false,
+        check_quiet: false,
+        recursive: false,
+        allow_special: false,
+        skip_nonfiles: false,
+        filters: glob::Filters::default(),
+        comparator: Comparator::default(),
+        unique: false,
+        blank_lines: BlankLines::Keep,
+        line_ending: LineEnding::Preserve,
+        add_newline: true,
+        prefix: None,
+        legend: false,
+        align_prefixes: false,
+        max_prefix_width: None,
+        basename_headers: false,
+        raw_paths: false,
+        escape_headings: false,
+        eof_markers: false,
+        line_numbers: false,
+        output_format: OutputFormat::Text,
+        line_number_width: None,
+        color: ColorWhen::Auto,
+        color_lines: false,
+        palette: Vec::new(),
+        heading_format: None,
+        heading_range: false,
+        group_separator: None,
+        min_group: None,
+        quiet_small_groups: false,
+        squeeze_blank: false,
+        grep: Vec::new(),
+        invert_grep: Vec::new(),
+        max_lines: None,
+        max_bytes: None,
+        records: Records::Lines,
+        headers: true,
+        keep_bom: false,
+        max_line_length: Some(16 << 20),
+        truncate_long_lines: false,
+        window: 1,
+        skip_lines: 0,
+        unsorted: Unsorted::Allow,
+        warn_unmatched: false,
+        time_zone: None,
+        rewrite_time: None,
+        since: None,
+        until: None,
+        locale: None,
+        glob: cfg!(windows),
+        stdin_used: false,
+        raw_key: false,
+        zero_terminated: false,
+        heading_style: HeadingStyle::Tailmerge,
+        line_ending_option: "line-ending",
+        mode_option: None,
+        default_mode: false,
+        default_blank_lines: false,
+        default_records: false,
+        next_label: None,
+        next_priority: None,
+        next_time_format: None,
+        next_time_zone: None,
+        next_format: None,
+        next_encoding: None,
+        next_skip_lines: None,
+        next_grep: None,
+        next_invert_grep: None,
+        time_range: [None, None],
+    };
+    let no_config = expanded.iter()
+        .take_while(|arg| arg.as_os_str() != "--" )
+        .any(|arg| arg.as_os_str() == "--no-config" );
+    if !no_config {
+        let mut defaults = config::environment_arguments();
+        defaults.extend(config::config_arguments());
+        parse_into(&mut options, defaults, true);
+        options.default_mode = options.mode_option.is_some()
+                ||  options.comparator.global.dictionary_order
+                ||  options.comparator.global.ignore_nonprinting;
+        options.default_blank_lines = options.blank_lines != BlankLines::Keep;
+        options.default_records = !matches!(options.records, Records::Lines);
+        if options.next_label.is_some() {
+            usage_error("--label cannot be a default");
+        } else if options.next_priority.is_some() {
+            usage_error("--priority cannot be a default");
+        } else if options.next_time_format.is_some() {
+            usage_error("--file-time-format cannot be a default");
+        } else if options.next_time_zone.is_some() {
+            usage_error("--file-tz cannot be a default");
+        } else if options.next_format.is_some() {
+            usage_error("--format cannot be a default");
+        } else if options.next_encoding.is_some() {
+            usage_error("--encoding cannot be a default");
+        } else if options.next_skip_lines.is_some() {
+            usage_error("--file-skip-lines cannot be a default");
+        } else if options.next_grep.is_some() {
+            usage_error("--file-grep cannot be a default");
+        } else if options.next_invert_grep.is_some() {
+            usage_error("--file-invert-grep cannot be a default");
+        }
+    }
+    parse_into(&mut options, expanded, false);
+    options.default_mode = false;
+    if options.next_label.is_some() {
+        usage_error("--label must be followed by a file");
+    } else if options.next_priority.is_some() {
+        usage_error("--priority must be followed by a file");
+    } else if options.next_time_format.is_some() {
+        usage_error("--file-time-format must be followed by a file");
+    } else if options.next_time_zone.is_some() {
+        usage_error("--file-tz must be followed by a file");
+    } else if options.next_format.is_some() {
+        usage_error("--format must be followed by a file");
+    } else if options.next_encoding.is_some() {
+        usage_error("--encoding must be followed by a file");
+    } else if options.next_skip_lines.is_some() {
+        usage_error("--file-skip-lines must be followed by a file");
+    } else if options.next_grep.is_some() {
+        usage_error("--file-grep must be followed by a file");
+    } else if options.next_invert_grep.is_some() {
+        usage_error("--file-invert-grep must be followed by a file");
+    }
+    let searches = [
+        ("key-regex", options.comparator.key_regex.is_some()),
+        ("logfmt-key", options.comparator.logfmt_key.is_some()),
+        ("json-key", options.comparator.json_key.is_some()),
+        ("csv-key", options.comparator.csv_key.is_some()),
+    ];
+    let mut given = searches.iter().filter(|&&(_, given)| given ).map(|&(option, _)| option );
+    if let (Some(first), Some(second)) = (given.next(), given.next()) {
+        incompatible(first, second);
+    }
+    let separator_option = if options.zero_terminated {"zero-terminated"} else {"record-separator"};
+    if options.zero_terminated  &&  options.comparator.record_separator.as_deref() != Some(b"\0") {
+        incompatible("zero-terminated", "record-separator");
+    } else if options.comparator.binary  &&  options.comparator.record_separator.is_some() {
+        incompatible(separator_option, "record-size");
+    } else if options.comparator.binary  &&  options.blank_lines == BlankLines::Empty {
+        incompatible("ignore-blank-lines", "record-size");
+    } else if options.comparator.binary  &&  options.blank_lines == BlankLines::Whitespace {
+        incompatible("ignore-whitespace-lines", "record-size");
+    } else if options.comparator.binary  &&  options.line_ending != LineEnding::Preserve {
+        incompatible("record-size", options.line_ending_option);
+    } else if options.comparator.terminator() != b"\n"  &&  options.line_ending != LineEnding::Preserve {
+        incompatible(separator_option, options.line_ending_option);
+    }
+    if let (Records::Paragraphs, true) = (&options.records, options.blank_lines != BlankLines::Keep) {
+        let ignore = match options.blank_lines {
+            BlankLines::Empty => "ignore-blank-lines",
+            _ => "ignore-whitespace-lines",
+        };
+        incompatible(ignore, "paragraph");
+    }
+    if options.prefix.is_none()  &&  options.align_prefixes {
+        usage_error("--align-prefixes requires --prefix-lines");
+    } else if options.prefix.is_none()  &&  options.max_prefix_width.is_some() {
+        usage_error("--max-prefix-width requires --prefix-lines");
+    }
+    if options.quiet_small_groups  &&  options.min_group.is_none() {
+        usage_error("--quiet-small-groups requires --min-group");
+    } else if options.min_group.is_some()  &&  options.heading_range {
+        incompatible("min-group", "heading-range");
+    } else if options.min_group.is_some()  &&  options.max_bytes.is_some() {
+        incompatible("min-group", "max-bytes");
+    }
+    if options.output_format != OutputFormat::Text  &&  options.prefix.is_some() {
+        incompatible("output-format", "prefix-lines");
+    } else if options.output_format != OutputFormat::Text  &&  options.eof_markers {
+        incompatible("output-format", "eof-markers");
+    } else if options.output_format != OutputFormat::Text  &&  options.legend {
+        incompatible("output-format", "legend");
+    }
+    if options.heading_style == HeadingStyle::Tail {
+        if options.heading_format.is_some() {
+            incompatible("heading-format", "heading-style");
+        }
+        // with a blank line before all but the first, like tail -f with several files
+        let template = match options.group_separator {
+            Some(_) => &b"==> {label} <==\n"[..],
+            None => b"\n==> {label} <==\n",
+        };
+        options.heading_format = Some(HeadingFormat::parse(template).unwrap());
+    }
+    if let (Some(format), LineEnding::Crlf) = (&mut options.heading_format, options.line_ending) {
+        format.crlf();
+    }
+    let value_key = options.comparator.logfmt_key.is_some()  ||  options.comparator.json_key.is_some();
+    if value_key  &&  !options.raw_key  &&  options.mode_option.is_none() {
+        options.set_mode(Mode::Time, "by-time");
+    }
+    if let Some(since) = options.time_range[0].take() {
+        options.since = Some(parse_time("since", &since, &options));
+    }
+    if let Some(until) = options.time_range[1].take() {
+        options.until = Some(parse_time("until", &until, &options));
+    }
+    if options.glob {
+        options.inputs = expand_patterns(options.inputs, &options.filters);
+    }
+    options
+}
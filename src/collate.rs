@@ -0,0 +1,62 @@
+/* logmerge - A program to merge files like tail -f
+ * Copyright (C) 2021 Torbjørn Birch Moltu
+ *
+ * licenced under the terms of the GNU Lesser General Public License
+ * as published by the Free Software Foundation,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Locale-aware comparison for --locale, using the C library.
+//!
+//! Lines are transformed with strxfrm() once when they are read,
+//! so that the heap can compare the results as bytes.
+//! This and the pager module are the only ones that use unsafe code.
+
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+
+#[cfg(any(target_os="linux", target_os="android", target_os="emscripten"))]
+const LC_COLLATE: c_int = 3;
+#[cfg(not(any(target_os="linux", target_os="android", target_os="emscripten")))]
+const LC_COLLATE: c_int = 1;
+
+extern "C" {
+    fn setlocale(category: c_int,  locale: *const c_char) -> *mut c_char;
+    fn strxfrm(dest: *mut c_char,  src: *const c_char,  n: usize) -> usize;
+}
+
+/// Set the locale used for collation, where an empty name means from the environment.
+/// Returns the name of the locale, or None if it isn't available.
+pub fn set_locale(name: &[u8]) -> Option<String> {
+    let name = CString::new(name).ok()?;
+    // SAFETY: name is NUL-terminated, and no other threads use the locale
+    let set = unsafe { setlocale(LC_COLLATE, name.as_ptr()) };
+    if set.is_null() {
+        return None;
+    }
+    // SAFETY: setlocale() returns a NUL-terminated string when it succeeds
+    Some(unsafe { CStr::from_ptr(set) }.to_string_lossy().into_owned())
+}
+
+/// Transform text so that comparing the results as bytes gives the order of the locale.
+/// Returns None for text that isn't valid UTF-8 or which contains NUL.
+pub fn transform(text: &[u8]) -> Option<Vec<u8>> {
+    std::str::from_utf8(text).ok()?;
+    let text = CString::new(text).ok()?;
+    // SAFETY: with a length of zero nothing is written
+    let length = unsafe { strxfrm(ptr::null_mut(), text.as_ptr(), 0) };
+    let mut transformed = vec![0u8; length+1];
+    // SAFETY: the buffer has room for the result and a NUL
+    unsafe { strxfrm(transformed.as_mut_ptr() as *mut c_char, text.as_ptr(), transformed.len()) };
+    transformed.truncate(length);
+    Some(transformed)
+}
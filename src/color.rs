@@ -0,0 +1,142 @@
+/* logmerge - A program to merge files like tail -f
+ * Copyright (C) 2021 Torbjørn Birch Moltu
+ *
+ * licenced under the terms of the GNU Lesser General Public License
+ * as published by the Free Software Foundation,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+
+//! Colors for --color, as SGR escape sequences.
+
+use std::env::var_os;
+
+/// What ends a colored header or line.
+pub const RESET: &[u8] = b"\x1b[0m";
+
+/// The basic colors that are readable on both dark and light backgrounds.
+const BASIC: [(&str, u8); 6] = [("red", 31), ("green", 32), ("yellow", 33), ("blue", 34), ("magenta", 35), ("cyan", 36)];
+
+/// How many colors the terminal supports.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Depth {
+    Basic,
+    /// the 256 colors of xterm
+    Indexed,
+    TrueColor,
+}
+impl Depth {
+    /// Find out from $COLORTERM and $TERM.
+    pub fn detect() -> Self {
+        let color_term = var_os("COLORTERM").unwrap_or_default();
+        let term = var_os("TERM").unwrap_or_default();
+        if color_term == "truecolor"  ||  color_term == "24bit" {
+            Depth::TrueColor
+        } else if term.to_string_lossy().contains("256color") {
+            Depth::Indexed
+        } else {
+            Depth::Basic
+        }
+    }
+}
+
+/// Whether $NO_COLOR is set and not empty, which means colors should only be used when asked for.
+/// (see <https://no-color.org/>)
+pub fn no_color() -> bool {
+    var_os("NO_COLOR").is_some_and(|value| !value.is_empty() )
+}
+
+/// Parse a color given to --palette: one of the basic color names,
+/// a number for one of the 256 colors, or #RRGGBB.
+pub fn parse(color: &[u8]) -> Result<Vec<u8>, String> {
+    if let Some(&(_, code)) = BASIC.iter().find(|(name, _)| name.as_bytes() == color ) {
+        return Ok(format!("\x1b[{}m", code).into_bytes());
+    }
+    let text = String::from_utf8_lossy(color);
+    if let Ok(index) = text.parse::<u8>() {
+        return Ok(indexed(index));
+    }
+    match text.strip_prefix('#') {
+        Some(hex) if hex.len() == 6  &&  hex.bytes().all(|b| b.is_ascii_hexdigit() ) => {
+            let rgb = u32::from_str_radix(hex, 16).unwrap();
+            Ok(rgb_color((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+        }
+        _ => Err("expected red, green, yellow, blue, magenta, cyan, 0-255 or #RRGGBB".to_string()),
+    }
+}
+
+fn indexed(index: u8) -> Vec<u8> {
+    format!("\x1b[38;5;{}m", index).into_bytes()
+}
+
+fn rgb_color(red: u8,  green: u8,  blue: u8) -> Vec<u8> {
+    format!("\x1b[38;2;{};{};{}m", red, green, blue).into_bytes()
+}
+
+/// FNV-1a, which doesn't change between versions like the hasher in std can.
+fn hash(name: &[u8]) -> u64 {
+    name.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3) )
+}
+
+/// A color from the hash of a name, which is readable on both dark and light backgrounds.
+fn hashed(hash: u64,  depth: Depth) -> Vec<u8> {
+    match depth {
+        Depth::Basic => unreachable!("basic colors are assigned in order"),
+        Depth::Indexed => {
+            // the 6x6x6 cube without grays and the darkest and lightest colors
+            let readable = (0..216u8).filter(|&i| {
+                let (r, g, b) = (i / 36, i / 6 % 6, i % 6);
+                let (min, max) = (r.min(g).min(b), r.max(g).max(b));
+                max >= 3  &&  min <= 2  &&  min != max
+            }).collect::<Vec<_>>();
+            indexed(16 + readable[(hash % readable.len() as u64) as usize])
+        }
+        Depth::TrueColor => {
+            // any hue, with the same saturation and brightness
+            let hue = (hash % 360) as f64 / 60.0;
+            let (max, min) = (230.0, 80.0);
+            let ramp = (1.0 - (hue % 2.0 - 1.0).abs()) * (max - min) + min;
+            let (red, green, blue) = match hue as u32 {
+                0 => (max, ramp, min),
+                1 => (ramp, max, min),
+                2 => (min, max, ramp),
+                3 => (min, ramp, max),
+                4 => (ramp, min, max),
+                _ => (max, min, ramp),
+            };
+            rgb_color(red as u8, green as u8, blue as u8)
+        }
+    }
+}
+
+/// Choose the color of each file from what it's shown as in headers.
+/// Files named in --palette (`pinned`) get that color.
+/// With only the basic colors, files get them in order so that the first six differ,
+/// otherwise the color comes from the name, so that a file gets the same color every time.
+pub fn assign(names: &[&[u8]],  depth: Depth,  pinned: &[(Vec<u8>, Vec<u8>)]) -> Vec<Vec<u8>> {
+    let mut colors = Vec::<Vec<u8>>::with_capacity(names.len());
+    for (i, &name) in names.iter().enumerate() {
+        if let Some((_, color)) = pinned.iter().find(|(label, _)| &label[..] == name ) {
+            colors.push(color.clone());
+            continue;
+        } else if depth == Depth::Basic {
+            colors.push(format!("\x1b[{}m", BASIC[i % BASIC.len()].1).into_bytes());
+            continue;
+        }
+        // try some other colors if another file already has it
+        let hash = hash(name);
+        let color = (0..16).map(|attempt| hashed(hash.wrapping_add(attempt * 37), depth) )
+                .find(|color| !colors.contains(color) )
+                .unwrap_or_else(|| hashed(hash, depth) );
+        colors.push(color);
+    }
+    colors
+}
@@ -0,0 +1,1141 @@
+/* logmerge - A program to merge files like tail -f
+ * Copyright (C) 2021 Torbjørn Birch Moltu
+ *
+ * licenced under the terms of the GNU Lesser General Public License
+ * as published by the Free Software Foundation,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Deciding which line should be printed first.
+//!
+//! Modes that need to parse the line do so once when the line is read,
+//! and store the result in a `Key` so that heap comparisons stay cheap.
+
+use crate::collate;
+use crate::regex::Regex;
+use crate::timestamp::{self, TimeFormat, Time};
+use crate::json;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::ops::Range;
+
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum Mode {
+    #[default]
+    Bytes,
+    /// -n
+    Numeric,
+    /// -g
+    General,
+    /// -h
+    Human,
+    /// -V
+    Version,
+    /// -M
+    Month,
+    /// --natural-sort
+    Natural,
+    /// --by-time
+    Time,
+}
+
+/// -f and --ignore-case=HOW
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum Folding {
+    #[default]
+    Exact,
+    Ascii,
+    /// simple case folding
+    Unicode,
+    /// like Unicode, but with Turkish and Azerbaijani I and i
+    Turkic,
+}
+
+/// --normalize
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum Normalization {
+    #[default]
+    None,
+    Nfc,
+    Nfd,
+}
+
+/// How to compare a key.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct KeyOptions {
+    pub mode: Mode,
+    /// the files are sorted in descending order
+    pub reverse: bool,
+    /// compare letters case-insensitively
+    pub fold_case: Folding,
+    pub normalize: Normalization,
+    /// skip spaces and tabs at the start of lines
+    pub ignore_leading_blanks: bool,
+    /// only compare blanks, letters and digits
+    pub dictionary_order: bool,
+    /// skip ASCII control characters when comparing
+    pub ignore_nonprinting: bool,
+    /// skip terminal escape sequences such as colors when comparing
+    pub ignore_ansi: bool,
+}
+
+#[derive(Default)]
+pub struct Comparator {
+    /// options for keys without modifiers
+    pub global: KeyOptions,
+    /// -t, or None if fields are separated by runs of blanks
+    pub separator: Option<u8>,
+    /// -k: the parts of lines to compare, in order
+    pub keys: Vec<KeySpec>,
+    /// --skip-chars: bytes at the start of lines that are never compared
+    pub skip_chars: usize,
+    /// --compare-bytes: how much of lines after skip_chars keys are found in
+    pub compare_bytes: Option<usize>,
+    /// -s: don't fall back to comparing entire lines when the keys are equal
+    pub stable: bool,
+    /// --locale: compare text keys in the order of the locale
+    pub collate: bool,
+    /// --key-regex: only compare what the regex matches
+    pub key_regex: Option<Regex>,
+    /// --logfmt-key: only compare the value of this logfmt field
+    pub logfmt_key: Option<Vec<u8>>,
+    /// --json-key: only compare this value in lines of JSON
+    pub json_key: Option<json::Pointer>,
+    /// --skip-timestamp: don't compare a timestamp at the start of lines
+    pub skip_timestamp: bool,
+    /// --time-format: how --by-time parses timestamps in files without --file-time-format
+    pub time_format: TimeFormat,
+    /// --assume-year: the year of syslog timestamps at the start of files
+    pub assume_year: Option<i64>,
+    /// --strict-time: lines without a timestamp are an error
+    pub strict_time: bool,
+    /// --record-separator or -z: what lines end with instead of newline
+    pub record_separator: Option<Vec<u8>>,
+    /// --record-size: lines don't end with anything
+    pub binary: bool,
+    /// --csv: lines can contain terminators inside quotes, and keys are found in all of it
+    pub csv: bool,
+    /// --csv-key: only compare this field, counting from 1
+    pub csv_key: Option<usize>,
+}
+
+/// What was parsed from the key, as offsets into the key.
+#[derive(Clone, Debug)]
+pub enum Parsed {
+    /// compare the key as text
+    Text,
+    /// the key after --ignore-case=unicode or --normalize
+    Prepared(Vec<u8>),
+    /// A decimal number, with the 1-based index of an SI suffix for -h, or 0.
+    /// `integer` excludes leading zeroes and `fraction` excludes trailing zeroes,
+    /// so that numbers which are equal have identical digits.
+    Decimal { negative: bool,  integer: Range<usize>,  fraction: Range<usize>,  unit: u8 },
+    /// A floating-point number, or None if the line doesn't start with one.
+    Float(Option<f64>),
+    /// the first word of the line
+    Word(Range<usize>),
+    /// 1-12, or 0 if the line doesn't start with a month, and where the rest of the line starts
+    Month(u8, usize),
+    /// the key transformed for --locale, or None if it couldn't be
+    Collated(Option<Vec<u8>>),
+    /// None if the line and the lines before it have no timestamp
+    Time(Option<Time>),
+}
+
+/// A part of a line that is compared, and what has been parsed from it.
+#[derive(Clone, Debug)]
+struct Extracted {
+    range: Range<usize>,
+    parsed: Parsed,
+}
+
+/// The keys of a line.
+/// The first is stored separately to not allocate when there is only one.
+#[derive(Clone, Debug)]
+pub struct Key {
+    first: Extracted,
+    rest: Vec<Extracted>,
+    /// --key-regex didn't match the line
+    unmatched: bool,
+}
+impl Key {
+    fn parts(&self) -> impl Iterator<Item=&Extracted> {
+        std::iter::once(&self.first).chain(&self.rest)
+    }
+
+    pub fn unmatched(&self) -> bool {
+        self.unmatched
+    }
+
+    /// The time of the first time key, if any.
+    pub fn time(&self) -> Option<i64> {
+        let time = self.parts().find_map(|part| match part.parsed {
+            Parsed::Time(time) => Some(time),
+            _ => None,
+        });
+        Some(time??.nanoseconds)
+    }
+
+    /// For --rewrite-time: where the timestamp of the first time key is in the line, and its time.
+    pub fn timestamp(&self) -> Option<(Range<usize>, i64)> {
+        let (range, time) = self.parts().find_map(|part| match part.parsed {
+            Parsed::Time(time) => Some((&part.range, time)),
+            _ => None,
+        })?;
+        let time = time.filter(|time| time.ends != 0 )?;
+        Some((range.start+time.starts..range.start+time.ends, time.nanoseconds))
+    }
+
+    /// Whether a time key didn't find a timestamp.
+    pub fn missing_time(&self) -> bool {
+        self.parts().any(|part| matches!(part.parsed, Parsed::Time(None)) )
+    }
+
+    /// Change the parsed times.
+    pub fn map_times(&mut self,  mut f: impl FnMut(&mut Time)) {
+        for part in std::iter::once(&mut self.first).chain(&mut self.rest) {
+            if let Parsed::Time(Some(time)) = &mut part.parsed {
+                f(time);
+            }
+        }
+    }
+
+    /// For lines without a timestamp, use the time of the previous line,
+    /// so that continuation lines are merged together with the line they belong to.
+    pub fn inherit_time(&mut self,  previous: &Key) {
+        let parts = std::iter::once(&mut self.first).chain(&mut self.rest);
+        for (part, previous) in parts.zip(previous.parts()) {
+            if let (Parsed::Time(time @ None), Parsed::Time(previous)) = (&mut part.parsed, &previous.parsed) {
+                *time = previous.map(|previous| Time { starts: 0,  ends: 0,  ..previous });
+            }
+        }
+    }
+}
+
+/// A position in a line given to -k, with 0-based field number.
+#[derive(Clone, Copy, Debug)]
+pub struct Position {
+    pub field: usize,
+    /// 0-based for the start, 1-based with 0 meaning the end of the field for the end
+    pub offset: usize,
+}
+
+/// START[,END] given to -k
+#[derive(Clone, Copy, Debug)]
+pub struct Field {
+    pub start: Position,
+    /// None means the end of the line
+    pub end: Option<Position>,
+}
+
+/// A -k option
+#[derive(Clone, Copy, Debug)]
+pub struct KeySpec {
+    /// None means the entire line
+    pub field: Option<Field>,
+    /// None if there were no modifiers, and the global options should be used
+    pub options: Option<KeyOptions>,
+}
+
+/// The key used when there are no -k options.
+static ENTIRE_LINE: KeySpec = KeySpec { field: None,  options: None };
+
+impl KeySpec {
+    /// Parse a key definition like `3`, `2,2n` or `3.2,3.5br`.
+    pub fn parse(definition: &[u8]) -> Result<Self, String> {
+        fn number(text: &[u8],  what: &str) -> Result<usize, String> {
+            if text.is_empty()  ||  !text.iter().all(u8::is_ascii_digit) {
+                return Err(format!("invalid {} '{}'", what, String::from_utf8_lossy(text)));
+            }
+            std::str::from_utf8(text).unwrap().parse()
+                .map_err(|_| format!("{} is too big", what) )
+        }
+        fn position(text: &[u8],  modifiers: &mut Vec<u8>) -> Result<(usize, Option<usize>), String> {
+            let letters = text.iter().position(u8::is_ascii_alphabetic).unwrap_or(text.len());
+            modifiers.extend_from_slice(&text[letters..]);
+            let text = &text[..letters];
+            let (field, offset) = match text.iter().position(|&b| b == b'.' ) {
+                Some(dot) => (&text[..dot], Some(&text[dot+1..])),
+                None => (text, None),
+            };
+            let field = number(field, "field number")?;
+            if field == 0 {
+                return Err("field numbers start at 1".to_string());
+            }
+            Ok((field-1, offset.map(|offset| number(offset, "character offset") ).transpose()?))
+        }
+        let (start, end) = match definition.iter().position(|&b| b == b',' ) {
+            Some(comma) => (&definition[..comma], Some(&definition[comma+1..])),
+            None => (definition, None),
+        };
+        let mut modifiers = Vec::new();
+        let (field, offset) = position(start, &mut modifiers)?;
+        let start = match offset {
+            Some(0) => return Err("character offsets start at 1".to_string()),
+            Some(offset) => Position { field,  offset: offset-1 },
+            None => Position { field,  offset: 0 },
+        };
+        let end = match end {
+            Some(end) => {
+                let (field, offset) = position(end, &mut modifiers)?;
+                Some(Position { field,  offset: offset.unwrap_or(0) })
+            }
+            None => None,
+        };
+        let options = match modifiers.is_empty() {
+            true => None,
+            false => Some(KeyOptions::from_modifiers(&modifiers)?),
+        };
+        Ok(KeySpec { field: Some(Field { start, end }),  options })
+    }
+}
+
+#[cfg(feature="normalize")]
+fn normalize(text: &str,  form: Normalization) -> String {
+    match form {
+        Normalization::Nfc => crate::normalize::nfc(text),
+        Normalization::Nfd | Normalization::None => crate::normalize::nfd(text),
+    }
+}
+
+/// --normalize is rejected when the tables aren't compiled in.
+#[cfg(not(feature="normalize"))]
+fn normalize(text: &str,  _: Normalization) -> String {
+    text.to_string()
+}
+
+/// Simple case folding, approximated by lowercasing with the exceptions where they differ.
+/// Characters that lowercase to several characters are left alone.
+fn fold_char(c: char,  turkic: bool) -> char {
+    match c {
+        'I' if turkic => 'ı',
+        'İ' if turkic => 'i',
+        'ς' => 'σ',
+        'µ' => 'μ',
+        'ϐ' => 'β',
+        'ϑ' => 'θ',
+        'ϕ' => 'φ',
+        'ϖ' => 'π',
+        'ϰ' => 'κ',
+        'ϱ' => 'ρ',
+        'ϵ' => 'ε',
+        'ẛ' => 'ṡ',
+        '\u{1fbe}' => 'ι',
+        _ => {
+            let mut lowercase = c.to_lowercase();
+            match (lowercase.next(), lowercase.next()) {
+                (Some(lower), None) => lower,
+                _ => c,
+            }
+        }
+    }
+}
+
+/// The key, or if it has been prepared, the prepared key.
+fn prepared_or<'a>(key: &'a [u8],  parsed: &'a Parsed) -> &'a [u8] {
+    match parsed {
+        Parsed::Prepared(prepared) => prepared,
+        _ => key,
+    }
+}
+
+fn is_blank(b: u8) -> bool {
+    b == b' '  ||  b == b'\t'
+}
+
+fn skip_blanks(line: &[u8],  mut i: usize) -> usize {
+    while i < line.len()  &&  is_blank(line[i]) {
+        i += 1;
+    }
+    i
+}
+
+fn skip_digits(line: &[u8],  mut i: usize) -> usize {
+    while i < line.len()  &&  line[i].is_ascii_digit() {
+        i += 1;
+    }
+    i
+}
+
+const UNITS: &[u8] = b"KMGTPEZY";
+
+/// Parse an optionally signed decimal number after any leading blanks,
+/// followed by a suffix from `UNITS` if `units` is true.
+/// Lines without a number are treated as zero.
+fn parse_decimal(line: &[u8],  units: bool) -> Parsed {
+    let mut i = skip_blanks(line, 0);
+    let negative = line.get(i) == Some(&b'-');
+    if negative  ||  line.get(i) == Some(&b'+') {
+        i += 1;
+    }
+    while line.get(i) == Some(&b'0') {
+        i += 1;
+    }
+    let integer = i..skip_digits(line, i);
+    let mut fraction = integer.end..integer.end;
+    if line.get(integer.end) == Some(&b'.') {
+        let start = integer.end + 1;
+        let mut end = skip_digits(line, start);
+        while end > start  &&  line[end-1] == b'0' {
+            end -= 1;
+        }
+        fraction = start..end;
+    }
+    let number_ends = skip_digits(line, fraction.start.max(integer.end));
+    let unit = match line.get(number_ends) {
+        Some(suffix) if units  &&  number_ends > i => {
+            let suffix = suffix.to_ascii_uppercase();
+            UNITS.iter().position(|&unit| unit == suffix ).map_or(0, |index| index as u8 + 1 )
+        }
+        _ => 0,
+    };
+    let zero = integer.is_empty()  &&  fraction.is_empty();
+    Parsed::Decimal { negative: negative && !zero,  integer,  fraction,  unit }
+}
+
+/// Compare two decimal numbers, using the length of the integer part
+/// so that numbers with any number of digits can be compared.
+/// Like GNU sort, a larger suffix wins before the numbers are compared.
+fn compare_decimal(a: &[u8],  a_key: &Parsed,  b: &[u8],  b_key: &Parsed) -> Ordering {
+    let (a_negative, a_integer, a_fraction, a_unit, b_negative, b_integer, b_fraction, b_unit) = match (a_key, b_key) {
+        (Parsed::Decimal { negative: an, integer: ai, fraction: af, unit: au },
+         Parsed::Decimal { negative: bn, integer: bi, fraction: bf, unit: bu }) => (*an, ai, af, *au, *bn, bi, bf, *bu),
+        _ => unreachable!("both keys must be decimal"),
+    };
+    let signed_unit = |negative, unit| if negative {-(unit as i8)} else {unit as i8};
+    let units = signed_unit(a_negative, a_unit).cmp(&signed_unit(b_negative, b_unit));
+    if units != Ordering::Equal {
+        return units;
+    }
+    let magnitude = a_integer.len().cmp(&b_integer.len())
+        .then_with(|| a[a_integer.clone()].cmp(&b[b_integer.clone()]) )
+        .then_with(|| a[a_fraction.clone()].cmp(&b[b_fraction.clone()]) );
+    match (a_negative, b_negative) {
+        (false, false) => magnitude,
+        (true, true) => magnitude.reverse(),
+        (true, false) => Ordering::Less,
+        (false, true) => Ordering::Greater,
+    }
+}
+
+/// Parse the longest prefix after blanks that is a floating-point number,
+/// including `inf`, `infinity`, `nan` and exponents.
+fn parse_float(line: &[u8]) -> Option<f64> {
+    let start = skip_blanks(line, 0);
+    let mut i = start;
+    if matches!(line.get(i), Some(b'-') | Some(b'+')) {
+        i += 1;
+    }
+    let word_ends = line[i..].iter().position(|b| !b.is_ascii_alphabetic() ).map_or(line.len(), |len| i+len );
+    let word = line[i..word_ends].to_ascii_lowercase();
+    let end = if word.starts_with(b"infinity") {
+        i + 8
+    } else if word.starts_with(b"inf")  ||  word.starts_with(b"nan") {
+        i + 3
+    } else {
+        let integer_ends = skip_digits(line, i);
+        let mut end = integer_ends;
+        if line.get(end) == Some(&b'.') {
+            end = skip_digits(line, end+1);
+        }
+        if end == i+1  &&  integer_ends == i {
+            // only a dot
+            return None;
+        }
+        if matches!(line.get(end), Some(b'e') | Some(b'E')) {
+            let mut exponent = end + 1;
+            if matches!(line.get(exponent), Some(b'-') | Some(b'+')) {
+                exponent += 1;
+            }
+            let exponent_ends = skip_digits(line, exponent);
+            if exponent_ends > exponent {
+                end = exponent_ends;
+            }
+        }
+        end
+    };
+    // only ASCII has been accepted
+    std::str::from_utf8(&line[start..end]).ok()?.parse().ok()
+}
+
+/// Lines without a number first, then NaN, then the numbers in order.
+fn compare_float(a: Option<f64>,  b: Option<f64>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => match (a.is_nan(), b.is_nan()) {
+            (false, false) => a.partial_cmp(&b).unwrap(),
+            (a_nan, b_nan) => b_nan.cmp(&a_nan),
+        },
+        (a, b) => a.is_some().cmp(&b.is_some()),
+    }
+}
+
+fn first_word(line: &[u8]) -> Range<usize> {
+    let start = skip_blanks(line, 0);
+    let end = line[start..].iter()
+        .position(|b| b.is_ascii_whitespace() )
+        .map_or(line.len(), |len| start+len );
+    start..end
+}
+
+/// Find the end of an escape sequence starting at `start`,
+/// or the end of the text if the sequence is cut off.
+fn escape_sequence_ends(text: &[u8],  start: usize) -> usize {
+    match text.get(start+1) {
+        // CSI: parameters and intermediate bytes followed by a final byte
+        Some(b'[') => {
+            let mut i = start + 2;
+            while i < text.len()  &&  (0x20..=0x3f).contains(&text[i]) {
+                i += 1;
+            }
+            match text.get(i) {
+                Some(0x40..=0x7e) => i+1,
+                _ => i,
+            }
+        }
+        // OSC: terminated by BEL or ESC \
+        Some(b']') => {
+            let mut i = start + 2;
+            while i < text.len() {
+                match text[i] {
+                    0x07 => return i+1,
+                    0x1b if text.get(i+1) == Some(&b'\\') => return i+2,
+                    _ => i += 1,
+                }
+            }
+            i
+        }
+        Some(_) => start+2,
+        None => start+1,
+    }
+}
+
+/// Compare numbers in text numerically, and the text around them as bytes.
+fn compare_natural(a: &[u8],  b: &[u8]) -> Ordering {
+    let (mut i, mut j) = (0, 0);
+    while i < a.len()  &&  j < b.len() {
+        let ordering = if a[i].is_ascii_digit()  &&  b[j].is_ascii_digit() {
+            while a.get(i) == Some(&b'0') {
+                i += 1;
+            }
+            while b.get(j) == Some(&b'0') {
+                j += 1;
+            }
+            let (a_end, b_end) = (skip_digits(a, i), skip_digits(b, j));
+            let ordering = (a_end-i).cmp(&(b_end-j)).then_with(|| a[i..a_end].cmp(&b[j..b_end]) );
+            i = a_end;
+            j = b_end;
+            ordering
+        } else {
+            let a_end = i + a[i..].iter().position(u8::is_ascii_digit).unwrap_or(a.len()-i).max(1);
+            let b_end = j + b[j..].iter().position(u8::is_ascii_digit).unwrap_or(b.len()-j).max(1);
+            let ordering = a[i..a_end].cmp(&b[j..b_end]);
+            i = a_end;
+            j = b_end;
+            ordering
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    (i < a.len()).cmp(&(j < b.len()))
+}
+
+/// Order of non-digit characters in versions:
+/// `~` sorts before everything, even the end of the part, so that `1.0~rc1` < `1.0`.
+/// Letters sort before other characters.
+fn version_order(b: Option<&u8>) -> i32 {
+    match b {
+        Some(b'~') => -1,
+        None => 0,
+        Some(b) if b.is_ascii_digit() => 0,
+        Some(b) if b.is_ascii_alphabetic() => *b as i32,
+        Some(b) => *b as i32 + 256,
+    }
+}
+
+/// Compare versions like Debian's dpkg does, by alternating between non-digit parts
+/// which are compared character by character, and digit parts which are compared numerically.
+fn compare_version(a: &[u8],  b: &[u8]) -> Ordering {
+    let (mut i, mut j) = (0, 0);
+    while i < a.len()  ||  j < b.len() {
+        while (i < a.len() && !a[i].is_ascii_digit())  ||  (j < b.len() && !b[j].is_ascii_digit()) {
+            let ordering = version_order(a.get(i)).cmp(&version_order(b.get(j)));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+            // the characters are equal, so neither is at the end
+            i += 1;
+            j += 1;
+        }
+        while a.get(i) == Some(&b'0') {
+            i += 1;
+        }
+        while b.get(j) == Some(&b'0') {
+            j += 1;
+        }
+        let (a_end, b_end) = (skip_digits(a, i), skip_digits(b, j));
+        let ordering = (a_end-i).cmp(&(b_end-j)).then_with(|| a[i..a_end].cmp(&b[j..b_end]) );
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+        i = a_end;
+        j = b_end;
+    }
+    Ordering::Equal
+}
+
+const MONTHS: [&[u8; 3]; 12] = [
+    b"JAN", b"FEB", b"MAR", b"APR", b"MAY", b"JUN",
+    b"JUL", b"AUG", b"SEP", b"OCT", b"NOV", b"DEC",
+];
+
+/// Recognize English month abbreviations after blanks, ignoring case.
+fn parse_month(line: &[u8]) -> Parsed {
+    let start = skip_blanks(line, 0);
+    if let Some(abbreviation) = line.get(start..start+3) {
+        let upper = abbreviation.to_ascii_uppercase();
+        if let Some(index) = MONTHS.iter().position(|&month| month[..] == upper[..] ) {
+            return Parsed::Month(index as u8 + 1, start+3);
+        }
+    }
+    Parsed::Month(0, start)
+}
+
+impl KeyOptions {
+    /// Options for a key with the single-letter modifiers of sort -k.
+    fn from_modifiers(modifiers: &[u8]) -> Result<Self, String> {
+        let mut options = KeyOptions::default();
+        let mut mode_modifier = None;
+        for &modifier in modifiers {
+            let mode = match modifier {
+                b'b' => {options.ignore_leading_blanks = true;  continue}
+                b'd' => {options.dictionary_order = true;  continue}
+                b'f' => {options.fold_case = Folding::Ascii;  continue}
+                b'i' => {options.ignore_nonprinting = true;  continue}
+                b'r' => {options.reverse = true;  continue}
+                b'n' => Mode::Numeric,
+                b'g' => Mode::General,
+                b'h' => Mode::Human,
+                b'V' => Mode::Version,
+                b'M' => Mode::Month,
+                _ => return Err(format!("unsupported modifier '{}'", modifier as char)),
+            };
+            match mode_modifier {
+                Some(previous) if previous != modifier => return Err(format!(
+                        "modifiers '{}' and '{}' are incompatible", previous as char, modifier as char
+                )),
+                _ => {}
+            }
+            mode_modifier = Some(modifier);
+            options.mode = mode;
+        }
+        if let Some(mode_modifier) = mode_modifier {
+            if let Some(&text_modifier) = modifiers.iter().find(|&&m| m == b'd' || m == b'i' ) {
+                return Err(format!(
+                        "modifiers '{}' and '{}' are incompatible", text_modifier as char, mode_modifier as char
+                ));
+            }
+        }
+        Ok(options)
+    }
+
+    /// Compare keys as text, with the options that affect that.
+    fn compare_text(&self,  a: &[u8],  b: &[u8]) -> Ordering {
+        if !self.dictionary_order  &&  !self.ignore_nonprinting  &&  !self.ignore_ansi {
+            return match self.fold_case {
+                Folding::Exact => a.cmp(b),
+                _ => a.iter().map(u8::to_ascii_lowercase).cmp(b.iter().map(u8::to_ascii_lowercase)),
+            };
+        }
+        let (mut i, mut j) = (0, 0);
+        loop {
+            i = self.skip_ignored(a, i);
+            j = self.skip_ignored(b, j);
+            match (a.get(i), b.get(j)) {
+                (Some(&a_byte), Some(&b_byte)) => {
+                    let ordering = self.fold(a_byte).cmp(&self.fold(b_byte));
+                    if ordering != Ordering::Equal {
+                        return ordering;
+                    }
+                    i += 1;
+                    j += 1;
+                }
+                (a_byte, b_byte) => return a_byte.is_some().cmp(&b_byte.is_some()),
+            }
+        }
+    }
+
+    /// Whether a byte should be skipped when comparing text.
+    /// Bytes of non-ASCII characters are never skipped.
+    fn ignored(&self,  b: u8) -> bool {
+        (self.dictionary_order  &&  !(b.is_ascii_alphanumeric() || b == b' ' || b == b'\t' || b >= 0x80))
+        ||  (self.ignore_nonprinting  &&  (b < b' ' || b == 0x7f))
+    }
+
+    /// Skip ignored bytes and escape sequences starting at `i`.
+    fn skip_ignored(&self,  text: &[u8],  mut i: usize) -> usize {
+        while i < text.len() {
+            if self.ignore_ansi  &&  text[i] == 0x1b {
+                i = escape_sequence_ends(text, i);
+            } else if self.ignored(text[i]) {
+                i += 1;
+            } else {
+                break;
+            }
+        }
+        i
+    }
+
+    /// The bytes of a key that are compared.
+    fn compared_bytes(&self,  key: &[u8]) -> Vec<u8> {
+        let mut compared = Vec::with_capacity(key.len());
+        let mut i = self.skip_ignored(key, 0);
+        while i < key.len() {
+            compared.push(self.fold(key[i]));
+            i = self.skip_ignored(key, i+1);
+        }
+        compared
+    }
+
+    fn fold(&self,  b: u8) -> u8 {
+        if self.fold_case != Folding::Exact {b.to_ascii_lowercase()} else {b}
+    }
+
+    /// Normalize and fold the case of non-ASCII text, which is too slow to do for every comparison.
+    /// Returns None if the key can be compared one byte at a time.
+    fn prepare(&self,  key: &[u8]) -> Option<Vec<u8>> {
+        let turkic = self.fold_case == Folding::Turkic;
+        let fold = turkic  ||  self.fold_case == Folding::Unicode;
+        if key.is_ascii()  &&  !(turkic && key.contains(&b'I')) {
+            return None;
+        }
+        let mut text = match (std::str::from_utf8(key), fold) {
+            (Ok(text), _) => Cow::Borrowed(text),
+            (Err(_), true) => String::from_utf8_lossy(key),
+            // invalid UTF-8 is not normalized
+            (Err(_), false) => return None,
+        };
+        if self.normalize != Normalization::None {
+            let normalized = normalize(&text, self.normalize);
+            if normalized != text {
+                text = Cow::Owned(normalized);
+            }
+        }
+        if fold {
+            text = Cow::Owned(text.chars().map(|c| fold_char(c, turkic) ).collect());
+        }
+        match text {
+            Cow::Owned(text) => Some(text.into_bytes()),
+            Cow::Borrowed(_) => None,
+        }
+    }
+
+    fn parse(&self,  key: &[u8]) -> Parsed {
+        match self.mode {
+            Mode::Bytes => match self.prepare(key) {
+                Some(prepared) => Parsed::Prepared(prepared),
+                None => Parsed::Text,
+            },
+            Mode::Numeric => parse_decimal(key, false),
+            Mode::Human => parse_decimal(key, true),
+            Mode::General => Parsed::Float(parse_float(key)),
+            Mode::Version => Parsed::Word(first_word(key)),
+            Mode::Month => parse_month(key),
+            Mode::Natural => Parsed::Text,
+            Mode::Time => unreachable!("times are parsed in Comparator::key()"),
+        }
+    }
+
+    fn compare_keys(&self,  a: &[u8],  a_parsed: &Parsed,  b: &[u8],  b_parsed: &Parsed) -> Ordering {
+        match self.mode {
+            Mode::Bytes => match (a_parsed, b_parsed) {
+                (Parsed::Collated(Some(a_collated)), Parsed::Collated(Some(b_collated))) => {
+                    a_collated.cmp(b_collated)
+                }
+                // invalid UTF-8 is compared as bytes
+                _ => self.compare_text(prepared_or(a, a_parsed), prepared_or(b, b_parsed)),
+            },
+            Mode::Numeric | Mode::Human => compare_decimal(a, a_parsed, b, b_parsed),
+            Mode::Natural => compare_natural(a, b),
+            Mode::General => match (a_parsed, b_parsed) {
+                (&Parsed::Float(a_float), &Parsed::Float(b_float)) => compare_float(a_float, b_float),
+                _ => unreachable!("both keys must be floats"),
+            },
+            Mode::Version => match (a_parsed, b_parsed) {
+                (Parsed::Word(a_word), Parsed::Word(b_word)) => {
+                    compare_version(&a[a_word.clone()], &b[b_word.clone()])
+                }
+                _ => unreachable!("both keys must be words"),
+            },
+            // the year is not known, so December is always after January
+            Mode::Month => match (a_parsed, b_parsed) {
+                (&Parsed::Month(a_month, a_rest), &Parsed::Month(b_month, b_rest)) => {
+                    a_month.cmp(&b_month).then_with(|| a[a_rest..].cmp(&b[b_rest..]) )
+                }
+                _ => unreachable!("both keys must be months"),
+            },
+            // lines before the first timestamp come first
+            Mode::Time => match (a_parsed, b_parsed) {
+                (Parsed::Time(a_time), Parsed::Time(b_time)) => {
+                    a_time.map(|time| time.nanoseconds ).cmp(&b_time.map(|time| time.nanoseconds ))
+                }
+                _ => unreachable!("both keys must be times"),
+            },
+        }
+    }
+}
+
+impl Comparator {
+    /// Where the field after skipping `fields` fields starts.
+    fn field_starts(&self,  line: &[u8],  fields: usize) -> usize {
+        let mut i = 0;
+        for _ in 0..fields {
+            i = self.field_ends(line, i);
+            if self.separator.is_some()  &&  i < line.len() {
+                i += 1;
+            }
+        }
+        i
+    }
+
+    /// Where the field starting at `i` ends.
+    /// Without -t, fields include the blanks before them.
+    fn field_ends(&self,  line: &[u8],  mut i: usize) -> usize {
+        match self.separator {
+            Some(separator) => {
+                while i < line.len()  &&  line[i] != separator {
+                    i += 1;
+                }
+            }
+            None => {
+                i = skip_blanks(line, i);
+                while i < line.len()  &&  !is_blank(line[i]) {
+                    i += 1;
+                }
+            }
+        }
+        i
+    }
+
+    /// What lines end with.
+    pub fn terminator(&self) -> &[u8] {
+        match (self.binary, &self.record_separator) {
+            (true, _) => b"",
+            (false, Some(separator)) => separator,
+            (false, None) => b"\n",
+        }
+    }
+
+    /// Whether keys need the time of earlier lines.
+    pub fn by_time(&self) -> bool {
+        self.specs().iter().any(|spec| self.options(spec).mode == Mode::Time )
+    }
+
+    fn specs(&self) -> &[KeySpec] {
+        match self.keys.is_empty() {
+            true => std::slice::from_ref(&ENTIRE_LINE),
+            false => &self.keys,
+        }
+    }
+
+    /// The part of the line to compare, limited by --skip-chars and --compare-bytes.
+    fn key_range(&self,  line: &[u8],  field: Option<&Field>,  options: &KeyOptions) -> Range<usize> {
+        let start = line.len().min(self.skip_chars);
+        let end = match self.compare_bytes {
+            Some(bytes) => line.len().min(start.saturating_add(bytes)),
+            None => line.len(),
+        };
+        let range = self.field_range(&line[start..end], field, options);
+        range.start+start..range.end+start
+    }
+
+    /// The part of the line to compare, where the line is limited to what keys can be found in.
+    fn field_range(&self,  line: &[u8],  field: Option<&Field>,  options: &KeyOptions) -> Range<usize> {
+        let field = match field {
+            Some(field) => field,
+            None if options.ignore_leading_blanks => return skip_blanks(line, 0)..line.len(),
+            None => return 0..line.len(),
+        };
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let mut start = self.field_starts(line, field.start.field);
+        if options.ignore_leading_blanks {
+            start = skip_blanks(line, start);
+        }
+        start = line.len().min(start + field.start.offset);
+        let end = match field.end {
+            None => line.len(),
+            Some(Position { field, offset: 0 }) => {
+                let starts = self.field_starts(line, field);
+                self.field_ends(line, starts)
+            }
+            Some(Position { field, offset }) => {
+                let mut starts = self.field_starts(line, field);
+                if options.ignore_leading_blanks {
+                    starts = skip_blanks(line, starts);
+                }
+                line.len().min(starts + offset)
+            }
+        };
+        start..end.max(start)
+    }
+
+    fn options<'a>(&'a self,  spec: &'a KeySpec) -> &'a KeyOptions {
+        spec.options.as_ref().unwrap_or(&self.global)
+    }
+
+    /// The first line of a record from --join-continuation, which keys are found in,
+    /// without its ending.
+    fn first_line<'a>(&self,  record: &'a [u8]) -> &'a [u8] {
+        let terminator = self.terminator();
+        if terminator.is_empty() {
+            return record;
+        } else if self.csv {
+            return line_content(record, terminator);
+        }
+        let first = match record.windows(terminator.len()).position(|window| window == terminator ) {
+            Some(ends) => &record[..ends+terminator.len()],
+            None => record,
+        };
+        line_content(first, terminator)
+    }
+
+    /// Find and parse what is needed to compare the line.
+    /// Timestamps are parsed with `time_format`, because it can differ between files.
+    pub fn key(&self,  line: &[u8],  time_format: &TimeFormat) -> Key {
+        let line = self.first_line(line);
+        // --skip-timestamp ignores a timestamp at the start of the line
+        let skipped = match self.skip_timestamp {
+            true => timestamp::leading(line),
+            false => 0,
+        };
+        // keys are found in what --key-regex matched or in the --logfmt-key or --json-key value,
+        // and lines without it have an empty key
+        let rest = &line[skipped..];
+        let mut number = false;
+        let found = match (&self.key_regex, &self.logfmt_key, &self.json_key) {
+            (Some(regex), _, _) => Some(regex.key_range(rest)),
+            (None, Some(name), _) => Some(logfmt_value(rest, name)),
+            (None, None, Some(pointer)) => Some(pointer.find(rest).map(|(range, kind)| {
+                number = kind == json::Kind::Number;
+                range
+            })),
+            (None, None, None) => {
+                self.csv_key.map(|field| csv_field(rest, field, self.separator.unwrap_or(b',')) )
+            }
+        };
+        // JSON numbers are seconds since 1970, unless the time format says otherwise
+        let time_format = match time_format {
+            TimeFormat::Iso8601 if number => &TimeFormat::Epoch,
+            _ => time_format,
+        };
+        let (searched, unmatched) = match found {
+            Some(Some(range)) => (range.start+skipped..range.end+skipped, false),
+            Some(None) => (0..0, true),
+            None => (skipped..line.len(), false),
+        };
+        let mut parts = self.specs().iter().map(|spec| {
+            let options = self.options(spec);
+            let range = self.key_range(&line[searched.clone()], spec.field.as_ref(), options);
+            let range = range.start+searched.start..range.end+searched.start;
+            let parsed = match options.mode {
+                Mode::Bytes if self.collate => {
+                    let key = &line[range.clone()];
+                    let key = key.strip_suffix(b"\n").unwrap_or(key);
+                    let prepared = options.prepare(key);
+                    let text = options.compared_bytes(prepared.as_deref().unwrap_or(key));
+                    Parsed::Collated(collate::transform(&text))
+                }
+                Mode::Time => Parsed::Time(time_format.parse(&line[range.clone()])),
+                _ => options.parse(&line[range.clone()]),
+            };
+            Extracted { range, parsed }
+        });
+        let first = parts.next().unwrap();
+        Key { first,  rest: parts.collect(),  unmatched }
+    }
+
+    /// Compare the keys of two lines in order until one differs.
+    fn compare_keys(&self,  a: &[u8],  a_key: &Key,  b: &[u8],  b_key: &Key) -> Ordering {
+        for (spec, (a_part, b_part)) in self.specs().iter().zip(a_key.parts().zip(b_key.parts())) {
+            let options = self.options(spec);
+            let ordering = options.compare_keys(
+                    &a[a_part.range.clone()], &a_part.parsed,
+                    &b[b_part.range.clone()], &b_part.parsed,
+            );
+            if ordering != Ordering::Equal {
+                return if options.reverse {ordering.reverse()} else {ordering};
+            }
+        }
+        Ordering::Equal
+    }
+
+    /// Whether the lines have equal keys, even if the lines differ.
+    pub fn keys_equal(&self,  a: &[u8],  a_key: &Key,  b: &[u8],  b_key: &Key) -> bool {
+        self.compare_keys(a, a_key, b, b_key) == Ordering::Equal
+    }
+
+    /// Compare two lines, where Less means `a` should be printed first.
+    pub fn compare(&self,  a: &[u8],  a_key: &Key,  b: &[u8],  b_key: &Key) -> Ordering {
+        let ordering = self.compare_keys(a, a_key, b, b_key);
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+        if self.stable {
+            return Ordering::Equal;
+        }
+        // fall back to the exact bytes of the entire line so that the order is deterministic
+        let ordering = line_content(a, self.terminator()).cmp(line_content(b, self.terminator()));
+        if self.global.reverse {ordering.reverse()} else {ordering}
+    }
+
+    /// For --debug: underline the compared part of the line, once for each key,
+    /// and describe any number that was parsed from it.
+    pub fn describe(&self,  line: &[u8],  key: &Key,  out: &mut Vec<u8>) {
+        let line = self.first_line(line);
+        let whole_line = Extracted { range: 0..line.len(),  parsed: Parsed::Text };
+        let last_resort = if self.stable {None} else {Some(&whole_line)};
+        for part in key.parts().chain(last_resort) {
+            let range = part.range.start.min(line.len())..part.range.end.min(line.len());
+            underline(&line[..range.end], range.start, out);
+            let key = &line[range];
+            match &part.parsed {
+                _ if key.is_empty() => {}
+                Parsed::Decimal { negative,  integer,  fraction,  unit } => {
+                    out.extend_from_slice(b" number ");
+                    if *negative {
+                        out.push(b'-');
+                    }
+                    match integer.is_empty() {
+                        true => out.push(b'0'),
+                        false => out.extend_from_slice(&key[integer.clone()]),
+                    }
+                    if !fraction.is_empty() {
+                        out.push(b'.');
+                        out.extend_from_slice(&key[fraction.clone()]);
+                    }
+                    if *unit != 0 {
+                        out.push(UNITS[*unit as usize - 1]);
+                    }
+                }
+                Parsed::Float(Some(number)) => out.extend_from_slice(format!(" number {}", number).as_bytes()),
+                Parsed::Float(None) => out.extend_from_slice(b" not a number"),
+                Parsed::Month(0, _) => out.extend_from_slice(b" not a month"),
+                Parsed::Month(month, _) => out.extend_from_slice(format!(" month {}", month).as_bytes()),
+                Parsed::Time(Some(time)) => {
+                    out.extend_from_slice(format!(" time {}", timestamp::format(time.nanoseconds)).as_bytes());
+                }
+                Parsed::Time(None) => out.extend_from_slice(b" no time"),
+                _ => {}
+            }
+            out.push(b'\n');
+        }
+    }
+}
+
+/// Write spaces under `before[..start]` and underscores under the rest,
+/// or a ^ if the rest is empty.
+/// Tabs are expanded to multiples of eight columns, and UTF-8 continuation bytes take no space.
+fn underline(before: &[u8],  start: usize,  out: &mut Vec<u8>) {
+    let mut column = 0;
+    for (i, &b) in before.iter().enumerate() {
+        let width = match b {
+            b'\t' => 8 - column % 8,
+            0x80..=0xbf => 0,
+            _ => 1,
+        };
+        column += width;
+        let mark = if i < start {b' '} else {b'_'};
+        out.extend(std::iter::repeat_n(mark, width));
+    }
+    if start == before.len() {
+        out.extend_from_slice(b"^ no match for key");
+    }
+}
+
+/// A line without its ending, which is `terminator`, or \r\n when that is \n,
+/// so that lines from Windows equal those from elsewhere.
+pub fn line_content<'a>(line: &'a [u8],  terminator: &[u8]) -> &'a [u8] {
+    match line.strip_suffix(terminator) {
+        Some(line) if terminator == b"\n" => line.strip_suffix(b"\r").unwrap_or(line),
+        Some(line) => line,
+        None => line,
+    }
+}
+
+/// Find the value of a field in a logfmt line like `ts=2024-05-01T12:00:00Z level=info msg="hi there"`.
+/// Quoted values are returned without the quotes, but backslash escapes are kept.
+fn logfmt_value(line: &[u8],  name: &[u8]) -> Option<Range<usize>> {
+    let mut i = 0;
+    while i < line.len() {
+        if line[i] == b' '  ||  line[i] == b'\t' {
+            i += 1;
+            continue;
+        }
+        let key_starts = i;
+        while i < line.len()  &&  !matches!(line[i], b' ' | b'\t' | b'=' | b'"') {
+            i += 1;
+        }
+        let is_name = &line[key_starts..i] == name;
+        if line.get(i) != Some(&b'=') {
+            // a key without a value, or garbage
+            i += 1;
+            continue;
+        }
+        i += 1;
+        let value = if line.get(i) == Some(&b'"') {
+            let starts = i+1;
+            i = starts;
+            while i < line.len()  &&  line[i] != b'"' {
+                i += if line[i] == b'\\' {2} else {1};
+            }
+            let value = starts..i.min(line.len());
+            i += 1;
+            value
+        } else {
+            let starts = i;
+            while i < line.len()  &&  line[i] != b' '  &&  line[i] != b'\t' {
+                i += 1;
+            }
+            starts..i
+        };
+        if is_name {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Find the `field`th field, counting from 1, of a CSV record.
+/// Quoted fields are returned without the quotes, but doubled quotes inside them are kept.
+fn csv_field(record: &[u8],  field: usize,  separator: u8) -> Option<Range<usize>> {
+    let (mut i, mut n) = (0, 1);
+    loop {
+        let starts = i;
+        let mut quoted = false;
+        while i < record.len()  &&  (quoted  ||  record[i] != separator) {
+            if record[i] == b'"' {
+                quoted = !quoted;
+            }
+            i += 1;
+        }
+        if n == field {
+            let value = &record[starts..i];
+            return match (value.first(), value.last()) {
+                (Some(b'"'), Some(b'"')) if value.len() >= 2 => Some(starts+1..i-1),
+                _ => Some(starts..i),
+            };
+        } else if i == record.len() {
+            return None;
+        }
+        i += 1;
+        n += 1;
+    }
+}
@@ -0,0 +1,126 @@
+/* logmerge - A program to merge files like tail -f
+ * Copyright (C) 2021 Torbjørn Birch Moltu
+ *
+ * licenced under the terms of the GNU Lesser General Public License
+ * as published by the Free Software Foundation,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Shell completion scripts, generated from the same table as the option parser.
+
+use crate::args::{OPTIONS, OptionSpec, NAME};
+use std::io::{Write, Result as IoResult};
+
+fn documented() -> impl Iterator<Item=&'static OptionSpec> {
+    OPTIONS.iter().filter(|option| !option.hidden )
+}
+
+fn takes_file(option: &OptionSpec) -> bool {
+    option.value == Some("FILE")
+}
+
+/// Escape for use inside single quotes in a shell script.
+fn single_quoted(text: &str) -> String {
+    text.replace('\'', "'\\''")
+}
+
+pub fn write_bash(to: &mut dyn Write) -> IoResult<()> {
+    let function = format!("_{}", NAME.replace('-', "_"));
+    let mut all = Vec::new();
+    let mut with_file = Vec::new();
+    let mut with_other = Vec::new();
+    for option in documented() {
+        let mut names = vec![format!("--{}", option.long)];
+        if let Some(short) = option.short {
+            names.push(format!("-{}", short));
+        }
+        match option.value {
+            // the value must be attached with =, so the next word is unrelated
+            Some(_) if option.optional => {}
+            Some(_) if takes_file(option) => with_file.extend(names.iter().cloned()),
+            Some(_) => with_other.extend(names.iter().cloned()),
+            None => {}
+        }
+        all.extend(names);
+    }
+    writeln!(to, "{}() {{", function)?;
+    writeln!(to, "    local cur=\"${{COMP_WORDS[COMP_CWORD]}}\"")?;
+    writeln!(to, "    local prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"")?;
+    writeln!(to, "    case \"$prev\" in")?;
+    if !with_file.is_empty() {
+        writeln!(to, "        {})", with_file.join("|"))?;
+        writeln!(to, "            COMPREPLY=($(compgen -f -- \"$cur\"))")?;
+        writeln!(to, "            return;;")?;
+    }
+    if !with_other.is_empty() {
+        writeln!(to, "        {})", with_other.join("|"))?;
+        writeln!(to, "            COMPREPLY=()")?;
+        writeln!(to, "            return;;")?;
+    }
+    writeln!(to, "    esac")?;
+    writeln!(to, "    if [[ \"$cur\" == -* ]]; then")?;
+    writeln!(to, "        COMPREPLY=($(compgen -W '{}' -- \"$cur\"))", all.join(" "))?;
+    writeln!(to, "    else")?;
+    writeln!(to, "        COMPREPLY=($(compgen -f -- \"$cur\"))")?;
+    writeln!(to, "    fi")?;
+    writeln!(to, "}}")?;
+    writeln!(to, "complete -o filenames -F {} {}", function, NAME)
+}
+
+pub fn write_zsh(to: &mut dyn Write) -> IoResult<()> {
+    writeln!(to, "#compdef {}", NAME)?;
+    writeln!(to)?;
+    writeln!(to, "_arguments -s \\")?;
+    for option in documented() {
+        let description = single_quoted(option.help)
+            .replace('[', "\\[")
+            .replace(']', "\\]")
+            .replace(':', "\\:");
+        let value = match option.value {
+            Some(name) if option.optional => format!("::{}: ", name),
+            Some(name) if takes_file(option) => format!(":{}:_files", name),
+            Some(name) => format!(":{}: ", name),
+            None => String::new(),
+        };
+        let equals = if option.optional {"=-"} else {""};
+        match option.short {
+            Some(short) if option.optional => {
+                writeln!(to, "    '-{}[{}]' \\", short, description)?;
+                writeln!(to, "    '--{}{}[{}]{}' \\", option.long, equals, description, value)?;
+            }
+            Some(short) => writeln!(to, "    {{-{},--{}}}'[{}]{}' \\",
+                    short, option.long, description, value
+            )?,
+            None => writeln!(to, "    '--{}{}[{}]{}' \\", option.long, equals, description, value)?,
+        }
+    }
+    writeln!(to, "    '*:file:_files'")
+}
+
+pub fn write_fish(to: &mut dyn Write) -> IoResult<()> {
+    for option in documented() {
+        write!(to, "complete -c {}", NAME)?;
+        if let Some(short) = option.short {
+            write!(to, " -s {}", short)?;
+        }
+        write!(to, " -l {}", option.long)?;
+        match option.value {
+            Some(_) if option.optional => {}
+            Some(_) if takes_file(option) => write!(to, " -r -F")?,
+            Some(_) => write!(to, " -x")?,
+            None => {}
+        }
+        // fish allows escaping inside single quotes
+        let description = option.help.replace('\\', "\\\\").replace('\'', "\\'");
+        writeln!(to, " -d '{}'", description)?;
+    }
+    Ok(())
+}
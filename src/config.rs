@@ -0,0 +1,255 @@
+/* logmerge - A program to merge files like tail -f
+ * Copyright (C) 2021 Torbjørn Birch Moltu
+ *
+ * licenced under the terms of the GNU Lesser General Public License
+ * as published by the Free Software Foundation,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Default options from the TAILMERGE_OPTS environment variable and the configuration file.
+//!
+//! Both are turned into arguments that are parsed before the real command line,
+//! so that the command line overrides the configuration file, which overrides
+//! the environment variable.
+
+use crate::args::{OPTIONS, usage_error, os_to_bytes, bytes_to_os};
+use std::env::var_os;
+use std::ffi::OsString;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+pub const ENVIRONMENT_VARIABLE: &str = "TAILMERGE_OPTS";
+
+/// Options that only make sense on the command line.
+const NOT_CONFIGURABLE: &[&str] = &["help", "version", "completions", "label", "priority", "no-config"];
+
+/// Split a string into words like a POSIX shell would, without expanding anything.
+pub fn split_shell_words(text: &[u8]) -> Result<Vec<Vec<u8>>, &'static str> {
+    let mut words = Vec::new();
+    let mut current = None::<Vec<u8>>;
+    let mut bytes = text.iter().copied();
+    while let Some(b) = bytes.next() {
+        match b {
+            b' ' | b'\t' | b'\n' | b'\r' => words.extend(current.take()),
+            b'\'' => {
+                let word = current.get_or_insert_with(Vec::new);
+                loop {
+                    match bytes.next() {
+                        Some(b'\'') => break,
+                        Some(b) => word.push(b),
+                        None => return Err("unterminated single quote"),
+                    }
+                }
+            }
+            b'"' => {
+                let word = current.get_or_insert_with(Vec::new);
+                loop {
+                    match bytes.next() {
+                        Some(b'"') => break,
+                        Some(b'\\') => match bytes.next() {
+                            Some(escaped @ (b'"' | b'\\' | b'$' | b'`')) => word.push(escaped),
+                            Some(b'\n') => {}
+                            Some(other) => word.extend_from_slice(&[b'\\', other]),
+                            None => return Err("unterminated double quote"),
+                        },
+                        Some(b) => word.push(b),
+                        None => return Err("unterminated double quote"),
+                    }
+                }
+            }
+            b'\\' => match bytes.next() {
+                Some(b'\n') => {}
+                Some(escaped) => current.get_or_insert_with(Vec::new).push(escaped),
+                None => return Err("backslash at end"),
+            },
+            b => current.get_or_insert_with(Vec::new).push(b),
+        }
+    }
+    words.extend(current);
+    Ok(words)
+}
+
+pub fn environment_arguments() -> Vec<OsString> {
+    let value = match var_os(ENVIRONMENT_VARIABLE) {
+        Some(value) => os_to_bytes(value),
+        None => return Vec::new(),
+    };
+    match split_shell_words(&value) {
+        Ok(words) => words.iter().map(|word| bytes_to_os(word) ).collect(),
+        Err(problem) => usage_error(&format!("cannot parse {}: {}", ENVIRONMENT_VARIABLE, problem)),
+    }
+}
+
+fn config_path() -> Option<PathBuf> {
+    let directory = match var_os("XDG_CONFIG_HOME") {
+        Some(directory) if !directory.is_empty() => PathBuf::from(directory),
+        _ => PathBuf::from(var_os("HOME")?).join(".config"),
+    };
+    Some(directory.join("tailmerge").join("config.toml"))
+}
+
+/// Parse a TOML string starting after the opening quote,
+/// returning the contents and the rest of the line.
+fn parse_string(quote: u8,  text: &[u8]) -> Result<(Vec<u8>, &[u8]), String> {
+    let mut value = Vec::new();
+    let mut i = 0;
+    while i < text.len() {
+        match text[i] {
+            b if b == quote => return Ok((value, &text[i+1..])),
+            b'\\' if quote == b'"' => {
+                i += 1;
+                match text.get(i) {
+                    Some(b'n') => value.push(b'\n'),
+                    Some(b't') => value.push(b'\t'),
+                    Some(b'r') => value.push(b'\r'),
+                    Some(b'0') => value.push(b'\0'),
+                    Some(b'"') => value.push(b'"'),
+                    Some(b'\\') => value.push(b'\\'),
+                    Some(b'u') if text.len() >= i+5 => {
+                        let hex = std::str::from_utf8(&text[i+1..i+5]).ok()
+                            .and_then(|hex| u32::from_str_radix(hex, 16).ok() )
+                            .and_then(char::from_u32)
+                            .ok_or("invalid \\u escape")?;
+                        value.extend_from_slice(hex.encode_utf8(&mut [0; 4]).as_bytes());
+                        i += 4;
+                    }
+                    _ => return Err("unsupported escape sequence in string".to_string()),
+                }
+            }
+            b => value.push(b),
+        }
+        i += 1;
+    }
+    Err("unterminated string".to_string())
+}
+
+fn trim_start(text: &[u8]) -> &[u8] {
+    let start = text.iter().position(|&b| b != b' '  &&  b != b'\t' ).unwrap_or(text.len());
+    &text[start..]
+}
+
+fn expect_end(rest: &[u8]) -> Result<(), String> {
+    match trim_start(rest).first() {
+        None | Some(b'#') => Ok(()),
+        Some(_) => Err("unexpected characters after the value".to_string()),
+    }
+}
+
+enum Value {
+    Boolean(bool),
+    /// strings and numbers, which become option arguments
+    Text(Vec<u8>),
+    Array(Vec<Vec<u8>>),
+}
+
+fn parse_value(text: &[u8]) -> Result<Value, String> {
+    match text.first() {
+        Some(&quote @ (b'"' | b'\'')) => {
+            let (value, rest) = parse_string(quote, &text[1..])?;
+            expect_end(rest)?;
+            Ok(Value::Text(value))
+        }
+        Some(b'[') => {
+            let mut values = Vec::new();
+            let mut rest = trim_start(&text[1..]);
+            loop {
+                match rest.first() {
+                    Some(b']') => {
+                        expect_end(&rest[1..])?;
+                        return Ok(Value::Array(values));
+                    }
+                    Some(&quote @ (b'"' | b'\'')) => {
+                        let (value, after) = parse_string(quote, &rest[1..])?;
+                        values.push(value);
+                        rest = trim_start(after);
+                        if rest.first() == Some(&b',') {
+                            rest = trim_start(&rest[1..]);
+                        } else if rest.first() != Some(&b']') {
+                            return Err("expected , or ] in array".to_string());
+                        }
+                    }
+                    _ => return Err("arrays can only contain strings, on one line".to_string()),
+                }
+            }
+        }
+        _ => {
+            let end = text.iter().position(|&b| b == b'#' ).unwrap_or(text.len());
+            let word = text[..end].trim_ascii_end();
+            match word {
+                b"true" => Ok(Value::Boolean(true)),
+                b"false" => Ok(Value::Boolean(false)),
+                _ if !word.is_empty()  &&  word.iter().all(|&b| b.is_ascii_digit() || b == b'-' ) => {
+                    Ok(Value::Text(word.to_vec()))
+                }
+                _ => Err("expected a string, number, boolean or array of strings".to_string()),
+            }
+        }
+    }
+}
+
+/// Turn one `key = value` line into arguments.
+fn parse_line(line: &[u8],  arguments: &mut Vec<OsString>) -> Result<(), String> {
+    let eq = line.iter().position(|&b| b == b'=' ).ok_or("expected key = value")?;
+    let key = String::from_utf8_lossy(line[..eq].trim_ascii()).into_owned();
+    let key = key.trim_matches('"');
+    let option = OPTIONS.iter()
+        .find(|option| option.long == key  &&  !NOT_CONFIGURABLE.contains(&option.long) )
+        .ok_or_else(|| format!("unknown key '{}'", key) )?;
+    let value = parse_value(line[eq+1..].trim_ascii())
+        .map_err(|problem| format!("invalid value for '{}': {}", key, problem) )?;
+    let with_value = |value: &[u8]| {
+        let mut argument = format!("--{}=", key).into_bytes();
+        argument.extend_from_slice(value);
+        bytes_to_os(&argument)
+    };
+    match (option.value, value) {
+        (None, Value::Boolean(true)) => arguments.push(OsString::from(format!("--{}", key))),
+        (None, Value::Boolean(false)) => {}
+        (None, _) => return Err(format!("'{}' must be true or false", key)),
+        (Some(_), Value::Boolean(true)) if option.optional => {
+            arguments.push(OsString::from(format!("--{}", key)));
+        }
+        (Some(_), Value::Boolean(false)) if option.optional => {}
+        (Some(_), Value::Text(value)) => arguments.push(with_value(&value)),
+        (Some(_), Value::Array(values)) => arguments.extend(values.iter().map(|value| with_value(value) )),
+        (Some(_), Value::Boolean(_)) => return Err(format!("'{}' must be a string", key)),
+    }
+    Ok(())
+}
+
+/// Read the configuration file if there is one.
+pub fn config_arguments() -> Vec<OsString> {
+    let path = match config_path() {
+        Some(path) => path,
+        None => return Vec::new(),
+    };
+    let contents = match fs::read(&path) {
+        Ok(contents) => contents,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Vec::new(),
+        Err(e) => crate::error("Cannot read", &os_to_bytes(path.into_os_string()), e, 2),
+    };
+    let mut arguments = Vec::new();
+    for (i, line) in contents.split(|&b| b == b'\n' ).enumerate() {
+        let line = line.trim_ascii();
+        if line.is_empty()  ||  line[0] == b'#' {
+            continue;
+        }
+        let result = match line[0] {
+            b'[' => Err("tables are not supported".to_string()),
+            _ => parse_line(line, &mut arguments),
+        };
+        if let Err(problem) = result {
+            usage_error(&format!("{}:{}: {}", path.display(), i+1, problem));
+        }
+    }
+    arguments
+}
@@ -0,0 +1,231 @@
+/* logmerge - A program to merge files like tail -f
+ * Copyright (C) 2021 Torbjørn Birch Moltu
+ *
+ * licenced under the terms of the GNU Lesser General Public License
+ * as published by the Free Software Foundation,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+
+//! Byte order marks and decoding files that aren't UTF-8.
+//!
+//! Programs on Windows often start UTF-8 files with the encoding of U+FEFF,
+//! which would otherwise become part of the first line and make it sort after everything else.
+//! Files in UTF-16, ISO-8859-1 or Windows-1252 are converted to UTF-8 as they're read,
+//! so that they can be merged with other files.
+
+use std::io::{Read, Error as IoError, ErrorKind};
+
+const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+const REPLACEMENT_CHARACTER: char = '\u{fffd}';
+
+/// From --encoding.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    /// UTF-16 if the file starts with a UTF-16 byte order mark, otherwise UTF-8
+    Auto,
+    /// ISO-8859-1, where every byte is the code point with the same value
+    Latin1,
+    /// like Latin1 except for 0x80..=0x9f
+    Windows1252,
+}
+
+/// What Windows-1252 has instead of the C1 control characters of ISO-8859-1.
+/// The five bytes it doesn't use are decoded as the control characters.
+const WINDOWS_1252: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž', '\u{8f}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}', 'ž', 'Ÿ',
+];
+
+/// Wraps a file and skips any byte order mark at its start,
+/// and converts it to UTF-8 if it's in another encoding.
+pub struct Decoder {
+    file: Box<dyn Read>,
+    /// Auto until the start has been read
+    encoding: Encoding,
+    /// --keep-bom
+    keep_bom: bool,
+    /// for messages
+    name: Box<[u8]>,
+    /// the start of the file has been read and any byte order mark skipped
+    started: bool,
+    /// read but not decoded, which for UTF-16 can be half a code unit
+    raw: Vec<u8>,
+    /// the first half of a surrogate pair whose second half hasn't been read yet
+    high_surrogate: Option<u16>,
+    /// decoded but not returned yet, starting at `given`
+    decoded: Vec<u8>,
+    given: usize,
+    eof: bool,
+    /// how many unpaired surrogates have been replaced with U+FFFD
+    replaced: u64,
+}
+impl Decoder {
+    pub fn new(file: Box<dyn Read>,  encoding: Encoding,  keep_bom: bool,  name: &[u8]) -> Self {
+        Decoder {
+            file,
+            encoding,
+            keep_bom,
+            name: name.into(),
+            started: false,
+            raw: Vec::new(),
+            high_surrogate: None,
+            decoded: Vec::new(),
+            given: 0,
+            eof: false,
+            replaced: 0,
+        }
+    }
+
+    /// Read the first three bytes, or as many as the file has,
+    /// to decide the encoding and skip any byte order mark.
+    fn start(&mut self) -> Result<(), IoError> {
+        let mut start = [0; 3];
+        let mut read = 0;
+        while read < start.len() {
+            match self.file.read(&mut start[read..]) {
+                Ok(0) => break,
+                Ok(bytes) => read += bytes,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let start = &start[..read];
+        let utf16 = match start.get(..2) {
+            Some(b"\xff\xfe") => Some(Encoding::Utf16Le),
+            Some(b"\xfe\xff") => Some(Encoding::Utf16Be),
+            _ => None,
+        };
+        let skip = match (self.encoding, utf16) {
+            (Encoding::Auto, Some(encoding)) => {
+                self.encoding = encoding;
+                2
+            }
+            (Encoding::Utf16Le, Some(Encoding::Utf16Le)) | (Encoding::Utf16Be, Some(Encoding::Utf16Be)) => 2,
+            (Encoding::Utf8, Some(_)) if !self.keep_bom => {
+                let message = "starts with a UTF-16 byte order mark (use --encoding auto to decode it)";
+                return Err(IoError::new(ErrorKind::InvalidData, message));
+            }
+            (Encoding::Auto, None) | (Encoding::Utf8, _) => {
+                self.encoding = Encoding::Utf8;
+                if start == UTF8_BOM {3} else {0}
+            }
+            (_, _) => 0,
+        };
+        let skip = if self.keep_bom {0} else {skip};
+        match self.encoding {
+            Encoding::Utf8 => self.decoded.extend_from_slice(&start[skip..]),
+            _ => self.raw.extend_from_slice(&start[skip..]),
+        }
+        self.started = true;
+        Ok(())
+    }
+
+    /// Decode a single-byte encoding, where a byte can become two.
+    fn decode_bytes(&mut self) {
+        for &b in &self.raw {
+            match (b, self.encoding) {
+                (0..=0x7f, _) => self.decoded.push(b),
+                (0x80..=0x9f, Encoding::Windows1252) => push_char(&mut self.decoded, WINDOWS_1252[b as usize - 0x80]),
+                _ => push_char(&mut self.decoded, b as char),
+            }
+        }
+        self.raw.clear();
+    }
+
+    /// Decode the complete code units in `raw`, or everything at end of file.
+    fn decode_utf16(&mut self) {
+        let big_endian = self.encoding == Encoding::Utf16Be;
+        let complete = self.raw.len() - self.raw.len() % 2;
+        for pair in self.raw[..complete].chunks_exact(2) {
+            let unit = match big_endian {
+                true => u16::from_be_bytes([pair[0], pair[1]]),
+                false => u16::from_le_bytes([pair[0], pair[1]]),
+            };
+            let c = match (self.high_surrogate.take(), unit) {
+                (Some(high), 0xdc00..=0xdfff) => {
+                    let c = 0x10000 + ((high as u32 - 0xd800) << 10) + (unit as u32 - 0xdc00);
+                    char::from_u32(c).unwrap()
+                }
+                (high, _) => {
+                    if high.is_some() {
+                        self.replaced += 1;
+                        push_char(&mut self.decoded, REPLACEMENT_CHARACTER);
+                    }
+                    match unit {
+                        0xd800..=0xdbff => {
+                            self.high_surrogate = Some(unit);
+                            continue;
+                        }
+                        0xdc00..=0xdfff => {
+                            self.replaced += 1;
+                            REPLACEMENT_CHARACTER
+                        }
+                        _ => char::from_u32(unit as u32).unwrap(),
+                    }
+                }
+            };
+            push_char(&mut self.decoded, c);
+        }
+        self.raw.drain(..complete);
+        if self.eof  &&  (self.high_surrogate.take().is_some() || !self.raw.is_empty()) {
+            // half a code unit or surrogate pair
+            self.replaced += 1;
+            self.raw.clear();
+            push_char(&mut self.decoded, REPLACEMENT_CHARACTER);
+        }
+    }
+}
+impl Read for Decoder {
+    fn read(&mut self,  buf: &mut [u8]) -> Result<usize, IoError> {
+        if !self.started {
+            self.start()?;
+        }
+        loop {
+            if self.given < self.decoded.len() {
+                let length = buf.len().min(self.decoded.len()-self.given);
+                buf[..length].copy_from_slice(&self.decoded[self.given..self.given+length]);
+                self.given += length;
+                return Ok(length);
+            }
+            self.decoded.clear();
+            self.given = 0;
+            if self.encoding == Encoding::Utf8 {
+                return self.file.read(buf);
+            } else if self.eof {
+                return Ok(0);
+            }
+            let mut chunk = [0; 8192];
+            let read = self.file.read(&mut chunk)?;
+            self.raw.extend_from_slice(&chunk[..read]);
+            if read == 0 {
+                self.eof = true;
+            }
+            match self.encoding {
+                Encoding::Latin1 | Encoding::Windows1252 => self.decode_bytes(),
+                _ => self.decode_utf16(),
+            }
+            if self.eof  &&  self.replaced != 0 {
+                let replaced = self.replaced.to_string();
+                crate::verbose(1, &[&self.name, b": replaced ", replaced.as_bytes(), b" unpaired surrogates with U+FFFD"]);
+            }
+        }
+    }
+}
+
+fn push_char(to: &mut Vec<u8>,  c: char) {
+    let mut encoded = [0; 4];
+    to.extend_from_slice(c.encode_utf8(&mut encoded).as_bytes());
+}
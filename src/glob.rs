@@ -0,0 +1,202 @@
+/* logmerge - A program to merge files like tail -f
+ * Copyright (C) 2021 Torbjørn Birch Moltu
+ *
+ * licenced under the terms of the GNU Lesser General Public License
+ * as published by the Free Software Foundation,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Wildcard patterns, for platforms where the shell doesn't expand them.
+//!
+//! Supports `*`, `?`, `[abc]`, `[a-z]`, `[!abc]` and `**` for any number of directories.
+
+use crate::args::{os_as_bytes, bytes_to_os};
+use std::fs;
+use std::path::{Path, PathBuf, Component};
+
+pub fn has_wildcards(pattern: &[u8]) -> bool {
+    pattern.iter().any(|&b| matches!(b, b'*' | b'?' | b'[') )
+}
+
+/// Match a bracket expression starting after the `[`.
+/// Returns whether it matched and the length of the expression including the `]`,
+/// or None if it is unterminated and should be treated as a literal `[`.
+fn match_class(class: &[u8],  b: u8) -> Option<(bool, usize)> {
+    let (negated, mut i) = match class.first() {
+        Some(b'!') | Some(b'^') => (true, 1),
+        _ => (false, 0),
+    };
+    let mut matched = false;
+    let mut first = true;
+    while i < class.len() {
+        if class[i] == b']'  &&  !first {
+            return Some((matched != negated, i+1));
+        }
+        first = false;
+        if i+2 < class.len()  &&  class[i+1] == b'-'  &&  class[i+2] != b']' {
+            matched |= class[i] <= b  &&  b <= class[i+2];
+            i += 3;
+        } else {
+            matched |= class[i] == b;
+            i += 1;
+        }
+    }
+    None
+}
+
+/// Check whether `text` matches `pattern`.
+/// `*` and `?` never match `/`, but `**` matches anything.
+pub fn matches(pattern: &[u8],  text: &[u8]) -> bool {
+    if let Some(&first) = pattern.first() {
+        match first {
+            b'*' if pattern.get(1) == Some(&b'*') => {
+                let mut rest = &pattern[2..];
+                // make **/ also match zero directories
+                if rest.first() == Some(&b'/') {
+                    if matches(&rest[1..], text) {
+                        return true;
+                    }
+                    rest = &rest[1..];
+                }
+                (0..=text.len()).any(|skip| matches(rest, &text[skip..]) )
+            }
+            b'*' => {
+                let max = text.iter().position(|&b| b == b'/' ).unwrap_or(text.len());
+                (0..=max).any(|skip| matches(&pattern[1..], &text[skip..]) )
+            }
+            b'?' => match text.first() {
+                Some(&b) if b != b'/' => matches(&pattern[1..], &text[1..]),
+                _ => false,
+            },
+            b'[' => match (text.first(), match_class(&pattern[1..], *text.first().unwrap_or(&0))) {
+                (Some(&b'/'), _) | (None, _) => false,
+                (Some(_), Some((true, length))) => matches(&pattern[1+length..], &text[1..]),
+                (Some(_), Some((false, _))) => false,
+                (Some(&b), None) => b == b'[' && matches(&pattern[1..], &text[1..]),
+            },
+            literal => text.first() == Some(&literal)  &&  matches(&pattern[1..], &text[1..]),
+        }
+    } else {
+        text.is_empty()
+    }
+}
+
+/// Path in a form where it can be matched: components separated by `/`.
+pub fn matchable(path: &Path) -> Vec<u8> {
+    let mut joined = Vec::new();
+    for component in path.components() {
+        if !joined.is_empty() {
+            joined.push(b'/');
+        }
+        joined.extend_from_slice(&os_as_bytes(component.as_os_str()));
+    }
+    joined
+}
+
+/// The --exclude and --include patterns.
+/// A file is excluded if it matches any exclude pattern and no include pattern.
+/// Patterns without a `/` are matched against the file name only,
+/// but otherwise against the path relative to the argument the file was found through.
+#[derive(Default)]
+pub struct Filters {
+    pub exclude: Vec<Vec<u8>>,
+    pub include: Vec<Vec<u8>>,
+}
+impl Filters {
+    fn any_matches(patterns: &[Vec<u8>],  relative: &[u8]) -> bool {
+        let name_starts = relative.iter().rposition(|&b| b == b'/' ).map_or(0, |slash| slash+1 );
+        patterns.iter().any(|pattern| match pattern.contains(&b'/') {
+            true => matches(pattern, relative),
+            false => matches(pattern, &relative[name_starts..]),
+        })
+    }
+
+    pub fn allows(&self,  relative: &Path) -> bool {
+        if self.exclude.is_empty() {
+            return true;
+        }
+        let relative = matchable(relative);
+        !Self::any_matches(&self.exclude, &relative)  ||  Self::any_matches(&self.include, &relative)
+    }
+}
+
+/// The part of a path before the first component with wildcards.
+pub fn literal_prefix(pattern: &Path) -> PathBuf {
+    pattern.components()
+        .take_while(|component| !has_wildcards(&os_as_bytes(component.as_os_str())) )
+        .collect()
+}
+
+fn read_dir_sorted(dir: &Path) -> Vec<(Vec<u8>, PathBuf)> {
+    let listing = if dir.as_os_str().is_empty() {Path::new(".")} else {dir};
+    let mut entries = match fs::read_dir(listing) {
+        Ok(entries) => entries.filter_map(|entry| entry.ok() )
+            .map(|entry| {
+                let name = os_as_bytes(&entry.file_name()).into_owned();
+                (name, dir.join(entry.file_name()))
+            })
+            .collect::<Vec<_>>(),
+        Err(_) => Vec::new(),
+    };
+    entries.sort();
+    entries
+}
+
+fn add_subdirectories(dir: &Path,  to: &mut Vec<PathBuf>) {
+    to.push(dir.to_path_buf());
+    for (name, path) in read_dir_sorted(dir) {
+        if name[0] != b'.'  &&  path.is_dir() {
+            add_subdirectories(&path, to);
+        }
+    }
+}
+
+/// Find the paths matching a pattern, sorted.
+/// Names starting with a dot are only matched by patterns that also start with a dot.
+pub fn expand(pattern: &Path) -> Vec<PathBuf> {
+    let mut candidates = vec![PathBuf::new()];
+    for component in pattern.components() {
+        let part = match component {
+            Component::Normal(part) => os_as_bytes(part),
+            other => {
+                for candidate in &mut candidates {
+                    candidate.push(other.as_os_str());
+                }
+                continue;
+            }
+        };
+        if &part[..] == b"**" {
+            let mut directories = Vec::new();
+            for candidate in &candidates {
+                add_subdirectories(candidate, &mut directories);
+            }
+            candidates = directories;
+        } else if has_wildcards(&part) {
+            let mut found = Vec::new();
+            for candidate in &candidates {
+                for (name, path) in read_dir_sorted(candidate) {
+                    if (name[0] != b'.' || part[0] == b'.')  &&  matches(&part, &name) {
+                        found.push(path);
+                    }
+                }
+            }
+            candidates = found;
+        } else {
+            for candidate in &mut candidates {
+                candidate.push(bytes_to_os(&part));
+            }
+        }
+    }
+    candidates.retain(|path| path.exists() );
+    candidates.sort_by(|a, b| os_as_bytes(a.as_os_str()).cmp(&os_as_bytes(b.as_os_str())) );
+    candidates.dedup();
+    candidates
+}
@@ -0,0 +1,127 @@
+/* logmerge - A program to merge files like tail -f
+ * Copyright (C) 2021 Torbjørn Birch Moltu
+ *
+ * licenced under the terms of the GNU Lesser General Public License
+ * as published by the Free Software Foundation,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+
+//! Headers from --heading-format, like `\n--- {index}: {path}\n`.
+
+/// A part of the template.
+#[derive(Clone, Debug)]
+enum Segment {
+    Literal(Vec<u8>),
+    /// `{path}`: the path, even if the file has a label
+    Path,
+    /// `{label}`: the label, or the path if the file has none
+    Label,
+    /// `{index}`: which file it is, counting from 1
+    Index,
+    /// `{total_lines_so_far}`: how many lines have been printed before the header
+    TotalLines,
+}
+
+/// A compiled template.
+#[derive(Clone, Debug)]
+pub struct HeadingFormat {
+    segments: Vec<Segment>,
+}
+
+impl HeadingFormat {
+    /// Parse a template where escape sequences have already been replaced.
+    /// `{{` and `}}` are literal braces.
+    pub fn parse(template: &[u8]) -> Result<Self, String> {
+        let mut segments = Vec::new();
+        let mut literal = Vec::new();
+        let mut i = 0;
+        while i < template.len() {
+            match (template[i], template.get(i+1)) {
+                (b'{', Some(b'{')) | (b'}', Some(b'}')) => {
+                    literal.push(template[i]);
+                    i += 2;
+                }
+                (b'{', _) => {
+                    let ends = match template[i..].iter().position(|&b| b == b'}' ) {
+                        Some(ends) => i + ends,
+                        None => return Err("'{' is never closed (write '{{' for a literal brace)".to_string()),
+                    };
+                    let placeholder = match &template[i+1..ends] {
+                        b"path" => Segment::Path,
+                        b"label" => Segment::Label,
+                        b"index" => Segment::Index,
+                        b"total_lines_so_far" => Segment::TotalLines,
+                        other => return Err(format!(
+                                "unknown placeholder {{{}}} (expected path, label, index or total_lines_so_far)",
+                                String::from_utf8_lossy(other)
+                        )),
+                    };
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(literal.split_off(0)));
+                    }
+                    segments.push(placeholder);
+                    i = ends + 1;
+                }
+                (b'}', _) => return Err("unmatched '}' (write '}}' for a literal brace)".to_string()),
+                (b, _) => {
+                    literal.push(b);
+                    i += 1;
+                }
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Ok(HeadingFormat { segments })
+    }
+
+    /// What all headers start with after the blank line before them, for --escape-headings.
+    pub fn marker(&self) -> &[u8] {
+        match self.segments.first() {
+            Some(Segment::Literal(literal)) => {
+                let starts = literal.iter().position(|&b| b != b'\n'  &&  b != b'\r' ).unwrap_or(literal.len());
+                let literal = &literal[starts..];
+                &literal[..literal.iter().position(|&b| b == b'\n'  ||  b == b'\r' ).unwrap_or(literal.len())]
+            }
+            _ => b"",
+        }
+    }
+
+    /// Make the newlines in the template \r\n, for --crlf.
+    pub fn crlf(&mut self) {
+        for segment in &mut self.segments {
+            if let Segment::Literal(literal) = segment {
+                let mut converted = Vec::with_capacity(literal.len());
+                for (i, &b) in literal.iter().enumerate() {
+                    if b == b'\n'  &&  (i == 0  ||  literal[i-1] != b'\r') {
+                        converted.push(b'\r');
+                    }
+                    converted.push(b);
+                }
+                *literal = converted;
+            }
+        }
+    }
+
+    /// Append the header for a file to `out`.
+    pub fn render(&self,  path: &[u8],  label: &[u8],  index: usize,  lines: u64,  out: &mut Vec<u8>) {
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => out.extend_from_slice(literal),
+                Segment::Path => out.extend_from_slice(path),
+                Segment::Label => out.extend_from_slice(label),
+                Segment::Index => out.extend_from_slice((index+1).to_string().as_bytes()),
+                Segment::TotalLines => out.extend_from_slice(lines.to_string().as_bytes()),
+            }
+        }
+    }
+}
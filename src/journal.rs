@@ -0,0 +1,191 @@
+/* logmerge - A program to merge files like tail -f
+ * Copyright (C) 2021 Torbjørn Birch Moltu
+ *
+ * licenced under the terms of the GNU Lesser General Public License
+ * as published by the Free Software Foundation,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Reading the export format of systemd's journal (`journalctl -o export`) as lines.
+//!
+//! Each record is a number of `FIELD=value` lines followed by an empty line,
+//! except that fields with binary values are written as `FIELD\n`, a 64-bit little-endian length,
+//! the value and a newline.
+//! Records are rendered like `2024-05-02T13:37:00.123456Z host sshd[123]: message`,
+//! with newlines and other control characters in messages escaped so that each record is one line.
+
+use crate::timestamp;
+use std::convert::TryFrom;
+use std::io::{Read, Error as IoError, ErrorKind};
+use std::ops::Range;
+
+/// Wraps a file in the export format and reads rendered lines from it.
+pub struct JournalExport {
+    file: Box<dyn Read>,
+    /// what has been read from the file, starting at `decoded`
+    raw: Vec<u8>,
+    decoded: usize,
+    /// the file has reached its end
+    eof: bool,
+    /// rendered lines that haven't been read yet, starting at `given`
+    rendered: Vec<u8>,
+    given: usize,
+    /// the names and values of the fields of the record being decoded, as ranges in `raw`
+    fields: Vec<(Range<usize>, Range<usize>)>,
+}
+impl JournalExport {
+    pub fn new(file: Box<dyn Read>) -> Self {
+        JournalExport {
+            file,
+            raw: Vec::new(),
+            decoded: 0,
+            eof: false,
+            rendered: Vec::new(),
+            given: 0,
+            fields: Vec::new(),
+        }
+    }
+
+    /// Find the fields of the next complete record in `raw`, and return where it ends.
+    /// At the end of the file the last record doesn't need to end with an empty line,
+    /// but its fields must be complete.
+    fn next_record(&mut self) -> Result<Option<usize>, IoError> {
+        self.fields.clear();
+        let raw = &self.raw;
+        let mut i = self.decoded;
+        loop {
+            let newline = match raw[i..].iter().position(|&b| b == b'\n' ) {
+                Some(newline) => i + newline,
+                None if i == raw.len() => return Ok(self.incomplete()),
+                None => return self.cut_off(),
+            };
+            if newline == i {
+                // an empty line ends the record; ignore any extra ones
+                if self.fields.is_empty() {
+                    i += 1;
+                    self.decoded = i;
+                    continue;
+                }
+                return Ok(Some(i+1));
+            }
+            match raw[i..newline].iter().position(|&b| b == b'=' ) {
+                Some(equals) => {
+                    self.fields.push((i..i+equals, i+equals+1..newline));
+                    i = newline+1;
+                }
+                None => {
+                    let length = match raw.get(newline+1..newline+9) {
+                        Some(length) => {
+                            let mut bytes = [0; 8];
+                            bytes.copy_from_slice(length);
+                            u64::from_le_bytes(bytes)
+                        }
+                        None => return self.cut_off(),
+                    };
+                    let starts = newline+9;
+                    let ends = match usize::try_from(length).ok().and_then(|length| starts.checked_add(length) ) {
+                        Some(ends) if ends < raw.len() => ends,
+                        _ => return self.cut_off(),
+                    };
+                    self.fields.push((i..newline, starts..ends));
+                    // skip the newline after the value
+                    i = ends+1;
+                }
+            }
+        }
+    }
+
+    /// What next_record() returns when the record isn't complete:
+    /// at the end of the file it ends there if it has any fields.
+    fn incomplete(&self) -> Option<usize> {
+        match self.eof  &&  !self.fields.is_empty() {
+            true => Some(self.raw.len()),
+            false => None,
+        }
+    }
+
+    /// What next_record() returns when a field isn't complete:
+    /// at the end of the file the rest of it is missing.
+    fn cut_off(&self) -> Result<Option<usize>, IoError> {
+        match self.eof {
+            true => Err(IoError::new(ErrorKind::InvalidData, "the last record is cut off")),
+            false => Ok(None),
+        }
+    }
+
+    /// Render the fields found by next_record().
+    fn render(&mut self) {
+        let (raw, fields) = (&self.raw, &self.fields);
+        let field = |name: &[u8]| {
+            fields.iter()
+                .find(|(field, _)| &raw[field.clone()] == name )
+                .map(|(_, value)| &raw[value.clone()] )
+        };
+        let out = &mut self.rendered;
+        let realtime = field(b"__REALTIME_TIMESTAMP")
+                .and_then(|value| std::str::from_utf8(value).ok()?.parse::<i64>().ok() );
+        match realtime {
+            Some(microseconds) => out.extend_from_slice(timestamp::format_microseconds(microseconds).as_bytes()),
+            None => out.push(b'-'),
+        }
+        out.push(b' ');
+        out.extend_from_slice(field(b"_HOSTNAME").unwrap_or(b"-"));
+        out.push(b' ');
+        let identifier = field(b"SYSLOG_IDENTIFIER").or_else(|| field(b"_COMM") ).unwrap_or(b"-");
+        out.extend_from_slice(identifier);
+        if let Some(pid) = field(b"_PID").or_else(|| field(b"SYSLOG_PID") ) {
+            out.push(b'[');
+            out.extend_from_slice(pid);
+            out.push(b']');
+        }
+        out.extend_from_slice(b": ");
+        let message = field(b"MESSAGE").unwrap_or(b"");
+        for &b in message.strip_suffix(b"\n").unwrap_or(message) {
+            match b {
+                b'\n' => out.extend_from_slice(b"\\n"),
+                b'\r' => out.extend_from_slice(b"\\r"),
+                b'\t' => out.push(b),
+                0..=0x1f | 0x7f => out.extend_from_slice(format!("\\x{:02x}", b).as_bytes()),
+                _ => out.push(b),
+            }
+        }
+        out.push(b'\n');
+    }
+}
+impl Read for JournalExport {
+    fn read(&mut self,  buf: &mut [u8]) -> Result<usize, IoError> {
+        while self.given == self.rendered.len() {
+            self.rendered.clear();
+            self.given = 0;
+            if let Some(ends) = self.next_record()? {
+                self.render();
+                self.decoded = ends;
+                continue;
+            } else if self.eof {
+                return Ok(0);
+            }
+            // keep only the record being decoded
+            self.raw.drain(..self.decoded);
+            self.decoded = 0;
+            let old_length = self.raw.len();
+            self.raw.resize(old_length + 64*1024, 0);
+            let read = self.file.read(&mut self.raw[old_length..]);
+            self.raw.truncate(old_length + read.as_ref().map_or(0, |&read| read ));
+            if read? == 0 {
+                self.eof = true;
+            }
+        }
+        let length = buf.len().min(self.rendered.len() - self.given);
+        buf[..length].copy_from_slice(&self.rendered[self.given..self.given+length]);
+        self.given += length;
+        Ok(length)
+    }
+}
@@ -0,0 +1,242 @@
+/* logmerge - A program to merge files like tail -f
+ * Copyright (C) 2021 Torbjørn Birch Moltu
+ *
+ * licenced under the terms of the GNU Lesser General Public License
+ * as published by the Free Software Foundation,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Finding a value in a line of JSON for --json-key, without parsing all of it,
+//! and escaping strings for --output-format=json.
+//!
+//! Values that aren't on the way to the key are skipped without being validated.
+
+use std::ops::Range;
+
+/// A JSON Pointer (RFC 6901) like `/meta/ts`.
+#[derive(Clone, Debug)]
+pub struct Pointer {
+    /// with ~0 and ~1 unescaped
+    tokens: Vec<Vec<u8>>,
+}
+
+/// What kind of value a pointer found.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Kind {
+    String,
+    Number,
+    /// true, false, null, an object or an array
+    Other,
+}
+
+impl Pointer {
+    pub fn new(pointer: &[u8]) -> Result<Self, String> {
+        let rest = match pointer {
+            [] => return Ok(Pointer { tokens: Vec::new() }),
+            [b'/', rest @ ..] => rest,
+            _ => return Err("doesn't start with /".to_string()),
+        };
+        let mut tokens = Vec::new();
+        for token in rest.split(|&b| b == b'/' ) {
+            let mut unescaped = Vec::with_capacity(token.len());
+            let mut bytes = token.iter().copied();
+            while let Some(b) = bytes.next() {
+                unescaped.push(match b {
+                    b'~' => match bytes.next() {
+                        Some(b'0') => b'~',
+                        Some(b'1') => b'/',
+                        _ => return Err("has a ~ that isn't followed by 0 or 1".to_string()),
+                    },
+                    b => b,
+                });
+            }
+            tokens.push(unescaped);
+        }
+        Ok(Pointer { tokens })
+    }
+
+    /// Find the value in a line, and return where it is.
+    /// For strings it's without the quotes, but with any escapes.
+    pub fn find(&self,  line: &[u8]) -> Option<(Range<usize>, Kind)> {
+        let mut i = whitespace(line, 0);
+        for token in &self.tokens {
+            i = match line.get(i)? {
+                b'{' => member(line, i+1, token)?,
+                b'[' => element(line, i+1, std::str::from_utf8(token).ok()?.parse().ok()?)?,
+                _ => return None,
+            };
+        }
+        let ends = skip_value(line, i)?;
+        match line[i] {
+            b'"' => Some((i+1..ends-1, Kind::String)),
+            b'-' | b'0'..=b'9' => Some((i..ends, Kind::Number)),
+            _ => Some((i..ends, Kind::Other)),
+        }
+    }
+}
+
+fn whitespace(line: &[u8],  mut i: usize) -> usize {
+    while i < line.len()  &&  matches!(line[i], b' ' | b'\t' | b'\r' | b'\n') {
+        i += 1;
+    }
+    i
+}
+
+/// Find where a string starting at `i` ends, after the closing quote.
+fn skip_string(line: &[u8],  mut i: usize) -> Option<usize> {
+    i += 1;
+    loop {
+        match line.get(i)? {
+            b'"' => return Some(i+1),
+            b'\\' => i += 2,
+            _ => i += 1,
+        }
+    }
+}
+
+/// Find where the value starting at `i` ends.
+fn skip_value(line: &[u8],  i: usize) -> Option<usize> {
+    match line.get(i)? {
+        b'"' => skip_string(line, i),
+        b'{' | b'[' => {
+            // skip nested values by counting brackets, without recursing
+            let mut depth = 0usize;
+            let mut i = i;
+            loop {
+                match line.get(i)? {
+                    b'"' => {
+                        i = skip_string(line, i)?;
+                        continue;
+                    }
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(i+1);
+                        }
+                    }
+                    _ => {}
+                }
+                i += 1;
+            }
+        }
+        _ => {
+            let length = line[i..].iter()
+                .take_while(|&&b| !matches!(b, b',' | b'}' | b']' | b' ' | b'\t' | b'\r' | b'\n') )
+                .count();
+            if length == 0 {None} else {Some(i+length)}
+        }
+    }
+}
+
+/// Find the value of a member of the object whose content starts at `i`.
+fn member(line: &[u8],  mut i: usize,  name: &[u8]) -> Option<usize> {
+    loop {
+        i = whitespace(line, i);
+        if line.get(i)? != &b'"' {
+            return None; // '}' or invalid
+        }
+        let key_ends = skip_string(line, i)?;
+        let matches = string_equals(&line[i+1..key_ends-1], name);
+        i = whitespace(line, key_ends);
+        if line.get(i)? != &b':' {
+            return None;
+        }
+        i = whitespace(line, i+1);
+        if matches {
+            return Some(i);
+        }
+        i = whitespace(line, skip_value(line, i)?);
+        match line.get(i)? {
+            b',' => i += 1,
+            _ => return None,
+        }
+    }
+}
+
+/// Find element `index` of the array whose content starts at `i`.
+fn element(line: &[u8],  mut i: usize,  index: usize) -> Option<usize> {
+    for _ in 0..index {
+        i = whitespace(line, skip_value(line, whitespace(line, i))?);
+        match line.get(i)? {
+            b',' => i += 1,
+            _ => return None,
+        }
+    }
+    let i = whitespace(line, i);
+    match line.get(i)? {
+        b']' => None,
+        _ => Some(i),
+    }
+}
+
+/// Compare the content of a JSON string with escapes to unescaped bytes.
+fn string_equals(mut escaped: &[u8],  mut unescaped: &[u8]) -> bool {
+    while let Some((&b, rest)) = escaped.split_first() {
+        let (decoded, rest) = match (b, rest) {
+            (b'\\', [b'u', rest @ ..]) => {
+                let hex = |digits: &[u8]| u32::from_str_radix(std::str::from_utf8(digits.get(..4)?).ok()?, 16).ok() ;
+                let (c, rest) = match hex(rest) {
+                    Some(high @ 0xd800..=0xdbff) => match (rest.get(4..6), rest.get(6..).and_then(hex)) {
+                        (Some(b"\\u"), Some(low @ 0xdc00..=0xdfff)) => {
+                            (0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00), &rest[10..])
+                        }
+                        _ => return false,
+                    },
+                    Some(c) => (c, &rest[4..]),
+                    None => return false,
+                };
+                let mut encoded = [0; 4];
+                let encoded = match char::from_u32(c) {
+                    Some(c) => c.encode_utf8(&mut encoded).as_bytes(),
+                    None => return false,
+                };
+                match unescaped.strip_prefix(encoded) {
+                    Some(after) => unescaped = after,
+                    None => return false,
+                }
+                escaped = rest;
+                continue;
+            }
+            (b'\\', [escape, rest @ ..]) => (match escape {
+                b'b' => b'\x08',
+                b'f' => b'\x0c',
+                b'n' => b'\n',
+                b'r' => b'\r',
+                b't' => b'\t',
+                other => *other,
+            }, rest),
+            (b, rest) => (b, rest),
+        };
+        match unescaped.split_first() {
+            Some((&first, after)) if first == decoded => unescaped = after,
+            _ => return false,
+        }
+        escaped = rest;
+    }
+    unescaped.is_empty()
+}
+
+/// Append `text` as the inside of a JSON string, for --output-format=json.
+/// Bytes that aren't valid UTF-8 are replaced with U+FFFD.
+pub fn escape(text: &[u8],  out: &mut Vec<u8>) {
+    for c in String::from_utf8_lossy(text).chars() {
+        match c {
+            '"' => out.extend_from_slice(b"\\\""),
+            '\\' => out.extend_from_slice(b"\\\\"),
+            '\n' => out.extend_from_slice(b"\\n"),
+            '\r' => out.extend_from_slice(b"\\r"),
+            '\t' => out.extend_from_slice(b"\\t"),
+            c if c < ' '  ||  c == '\x7f' => out.extend_from_slice(format!("\\u{:04x}", c as u32).as_bytes()),
+            c => out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+}
@@ -14,19 +14,51 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+mod args;
+mod collate;
+mod color;
+mod completions;
+mod compare;
+mod config;
+mod encoding;
+mod glob;
+mod heading;
+mod json;
+mod journal;
+#[cfg(feature="normalize")]
+mod normalize;
+#[cfg(feature="normalize")]
+mod normalize_tables;
+mod pager;
+mod regex;
+mod timestamp;
+mod width;
+mod zone;
+
+use compare::{Comparator, Key, line_content};
+use regex::Regex;
+use timestamp::{TimeFormat, YearGuess};
+use zone::Zone;
+use args::{parse_args, os_to_bytes, os_as_bytes, print_usage_and_fail};
+use args::{Input, InputFormat, OutputFormat, Location, BlankLines, Records, Unsorted, ColorWhen};
+use journal::JournalExport;
+use encoding::{Decoder, Encoding};
+
 use std::env::args_os;
-use std::process::exit;
-use std::fs::File;
-use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH, Instant};
+use std::ffi::OsStr;
+use std::process;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
 #[cfg(unix)]
-use std::os::unix::ffi::OsStringExt;
-#[cfg(wasi)]
-use std::os::wasi::ffi::OsStringExt;
-use std::io::{stderr, Write, Error as IoError, Read, stdout, IoSlice};
-use std::error::Error as _;
-use std::collections::BinaryHeap;
+use std::os::unix::fs::MetadataExt;
+use std::io::{stderr, Write, Error as IoError, ErrorKind, Read, stdin, stdout, IoSlice, IsTerminal};
+use std::collections::{BinaryHeap, VecDeque};
+use std::rc::Rc;
 use std::cmp::{Ord, PartialOrd, Ordering};
 use std::cell::{RefCell, Ref, Cell};
+use std::sync::atomic::{AtomicU8, AtomicBool, Ordering as AtomicOrdering};
+use std::borrow::Cow;
 #[cfg(any(debug_assertions, feature="debug"))]
 use std::fmt::{Debug, Formatter, Result as FmtResult};
 
@@ -34,7 +66,8 @@ fn write_all_vectored(to: &mut dyn Write,  buffers: &[IoSlice]) -> Result<(), Io
     let mut i = 0;
     while i < buffers.len() {
         let mut wrote = to.write_vectored(&buffers[i..])?;
-        while wrote > buffers[i].len() {
+        // also skip empty buffers, which would otherwise be written forever
+        while i < buffers.len()  &&  wrote >= buffers[i].len() {
             wrote -= buffers[i].len();
             i += 1;
         }
@@ -47,67 +80,656 @@ fn write_all_vectored(to: &mut dyn Write,  buffers: &[IoSlice]) -> Result<(), Io
 }
 
 fn error(what: &str,  path: &[u8],  e: IoError,  exit_code: i32) -> ! {
+    if exit_code == 4  &&  e.kind() == ErrorKind::BrokenPipe  &&  pager::PAGED.load(AtomicOrdering::Relaxed) {
+        // the pager was closed before it got everything
+        exit(0);
+    }
     let stderr = stderr();
+    let description = e.to_string();
     let _ = write_all_vectored(&mut stderr.lock(), &[
         IoSlice::new(what.as_bytes()),
         IoSlice::new(b" "),
-        IoSlice::new(path),
+        IoSlice::new(&escape_path(path)),
         IoSlice::new(b": "),
-        #[allow(deprecated)]
-        IoSlice::new(e.description().as_bytes()),
+        IoSlice::new(description.as_bytes()),
         IoSlice::new(b"\n"),
     ]);
     exit(exit_code);
 }
 
+/// Exit, but first wait for any pager, so that it isn't left running without its input.
+fn exit(code: i32) -> ! {
+    pager::wait();
+    process::exit(code);
+}
+
+fn warning(parts: &[&[u8]]) {
+    let mut slices = Vec::with_capacity(parts.len()+2);
+    slices.push(IoSlice::new(b"Warning: "));
+    slices.extend(parts.iter().map(|part| IoSlice::new(part) ));
+    slices.push(IoSlice::new(b"\n"));
+    let _ = write_all_vectored(&mut stderr().lock(), &slices);
+}
+
+/// Set from --verbose
+static VERBOSITY: AtomicU8 = AtomicU8::new(0);
+
+/// Print a message to stderr if --verbose was given at least `level` times.
+fn verbose(level: u8,  parts: &[&[u8]]) {
+    if VERBOSITY.load(AtomicOrdering::Relaxed) >= level {
+        let mut slices = parts.iter().map(|part| IoSlice::new(part) ).collect::<Vec<_>>();
+        slices.push(IoSlice::new(b"\n"));
+        let _ = write_all_vectored(&mut stderr().lock(), &slices);
+    }
+}
+
+/// Cleared by --raw-paths
+static ESCAPE_PATHS: AtomicBool = AtomicBool::new(true);
+
+/// Replace control characters in a path or label with \n, \r, \t or \xNN,
+/// so that a newline in it can't look like the end of a header, and it can't change the terminal.
+fn escape_path(path: &[u8]) -> Cow<'_, [u8]> {
+    if !ESCAPE_PATHS.load(AtomicOrdering::Relaxed)  ||  !path.iter().any(u8::is_ascii_control) {
+        return Cow::Borrowed(path);
+    }
+    let mut escaped = Vec::with_capacity(path.len()+8);
+    for &b in path {
+        match b {
+            b'\n' => escaped.extend_from_slice(b"\\n"),
+            b'\r' => escaped.extend_from_slice(b"\\r"),
+            b'\t' => escaped.extend_from_slice(b"\\t"),
+            _ if b.is_ascii_control() => escaped.extend_from_slice(format!("\\x{:02x}", b).as_bytes()),
+            _ => escaped.push(b),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// Something that identifies a file independently of which path it was opened through.
+#[cfg(unix)]
+fn file_identity(path: &Path) -> Option<(u64, u64)> {
+    fs::metadata(path).ok().map(|metadata| (metadata.dev(), metadata.ino()) )
+}
+#[cfg(not(unix))]
+fn file_identity(path: &Path) -> Option<std::path::PathBuf> {
+    fs::canonicalize(path).ok()
+}
+
+/// Create "label (path) (listed in list position)" for error messages.
+fn describe(path: &[u8],  label: Option<&[u8]>,  listed_in: Option<(&[u8], &str)>) -> Vec<u8> {
+    let mut described = Vec::new();
+    if let Some(label) = label {
+        described.extend_from_slice(label);
+        described.extend_from_slice(b" (");
+        described.extend_from_slice(path);
+        described.push(b')');
+    } else {
+        described.extend_from_slice(path);
+    }
+    if let Some((list, position)) = listed_in {
+        described.extend_from_slice(b" (listed in ");
+        described.extend_from_slice(list);
+        described.push(b' ');
+        described.extend_from_slice(position.as_bytes());
+        described.push(b')');
+    }
+    escape_path(&described).into_owned()
+}
+
+/// What kind of file something is, if it is a kind that requires --allow-special.
+#[cfg(unix)]
+fn special_kind(file_type: fs::FileType) -> Option<&'static str> {
+    use std::os::unix::fs::FileTypeExt;
+    if file_type.is_char_device() {
+        Some("a character device")
+    } else if file_type.is_block_device() {
+        Some("a block device")
+    } else if file_type.is_socket() {
+        Some("a socket")
+    } else {
+        None
+    }
+}
+#[cfg(not(unix))]
+fn special_kind(_: fs::FileType) -> Option<&'static str> {
+    None
+}
+
+#[cfg(unix)]
+fn is_fifo(file_type: fs::FileType) -> bool {
+    std::os::unix::fs::FileTypeExt::is_fifo(&file_type)
+}
+#[cfg(not(unix))]
+fn is_fifo(_: fs::FileType) -> bool {
+    false
+}
+
+/// Open a file and get its type, or exit with an error message.
+/// Sockets cannot be opened, so for them only the type is returned.
+fn open(arg: &OsStr,  described: &[u8]) -> (Option<File>, fs::FileType) {
+    match File::open(Path::new(arg)) {
+        Ok(file) => match file.metadata() {
+            Ok(metadata) => (Some(file), metadata.file_type()),
+            Err(e) => error("Cannot get the type of", described, e, 2),
+        },
+        Err(e) => match fs::metadata(arg) {
+            Ok(metadata) if special_kind(metadata.file_type()).is_some() => (None, metadata.file_type()),
+            _ => error("Cannot open", described, e, 2),
+        },
+    }
+}
+
+/// Get the files in a directory sorted by name, and also subdirectories if `recursive`.
+/// `relative` is the path of the directory relative to the argument it was found through.
+fn list_directory(dir: &Path,  relative: &Path,  recursive: bool,  filters: &glob::Filters)
+-> Vec<Input> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => error("Cannot list", &os_as_bytes(dir.as_os_str()), e, 2),
+    };
+    let mut paths = entries.filter_map(|entry| match entry {
+            Ok(entry) => Some(entry.path()),
+            Err(e) => error("Cannot list", &os_as_bytes(dir.as_os_str()), e, 2),
+        })
+        .filter(|path| match fs::metadata(path) {
+            Ok(metadata) => metadata.is_file()  ||  (recursive && metadata.is_dir()),
+            Err(_) => true, // let opening it report the error
+        })
+        .collect::<Vec<PathBuf>>();
+    paths.sort_by(|a, b| os_as_bytes(a.as_os_str()).cmp(&os_as_bytes(b.as_os_str())) );
+    paths.into_iter()
+        .map(|path| {
+            let relative = relative.join(path.file_name().unwrap());
+            let location = Location::Path(path.into_os_string());
+            Input {
+                    location,  label: None,  relative: Some(relative),  priority: 0,
+                    time_format: None,  time_zone: None,  format: InputFormat::Text,
+                    encoding: Encoding::Utf8,  skip_lines: None,  grep: None,  invert_grep: None,
+            }
+        })
+        .filter(|input| filters.allows(input.relative.as_ref().unwrap()) )
+        .collect()
+}
+
 struct Source {
+    /// what to show in headers: the label if there is one, otherwise the path
     path: Box<[u8]>,
-    file: File,
+    /// the path and any label, for error messages
+    described: Box<[u8]>,
+    /// for --heading-format: the path even if there is a label
+    file_path: Box<[u8]>,
+    /// for --color: the escape sequence that starts its color, or empty
+    color: Box<[u8]>,
+    /// for --line-numbers: how many digits to pad line numbers to
+    number_width: usize,
+    /// from the metadata of files, for --max-bytes
+    size: Option<u64>,
+    /// a File, or standard input
+    file: Box<dyn Read>,
     buffer: Box<[u8]>,
     read: usize,
+    /// from --priority
+    priority: i64,
+    /// when the file was last modified, in nanoseconds since 1970, for guessing the year of syslog timestamps
+    modified: i64,
+    /// from --file-time-format or --time-format
+    time_format: TimeFormat,
+    /// for --time-format=syslog
+    year: Cell<YearGuess>,
+    /// from --file-tz or --tz
+    time_zone: Option<Rc<Zone>>,
+    /// what lines end with, from --record-separator or -z
+    terminator: Box<[u8]>,
+    /// --paragraph, for which a blank line is added at the end of the file if there isn't one
+    paragraphs: bool,
+    /// --record-size, for which lines aren't found by the terminator, which is empty
+    record_size: Option<usize>,
+    /// from --file-skip-lines or --skip-lines
+    skip_lines: u64,
+    /// from --file-grep, or None to use --grep
+    grep: Option<Rc<Vec<Regex>>>,
+    /// from --file-invert-grep, or None to use --invert-grep
+    invert_grep: Option<Rc<Vec<Regex>>>,
+    /// --max-line-length, or None if unlimited
+    max_line_length: Option<usize>,
+    /// --truncate-long-lines
+    truncate_long_lines: bool,
+    /// where a line that is longer than max_line_length starts in buffer, for read_more() to handle
+    long_line_at: Cell<Option<usize>>,
+    /// how many bytes at the end of buffer[..read] were added because the file didn't end with a terminator
+    added: usize,
+    /// the position of buffer[0] in the file, for error messages,
+    /// which is only approximate after a line has been truncated
+    offset: i64,
+    /// lines that have been found but not offered to the merge yet, in file order
+    pending: RefCell<Vec<Pending>>,
+    /// where to look for the next line
+    scanned: Cell<usize>,
+    /// everything has been read
+    eof: Cell<bool>,
+    /// the number of lines found so far, including skipped ones
+    lines: Cell<u64>,
+    /// the number of lines printed
+    printed: Cell<u64>,
+    /// for --stats: the number of bytes printed, as they were in the file
+    printed_bytes: Cell<u64>,
+    /// for --stats: how many times the merge switched to this file
+    groups: Cell<u64>,
+    /// for --memory-report: the size before and after each time the buffer grew,
+    /// and the position in the file it had read to
+    growth: Vec<(usize, usize, i64)>,
+    /// for --memory-report: how many times more than half of the buffer was kept when reading more
+    long_shifts: u64,
+    /// for --time: how many bytes have been read from the file
+    bytes_read: u64,
+    /// for --by-time: the key of the last line found, which lines without a timestamp get the time of
+    last_key: RefCell<Option<Key>>,
+    /// for --unsorted: a copy of the last line merged from this source, and its line number
+    previous: RefCell<Option<(Vec<u8>, Key, u64)>>,
+    /// for --unsorted: the line numbers of the first line that was merged after a larger line,
+    /// and of that line
+    unsorted_at: Cell<Option<(u64, u64)>>,
+}
+
+/// A line in Source.buffer that has been found.
+struct Pending {
+    starts_at: usize,
+    length: usize,
+    key: Key,
+    /// how many lines after it have been merged before it, for --window
+    passed: usize,
+    /// the line number in the file
+    number: u64,
+}
+
+/// Find where the first terminator in `buffer` starts.
+fn find_terminator(buffer: &[u8],  terminator: &[u8]) -> Option<usize> {
+    match terminator {
+        [byte] => buffer.iter().position(|b| b == byte ),
+        _ => buffer.windows(terminator.len()).position(|window| window == terminator ),
+    }
 }
+
+/// Count the terminators in `buffer`, which ends with one or is the start of a line.
+fn count_terminators(mut buffer: &[u8],  terminator: &[u8]) -> usize {
+    let mut count = 0;
+    while let Some(found) = find_terminator(buffer, terminator) {
+        buffer = &buffer[found+terminator.len()..];
+        count += 1;
+    }
+    count
+}
+
+/// Find the first complete line in `buffer[from..]` that isn't skipped,
+/// or return where the incomplete part starts if there is none.
+/// With --join-continuation or --paragraph the line includes the lines joined to it,
+/// so it isn't complete until the next line that isn't has been read, or `eof`.
+fn next_line(buffer: &[u8],  mut from: usize,  blank_lines: BlankLines,  terminator: &[u8],
+        records: &Records,  eof: bool,
+) -> Result<(usize, usize), usize> {
+    if let &Records::Fixed(size) = records {
+        return if buffer.len() - from >= size {Ok((from, size))} else {Err(from)};
+    }
+    let find = match records {
+        Records::Csv => find_unquoted_terminator,
+        _ => find_terminator,
+    };
+    while let Some(found) = find(&buffer[from..], terminator) {
+        let mut line_len = found + terminator.len();
+        if blank_lines.skips(&buffer[from..from+line_len], terminator) {
+            from += line_len;
+            continue;
+        } else if let Records::Lines | Records::Csv = records {
+            return Ok((from, line_len));
+        }
+        // blank lines at the start of a file are a paragraph of their own
+        let mut blank = line_content(&buffer[from..from+line_len], terminator).is_empty();
+        loop {
+            let rest = &buffer[from+line_len..];
+            let next = match find_terminator(rest, terminator) {
+                Some(found) => &rest[..found+terminator.len()],
+                None if eof => break,
+                None => return Err(from),
+            };
+            let content = line_content(next, terminator);
+            let joined = match records {
+                Records::Continuation(regex) => regex.is_match(content),
+                // paragraphs end with all the blank lines after them
+                _ => !blank  ||  content.is_empty(),
+            };
+            if !joined {
+                break;
+            }
+            blank = content.is_empty();
+            line_len += next.len();
+        }
+        return Ok((from, line_len));
+    }
+    Err(from)
+}
+
+/// For --csv: find where the first terminator in `buffer` that isn't inside double quotes starts.
+/// Doubled quotes inside quotes need no special handling, as they end and restart the quoting.
+fn find_unquoted_terminator(buffer: &[u8],  terminator: &[u8]) -> Option<usize> {
+    let mut quoted = false;
+    for (i, &b) in buffer.iter().enumerate() {
+        if b == b'"' {
+            quoted = !quoted;
+        } else if !quoted  &&  buffer[i..].starts_with(terminator) {
+            return Some(i);
+        }
+    }
+    None
+}
+
+/// For --paragraph: whether the last line in `buffer`, which ends with a terminator, is blank.
+fn ends_with_blank_line(buffer: &[u8],  terminator: &[u8]) -> bool {
+    let without = &buffer[..buffer.len()-terminator.len()];
+    let last_starts = match without.windows(terminator.len()).rposition(|window| window == terminator ) {
+        Some(previous) => previous + terminator.len(),
+        None => 0,
+    };
+    line_content(&buffer[last_starts..], terminator).is_empty()
+}
+
+/// For --escape-headings: whether a line could be mistaken for a header, or is one that has been escaped,
+/// because it starts with `marker` after zero or more backslashes.
+fn looks_like_heading(line: &[u8],  marker: &[u8]) -> bool {
+    let unslashed = line.iter().position(|&b| b != b'\\' ).unwrap_or(line.len());
+    !marker.is_empty()  &&  line[unslashed..].starts_with(marker)
+}
+
+/// For --output-format=tsv: whether `text` has anything that escape_tsv() would change.
+fn needs_tsv_escape(text: &[u8]) -> bool {
+    text.iter().any(|&b| matches!(b, b'\t' | b'\n' | b'\r' | b'\\') )
+}
+
+/// For --output-format=tsv: append `text` with tabs, newlines, carriage returns and backslashes escaped.
+fn escape_tsv(text: &[u8],  out: &mut Vec<u8>) {
+    for &b in text {
+        match b {
+            b'\t' => out.extend_from_slice(b"\\t"),
+            b'\n' => out.extend_from_slice(b"\\n"),
+            b'\r' => out.extend_from_slice(b"\\r"),
+            b'\\' => out.extend_from_slice(b"\\\\"),
+            _ => out.push(b),
+        }
+    }
+}
+
+/// What --truncate-long-lines puts after the part of a line that is kept.
+const TRUNCATED: &[u8] = b"[truncated]";
+
 impl Source {
-    /// Returns None on EOF and the length of the next line otherwise.
-    pub fn read_next_line(&mut self,  next_line_begins: usize) -> Option<usize> {
-        self.buffer.copy_within(next_line_begins..self.read, 0);
-        self.read -= next_line_begins;
+    /// Get the key of a line that comes after the previous line found.
+    fn key(&self,  comparator: &Comparator,  by_time: bool,  line: &[u8]) -> Key {
+        let mut key = comparator.key(line, &self.time_format);
+        if comparator.strict_time  &&  key.missing_time() {
+            let line = line_content(line, &self.terminator);
+            let _ = write_all_vectored(&mut stderr().lock(), &[
+                    IoSlice::new(b"Error: "),
+                    IoSlice::new(&self.described),
+                    IoSlice::new(b": no timestamp in "),
+                    IoSlice::new(line),
+                    IoSlice::new(b"\n"),
+            ]);
+            exit(6);
+        }
+        if self.time_format.yearless() {
+            let mut year = self.year.get();
+            key.map_times(|time| {
+                time.nanoseconds = year.apply(time.nanoseconds, comparator.assume_year, self.modified);
+            });
+            self.year.set(year);
+        }
+        if let Some(zone) = &self.time_zone {
+            key.map_times(|time| if time.naive {
+                time.nanoseconds = zone.to_utc(time.nanoseconds);
+            });
+        }
+        if by_time {
+            let mut last_key = self.last_key.borrow_mut();
+            if let Some(last_key) = &*last_key {
+                key.inherit_time(last_key);
+            }
+            *last_key = Some(key.clone());
+        }
+        key
+    }
+
+    /// For --heading-range when not merging by time: the time of the timestamp the line starts with.
+    fn line_time(&self,  comparator: &Comparator,  line: &[u8]) -> Option<i64> {
+        let time = self.time_format.parse(line)?;
+        let mut nanoseconds = time.nanoseconds;
+        if self.time_format.yearless() {
+            let mut year = self.year.get();
+            nanoseconds = year.apply(nanoseconds, comparator.assume_year, self.modified);
+            self.year.set(year);
+        }
+        match &self.time_zone {
+            Some(zone) if time.naive => Some(zone.to_utc(nanoseconds)),
+            _ => Some(nanoseconds),
+        }
+    }
+
+    /// Count the lines in `buffer`, which is complete lines.
+    fn count_lines(&self,  buffer: &[u8]) -> usize {
+        match self.record_size {
+            Some(size) => buffer.len() / size,
+            None => count_terminators(buffer, &self.terminator),
+        }
+    }
+
+    /// Describe the first unsorted line found by --unsorted, if any.
+    fn describe_unsorted(&self) -> Option<Vec<u8>> {
+        let (number, previous_number) = self.unsorted_at.get()?;
+        let mut description = self.described.to_vec();
+        description.extend_from_slice(format!(
+                " is not sorted: line {} should come before line {}", number, previous_number
+        ).as_bytes());
+        Some(description)
+    }
+
+    /// Find the next line, reading more if necessary, and return its start and length,
+    /// or None at end of file. The previous line is no longer in the buffer after this.
+    pub fn read_next_line(&mut self,  blank_lines: BlankLines,  records: &Records) -> Option<(usize, usize)> {
         loop {
-            match self.file.read(&mut self.buffer[self.read..]) {
+            let buffer = &self.buffer[..self.read];
+            match next_line(buffer, self.scanned.get(), blank_lines, &self.terminator, records, self.eof.get()) {
+                Ok((starts_at, length)) => match self.too_long(starts_at, length) {
+                    Some(long_line_at) => {
+                        self.scanned.set(starts_at);
+                        self.long_line(self.max_line_length.unwrap(), long_line_at);
+                    }
+                    None => {
+                        self.scanned.set(starts_at+length);
+                        return Some((starts_at, length));
+                    }
+                },
+                Err(incomplete) if self.eof.get() => {
+                    // for check() to find unclosed quotes with
+                    self.scanned.set(incomplete);
+                    return None;
+                }
+                Err(incomplete) => self.read_more(incomplete),
+            }
+        }
+    }
+
+    /// Move what hasn't been consumed, starting at `keep_from`, to the start of the buffer,
+    /// and then read until a line terminator has been read or end of file is reached.
+    fn read_more(&mut self,  keep_from: usize) {
+        if self.read - keep_from > self.buffer.len() / 2 {
+            self.long_shifts += 1;
+        }
+        self.buffer.copy_within(keep_from..self.read, 0);
+        self.offset += keep_from as i64;
+        self.read -= keep_from;
+        self.scanned.set(self.scanned.get() - keep_from);
+        for pending in self.pending.get_mut() {
+            pending.starts_at -= keep_from;
+        }
+        // a line that next_line() found to be too long
+        if let (Some(starts), Some(max)) = (self.long_line_at.take(), self.max_line_length) {
+            self.long_line(max, starts - keep_from);
+            return;
+        }
+        loop {
+            // records of --record-size have no lines to measure
+            let lines = self.record_size.is_none()  &&  !self.terminator.is_empty();
+            if let (Some(max), true) = (self.max_line_length, lines) {
+                // the last line of a record that isn't complete yet
+                let unterminated = &self.buffer[self.scanned.get()..self.read];
+                let line_starts = self.scanned.get() + unterminated.windows(self.terminator.len())
+                        .rposition(|window| window == &self.terminator[..] )
+                        .map_or(0, |found| found + self.terminator.len() );
+                if self.read - line_starts > max {
+                    self.long_line(max, line_starts);
+                    return;
+                }
+            }
+            // also grow before reading, as what is kept can be several lines
+            // when --window or --join-continuation needs more of the file
+            if self.buffer.len() - self.read < self.buffer.len() / 4 {
+                let mut new = Vec::with_capacity(self.buffer.len()*2);
+                new.extend_from_slice(&self.buffer[..self.read]);
+                new.resize(self.buffer.len()*2, 0);
+                self.growth.push((self.buffer.len(), new.len(), self.offset + self.read as i64));
+                self.buffer = new.into_boxed_slice();
+                let size = self.buffer.len().to_string();
+                verbose(1, &[&self.path, b": grew buffer to ", size.as_bytes(), b" bytes"]);
+            }
+            let read = self.file.read(&mut self.buffer[self.read..]);
+            if let Ok(new_bytes) = read {
+                self.bytes_read += new_bytes as u64;
+            }
+            match read {
+                Ok(new_bytes @ 1..=usize::MAX) if self.record_size.is_some() => {
+                    // let the caller check whether a whole record has been read
+                    self.read += new_bytes;
+                    return;
+                }
                 Ok(new_bytes @ 1..=usize::MAX) => {
-                    let no_newline = self.read;
+                    // back up in case the terminator started in what was read before
+                    let no_terminator = self.read.saturating_sub(self.terminator.len()-1);
                     self.read += new_bytes;
-                    let new_part = &self.buffer[no_newline..self.read];
-                    if let Some(found) = new_part.iter().position(|&b| b == b'\n' ) {
-                        //let _ = stdout().write_all(&self.buffer[..no_newline+found+1]);
-                        return Some(no_newline+found+1);
-                    } else if self.buffer.len() - self.read < self.buffer.len() / 4 {
-                        let mut new = Vec::with_capacity(self.buffer.len()*2);
-                        new.extend_from_slice(&self.buffer[..self.read]);
-                        new.truncate(self.buffer.len()*2);
-                        self.buffer = new.into_boxed_slice();
+                    let new_part = &self.buffer[no_terminator..self.read];
+                    if find_terminator(new_part, &self.terminator).is_some() {
+                        return;
                     }
                     // continue
                 }
-                Ok(0) if self.read == 0 => {// EOF reached after a newline
-                    return None;
+                Ok(0) if self.record_size.is_some_and(|size| !self.read.is_multiple_of(size) ) => {
+                    let left_over = format!("{} bytes are left over", self.read % self.record_size.unwrap());
+                    error("Incomplete record at the end of", &self.described, IoError::new(ErrorKind::InvalidData, left_over), 3);
                 }
-                Ok(0) => {// no newline at end of file; add one
-                    if self.read < self.buffer.len() {
-                        self.buffer[self.read] = b'\n';
-                    } else {
-                        let mut new = Vec::with_capacity(self.buffer.len()+1);
-                        new.extend_from_slice(&self.buffer);
-                        new.push(b'\n');
-                        self.buffer = new.into_boxed_slice();
+                Ok(0) if self.read == 0 => {// EOF reached after everything has been consumed
+                    self.eof.set(true);
+                    return;
+                }
+                Ok(0) if self.buffer[..self.read].ends_with(&self.terminator) => {// EOF reached after a terminator
+                    if !self.paragraphs  ||  ends_with_blank_line(&self.buffer[..self.read], &self.terminator) {
+                        self.eof.set(true);
+                        return;
                     }
-                    self.read += 1;
-                    return Some(self.read);
+                    // end the last paragraph with a blank line; the next read will return 0 again
+                    self.append(&self.terminator.clone());
+                    self.added += self.terminator.len();
+                    return;
+                }
+                Ok(0) => {// no terminator at end of file; add one
+                    self.append(&self.terminator.clone());
+                    self.added += self.terminator.len();
+                    return;
                 }
-                Err(e) => error("Error reading from", &self.path, e, 3),
+                Err(e) => error("Error reading from", &self.described, e, 3),
                 Ok(negative) => unreachable!("usize value not in 0..=usize::MAX: {}", negative),
             }
         }
     }
+
+    /// For --csv: exit with an error about the line at end of file that has a quote which isn't closed.
+    fn unclosed_quote(&self,  line_number: u64) -> ! {
+        let message = format!("the quote in the record starting at line {} is never closed", line_number);
+        error("Cannot merge", &self.described, IoError::new(ErrorKind::InvalidData, message), 3);
+    }
+
+    /// Add bytes that aren't in the file after what has been read, growing the buffer if necessary.
+    fn append(&mut self,  bytes: &[u8]) {
+        let length = bytes.len();
+        if self.read+length <= self.buffer.len() {
+            self.buffer[self.read..self.read+length].copy_from_slice(bytes);
+        } else {
+            let mut new = Vec::with_capacity(self.read+length);
+            new.extend_from_slice(&self.buffer[..self.read]);
+            new.extend_from_slice(bytes);
+            self.buffer = new.into_boxed_slice();
+        }
+        self.read += length;
+    }
+
+    /// For --max-line-length: where the first line in buffer[starts_at..starts_at+length] that is too long
+    /// starts, ignoring lines that have already been truncated.
+    fn too_long(&self,  starts_at: usize,  length: usize) -> Option<usize> {
+        let max = self.max_line_length.filter(|&max| length > max  &&  self.record_size.is_none() )?;
+        // records can be several lines
+        let ends = starts_at + length;
+        let mut line_starts = starts_at;
+        while line_starts < ends {
+            let rest = &self.buffer[line_starts..ends];
+            let line = &rest[..find_terminator(rest, &self.terminator).unwrap_or(rest.len())];
+            let truncated = line.len() == max + TRUNCATED.len()  &&  line.ends_with(TRUNCATED);
+            if line.len() > max  &&  !truncated {
+                return Some(line_starts);
+            }
+            line_starts += line.len() + self.terminator.len();
+        }
+        None
+    }
+
+    /// Handle a line longer than --max-line-length, which starts at `starts`:
+    /// Exit with an error, or with --truncate-long-lines keep the first `max` bytes of it
+    /// and read past the rest without keeping it.
+    fn long_line(&mut self,  max: usize,  starts: usize) {
+        if !self.truncate_long_lines {
+            let message = format!(
+                    "the line at offset {} is longer than {} bytes (see --max-line-length and --truncate-long-lines)",
+                    self.offset + starts as i64, max
+            );
+            error("Cannot merge", &self.described, IoError::new(ErrorKind::InvalidData, message), 3);
+        }
+        let cut = starts + max;
+        let mut rest = self.buffer[cut..self.read].to_vec();
+        self.read = cut;
+        let mut skipped = 0;
+        let after = loop {
+            if let Some(found) = find_terminator(&rest, &self.terminator) {
+                skipped += found;
+                break rest.split_off(found);
+            }
+            // keep what a terminator could start in
+            let keep = rest.len().min(self.terminator.len()-1);
+            skipped += rest.len() - keep;
+            rest.drain(..rest.len()-keep);
+            let mut chunk = [0; 64*1024];
+            match self.file.read(&mut chunk) {
+                Ok(0) => {
+                    skipped += rest.len();
+                    break Vec::new();
+                }
+                Ok(new_bytes) => {
+                    self.bytes_read += new_bytes as u64;
+                    rest.extend_from_slice(&chunk[..new_bytes]);
+                }
+                Err(e) => error("Error reading from", &self.described, e, 3),
+            }
+        };
+        self.append(TRUNCATED);
+        self.append(&after);
+        self.offset += skipped as i64 - TRUNCATED.len() as i64;
+        let length = (max + skipped).to_string();
+        verbose(1, &[&self.path, b": truncated a line of ", length.as_bytes(), b" bytes"]);
+    }
 }
 #[cfg(any(debug_assertions, feature="debug"))]
 impl Debug for Source {
@@ -120,6 +742,608 @@ impl Debug for Source {
     }
 }
 
+/// For --basename-headers: show only as many of the last components of the path as are needed
+/// to tell it apart from the other files, for files without a label.
+fn basename_headers(sources: &mut [RefCell<Source>]) {
+    // the components of each path from the end, or None if it has a label
+    let components = sources.iter_mut().map(|source| {
+        let source = source.get_mut();
+        if source.path != source.file_path {
+            return None;
+        }
+        let components = source.file_path.split(|&b| b == b'/' ).filter(|component| !component.is_empty() );
+        Some(components.rev().map(<[u8]>::to_vec).collect::<Vec<_>>())
+    }).collect::<Vec<_>>();
+    for (i, source) in sources.iter_mut().enumerate() {
+        let own = match &components[i] {
+            Some(own) => own,
+            None => continue,
+        };
+        let shared = components.iter().enumerate()
+                .filter_map(|(j, other)| if j == i {None} else {other.as_ref()} )
+                .map(|other| own.iter().zip(other).take_while(|(a, b)| a == b ).count() )
+                .max()
+                .unwrap_or(0);
+        // keep the full path if another file has the same or ends with all of it
+        if shared < own.len() {
+            let shortest = own[..=shared].iter().rev().map(Vec::as_slice).collect::<Vec<_>>();
+            source.get_mut().path = shortest.join(&b'/').into_boxed_slice();
+        }
+    }
+}
+
+/// What --prefix-lines starts the lines of each file with,
+/// truncated to --max-prefix-width and padded to the longest with --align-prefixes.
+fn prefixes(sources: &[RefCell<Source>],  align: bool,  max_width: Option<usize>) -> Vec<Box<[u8]>> {
+    let mut prefixes = sources.iter()
+            .map(|source| {
+                let path = &source.borrow().path;
+                max_width.map_or_else(|| path.to_vec(), |max_width| width::truncate(path, max_width) )
+            })
+            .collect::<Vec<_>>();
+    if align {
+        let widths = prefixes.iter().map(|prefix| width::width(prefix) ).collect::<Vec<_>>();
+        let widest = widths.iter().copied().max().unwrap_or(0);
+        for (prefix, width) in prefixes.iter_mut().zip(widths) {
+            prefix.resize(prefix.len() + widest - width, b' ');
+        }
+    }
+    prefixes.into_iter().map(Vec::into_boxed_slice).collect()
+}
+
+/// For --min-group: a group that hasn't reached the minimum size yet.
+struct Held {
+    /// where the header ends in Output.group
+    header_ends: usize,
+    /// what to start its lines with if it doesn't get a header
+    prefix: Vec<u8>,
+    /// where each line ends in Output.group
+    line_ends: Vec<usize>,
+}
+
+/// Where the merged lines are written, which for --heading-range keeps each group until it ends
+/// so that the times of its first and last line can be put in its header,
+/// for --max-bytes keeps each line until it is known to fit,
+/// and for --min-group keeps the first lines of each group until it is known to be big enough.
+struct Output {
+    to: Box<dyn Write>,
+    /// --heading-range
+    grouped: bool,
+    /// what has been written of the current group, or of the current line with --max-bytes
+    group: Vec<u8>,
+    /// where to put the times in group
+    header_ends: Option<usize>,
+    /// of the first and last line in the group that has a timestamp
+    times: Option<(i64, i64)>,
+    /// --rewrite-time or ISO 8601
+    time_format: TimeFormat,
+    /// --max-bytes
+    limit: Option<u64>,
+    /// how much has been written to `to`, for --max-bytes and --time
+    written: u64,
+    /// for --max-bytes: where the current line starts in group, and header_ends and times before it
+    line_starts: (usize, Option<usize>, Option<(i64, i64)>),
+    /// --min-group
+    min_group: usize,
+    /// for --min-group: the current group if it's still too small
+    held: Option<Held>,
+}
+impl Output {
+    fn buffered(&self) -> bool {
+        self.grouped  ||  self.limit.is_some()  ||  self.held.is_some()
+    }
+
+    /// For --min-group: start a group, before its header is written.
+    fn hold(&mut self,  prefix: Vec<u8>) {
+        self.held = Some(Held { header_ends: 0,  prefix,  line_ends: Vec::new() });
+    }
+
+    /// For --min-group: remember where the header ends.
+    fn header_held(&mut self) {
+        if let Some(held) = &mut self.held {
+            held.header_ends = self.group.len();
+        }
+    }
+
+    /// For --min-group: write the group with its header once it is big enough.
+    fn line_done(&mut self) -> Result<(), IoError> {
+        if let Some(held) = &mut self.held {
+            held.line_ends.push(self.group.len());
+            if held.line_ends.len() >= self.min_group {
+                self.held = None;
+                self.to.write_all(&self.group)?;
+                self.written += self.group.len() as u64;
+                self.group.clear();
+            }
+        }
+        Ok(())
+    }
+
+    /// What --heading-range adds to the header of the current group.
+    fn range(&self) -> Vec<u8> {
+        let mut range = Vec::new();
+        if let (Some((first, last)), Some(_)) = (self.times, self.header_ends) {
+            range.extend_from_slice(b" [");
+            self.time_format.format(first, &mut range);
+            range.extend_from_slice(b" - ");
+            self.time_format.format(last, &mut range);
+            range.push(b']');
+        }
+        range
+    }
+
+    /// For --max-bytes: remove the line (and any header before it) written since the last call
+    /// and return false if it would make the output longer than the limit.
+    fn line_fits(&mut self) -> Result<bool, IoError> {
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None => return Ok(true),
+        };
+        if self.written + self.group.len() as u64 + self.range().len() as u64 > limit {
+            (_, self.header_ends, self.times) = self.line_starts;
+            self.group.truncate(self.line_starts.0);
+            return Ok(false);
+        }
+        if !self.grouped {
+            self.to.write_all(&self.group)?;
+            self.written += self.group.len() as u64;
+            self.group.clear();
+        }
+        self.line_starts = (self.group.len(), self.header_ends, self.times);
+        Ok(true)
+    }
+
+    /// Start a group, after its header has been written.
+    /// `after` is how many bytes of the header should be after the times.
+    fn header_written(&mut self,  after: usize) {
+        self.header_ends = Some(self.group.len() - after);
+    }
+
+    fn line_time(&mut self,  time: i64) {
+        self.times = Some(match self.times {
+            Some((first, _)) => (first, time),
+            None => (time, time),
+        });
+    }
+
+    /// Write the current group with the times in its header.
+    fn end_group(&mut self) -> Result<(), IoError> {
+        if let Some(Held { header_ends, prefix, line_ends }) = self.held.take() {
+            // too small for a header, so start each line with the path instead
+            let mut slices = Vec::with_capacity(line_ends.len()*2+1);
+            let mut starts = header_ends;
+            for ends in line_ends {
+                slices.push(IoSlice::new(&prefix));
+                slices.push(IoSlice::new(&self.group[starts..ends]));
+                starts = ends;
+            }
+            // any --eof-markers
+            slices.push(IoSlice::new(&self.group[starts..]));
+            write_all_vectored(&mut self.to, &slices)?;
+            self.written += slices.iter().map(|slice| slice.len() as u64 ).sum::<u64>();
+            self.group.clear();
+            return Ok(());
+        }
+        let range = self.range();
+        let (header, lines) = self.group.split_at(self.header_ends.unwrap_or(0));
+        write_all_vectored(&mut self.to, &[IoSlice::new(header), IoSlice::new(&range), IoSlice::new(lines)])?;
+        self.written += (self.group.len() + range.len()) as u64;
+        self.group.clear();
+        self.header_ends = None;
+        self.times = None;
+        self.line_starts = (0, None, None);
+        Ok(())
+    }
+}
+impl Write for Output {
+    fn write(&mut self,  buf: &[u8]) -> Result<usize, IoError> {
+        if !self.buffered() {
+            let wrote = self.to.write(buf)?;
+            self.written += wrote as u64;
+            return Ok(wrote);
+        }
+        self.group.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn write_vectored(&mut self,  bufs: &[IoSlice]) -> Result<usize, IoError> {
+        if !self.buffered() {
+            let wrote = self.to.write_vectored(bufs)?;
+            self.written += wrote as u64;
+            return Ok(wrote);
+        }
+        for buf in bufs {
+            self.group.extend_from_slice(buf);
+        }
+        Ok(bufs.iter().map(|buf| buf.len() ).sum())
+    }
+
+    /// Also writes the current group, so only do this when exiting.
+    fn flush(&mut self) -> Result<(), IoError> {
+        self.line_fits()?;
+        self.end_group()?;
+        self.to.flush()
+    }
+}
+
+/// For --heading-range: how big a group can get before it is written with the times so far,
+/// and the rest of it gets a new header.
+const MAX_GROUP: usize = 16*1024*1024;
+
+/// For --max-bytes: tell where in each file the output stopped.
+/// `stopped_at` is the index of the sources that have lines left and the position of the first of those.
+fn unread_note(sources: &[Ref<Source>],  mut stopped_at: Vec<(usize, u64)>) {
+    stopped_at.sort_unstable();
+    let mut note = b"Reached --max-bytes with lines left in:\n".to_vec();
+    for (source_index, position) in stopped_at {
+        let source = &sources[source_index];
+        note.extend_from_slice(&source.path);
+        note.extend_from_slice(format!(": from byte {}", position).as_bytes());
+        if let Some(size) = source.size {
+            note.extend_from_slice(format!(" of {}", size).as_bytes());
+        }
+        note.push(b'\n');
+    }
+    let _ = stderr().lock().write_all(&note);
+}
+
+/// For --stats: buffers bigger than this are reported.
+const LARGE_BUFFER: usize = 1024*1024;
+
+/// For --stats: print a table with what each file contributed on stderr.
+fn print_stats(sources: &[RefCell<Source>],  started: Instant) {
+    let mut rows = vec![[&b"file"[..], b"lines", b"bytes", b"groups", b"large buffer"].map(<[u8]>::to_vec)];
+    let mut totals = [0u64; 3];
+    for source in sources {
+        let source = source.borrow();
+        let counts = [source.printed.get(), source.printed_bytes.get(), source.groups.get()];
+        for (total, count) in totals.iter_mut().zip(counts) {
+            *total += count;
+        }
+        let large = if source.buffer.len() > LARGE_BUFFER {&b"yes"[..]} else {b"no"};
+        rows.push([
+                source.path.to_vec(),
+                counts[0].to_string().into_bytes(),
+                counts[1].to_string().into_bytes(),
+                counts[2].to_string().into_bytes(),
+                large.to_vec(),
+        ]);
+    }
+    rows.push([
+            b"total".to_vec(),
+            totals[0].to_string().into_bytes(),
+            totals[1].to_string().into_bytes(),
+            totals[2].to_string().into_bytes(),
+            Vec::new(),
+    ]);
+    let widths = (0..5).map(|column| rows.iter().map(|row| width::width(&row[column]) ).max().unwrap_or(0) )
+            .collect::<Vec<_>>();
+    let mut table = Vec::new();
+    for row in &rows {
+        // the file column is aligned to the left, and the numbers to the right
+        table.extend_from_slice(&row[0]);
+        table.resize(table.len() + widths[0] - width::width(&row[0]), b' ');
+        for column in 1..4 {
+            table.resize(table.len() + 2 + widths[column] - row[column].len(), b' ');
+            table.extend_from_slice(&row[column]);
+        }
+        if !row[4].is_empty() {
+            table.extend_from_slice(b"  ");
+            table.extend_from_slice(&row[4]);
+        }
+        table.push(b'\n');
+    }
+    table.extend_from_slice(format!("took {:.3} seconds\n", started.elapsed().as_secs_f64()).as_bytes());
+    let _ = stderr().lock().write_all(&table);
+}
+
+/// For --time: a size in bytes with a binary unit, like 1.5 MiB.
+fn human_size(bytes: f64) -> String {
+    let mut size = bytes;
+    for unit in ["B", "KiB", "MiB", "GiB", "TiB"] {
+        if size < 1024.0  ||  unit == "TiB" {
+            return match unit {
+                "B" => format!("{:.0} {}", size, unit),
+                _ => format!("{:.1} {}", size, unit),
+            };
+        }
+        size /= 1024.0;
+    }
+    unreachable!()
+}
+
+/// For --memory-report: print the buffer sizes of each file on stderr,
+/// and `peak`, the most memory the merge used at once.
+fn print_memory_report(sources: &[RefCell<Source>],  peak: usize) {
+    let mut report = Vec::new();
+    for source in sources {
+        let source = source.borrow();
+        let initial = source.growth.first().map_or(source.buffer.len(), |&(old, _, _)| old );
+        report.extend_from_slice(&source.path);
+        report.extend_from_slice(format!(
+                ": buffer started at {} bytes and ended at {} bytes, more than half of it was kept {} times\n",
+                initial, source.buffer.len(), source.long_shifts
+        ).as_bytes());
+        for &(old, new, position) in &source.growth {
+            report.extend_from_slice(format!(
+                    "  grew from {} to {} bytes after reading {} bytes\n", old, new, position
+            ).as_bytes());
+        }
+    }
+    report.extend_from_slice(format!("peak: about {} bytes for buffers and output\n", peak).as_bytes());
+    let _ = stderr().lock().write_all(&report);
+}
+
+/// For --tee: a file the merged lines are also written to.
+struct TeeFile {
+    path: Vec<u8>,
+    /// None after writing to it failed with best-effort
+    file: Option<File>,
+    best_effort: bool,
+}
+
+/// For --tee: write to the output and then to each file.
+struct Tee {
+    to: Box<dyn Write>,
+    files: Vec<TeeFile>,
+}
+impl Tee {
+    fn copy(&mut self,  buffers: &[IoSlice]) {
+        for tee in &mut self.files {
+            let written = match &mut tee.file {
+                Some(file) => write_all_vectored(file, buffers),
+                None => continue,
+            };
+            match written {
+                Ok(()) => {}
+                Err(e) if tee.best_effort => {
+                    let e = e.to_string();
+                    warning(&[b"Cannot write to ", &escape_path(&tee.path), b": ", e.as_bytes(), b", so stopped writing to it"]);
+                    tee.file = None;
+                }
+                Err(e) => error("Error writing to", &tee.path, e, 4),
+            }
+        }
+    }
+}
+impl Write for Tee {
+    fn write(&mut self,  buf: &[u8]) -> Result<usize, IoError> {
+        self.to.write_all(buf)?;
+        self.copy(&[IoSlice::new(buf)]);
+        Ok(buf.len())
+    }
+
+    fn write_vectored(&mut self,  bufs: &[IoSlice]) -> Result<usize, IoError> {
+        write_all_vectored(&mut self.to, bufs)?;
+        self.copy(bufs);
+        Ok(bufs.iter().map(|buf| buf.len() ).sum())
+    }
+
+    fn flush(&mut self) -> Result<(), IoError> {
+        self.to.flush()?;
+        for tee in &mut self.files {
+            if let Some(file) = &mut tee.file {
+                file.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// For --unsorted=error
+fn unsorted_error(source: &Source) -> ! {
+    let description = source.describe_unsorted().unwrap();
+    let _ = write_all_vectored(&mut stderr().lock(), &[
+            IoSlice::new(b"Error: "),
+            IoSlice::new(&description),
+            IoSlice::new(b"\n"),
+    ]);
+    exit(6);
+}
+
+/// Check that the lines in each source are in order for --check, one source at a time.
+/// Returns false if any is not.
+fn check(sources: Vec<RefCell<Source>>,  comparator: &Comparator,  blank_lines: BlankLines,
+        records: &Records,  quiet: bool,
+) -> bool {
+    let by_time = comparator.by_time();
+    let mut all_sorted = true;
+    for source in sources {
+        let mut source = source.into_inner();
+        let mut sorted = true;
+        // copied because the buffer gets reused
+        let mut previous = None::<(Vec<u8>, Key, usize)>;
+        let mut lines = 0;
+        let mut next = source.read_next_line(BlankLines::Keep, records);
+        while let Some((starts_at, line_length)) = next {
+            let line = &source.buffer[starts_at..starts_at+line_length];
+            // with --join-continuation or --paragraph, lines are numbered by their first line
+            let line_number = lines + 1;
+            lines += source.count_lines(line);
+            if line_number as u64 > source.skip_lines  &&  !blank_lines.skips(line, &source.terminator) {
+                let key = source.key(comparator, by_time, line);
+                if let Some((previous, previous_key, previous_number)) = &previous {
+                    if comparator.compare(previous, previous_key, line, &key) == Ordering::Greater {
+                        sorted = false;
+                        if !quiet {
+                            let (line_number, previous_number) = (line_number.to_string(), previous_number.to_string());
+                            let stderr = stderr();
+                            let _ = write_all_vectored(&mut stderr.lock(), &[
+                                    IoSlice::new(&source.described),
+                                    IoSlice::new(b": line "),
+                                    IoSlice::new(line_number.as_bytes()),
+                                    IoSlice::new(b" should come before line "),
+                                    IoSlice::new(previous_number.as_bytes()),
+                                    IoSlice::new(b":\n"),
+                                    IoSlice::new(previous_number.as_bytes()),
+                                    IoSlice::new(b": "),
+                                    IoSlice::new(line_content(previous, &source.terminator)),
+                                    IoSlice::new(b"\n"),
+                                    IoSlice::new(line_number.as_bytes()),
+                                    IoSlice::new(b": "),
+                                    IoSlice::new(line_content(line, &source.terminator)),
+                                    IoSlice::new(b"\n"),
+                            ]);
+                        }
+                        break;
+                    }
+                }
+                previous = Some((line.to_vec(), key, line_number));
+            }
+            next = source.read_next_line(BlankLines::Keep, records);
+        }
+        // what is left at the end of the file wasn't a line
+        if next.is_none()  &&  source.scanned.get() < source.read {
+            source.unclosed_quote(lines as u64 + 1);
+        }
+        verbose(1, &[&source.described, if sorted {b": is sorted"} else {b": is not sorted"}]);
+        all_sorted &= sorted;
+    }
+    all_sorted
+}
+
+/// State shared by all FirstLines
+struct Shared {
+    /// index of the source the last printed line came from, or sources.len() if none
+    last_source: Cell<usize>,
+    comparator: Comparator,
+    /// --warn-unmatched
+    warn_unmatched: bool,
+    blank_lines: BlankLines,
+    /// --join-continuation or --paragraph
+    records: Records,
+    /// --window: how many lines to choose the next line from
+    window: usize,
+    unsorted: Unsorted,
+    /// comparator.by_time()
+    by_time: bool,
+    /// --since
+    since: Option<i64>,
+    /// --until
+    until: Option<i64>,
+}
+impl Shared {
+    /// Find the next line to merge from a source, which is the smallest of the next `window` lines.
+    /// Returns where to keep the buffer from if more needs to be read first, and None at end of file.
+    fn next_line(&self,  source: &Source) -> Result<Option<Pending>, usize> {
+        let mut pending = source.pending.borrow_mut();
+        // count skipped lines too
+        let count_lines = |until: usize| {
+            let lines = source.count_lines(&source.buffer[source.scanned.get()..until]);
+            source.lines.set(source.lines.get() + lines as u64);
+        };
+        while pending.len() < self.window {
+            let buffer = &source.buffer[..source.read];
+            let found = next_line(buffer, source.scanned.get(), self.blank_lines, &source.terminator,
+                    &self.records, source.eof.get()
+            );
+            match found {
+                Ok((starts_at, length)) if source.too_long(starts_at, length).is_some() => {
+                    // let read_more() truncate it or exit
+                    count_lines(starts_at);
+                    source.scanned.set(starts_at);
+                    source.long_line_at.set(source.too_long(starts_at, length));
+                    return Err(pending.first().map_or(starts_at, |first| first.starts_at ));
+                }
+                Ok((starts_at, length)) => {
+                    // joined lines get the number of the first
+                    count_lines(starts_at);
+                    let number = source.lines.get() + 1;
+                    source.scanned.set(starts_at);
+                    count_lines(starts_at+length);
+                    source.scanned.set(starts_at+length);
+                    if number <= source.skip_lines {
+                        continue;
+                    }
+                    let line = &source.buffer[starts_at..starts_at+length];
+                    let key = source.key(&self.comparator, self.by_time, line);
+                    if self.warn_unmatched  &&  key.unmatched() {
+                        let line = line_content(line, &source.terminator);
+                        let problem = match (&self.comparator.logfmt_key, &self.comparator.json_key) {
+                            (Some(_), _) => &b": --logfmt-key is missing from "[..],
+                            (_, Some(_)) => b": --json-key is missing from ",
+                            _ if self.comparator.csv_key.is_some() => b": --csv-key is missing from ",
+                            _ => b": --key-regex doesn't match ",
+                        };
+                        warning(&[&source.path, problem, line]);
+                    }
+                    pending.push(Pending { starts_at,  length,  key,  passed: 0,  number });
+                }
+                Err(incomplete) if !source.eof.get() => {
+                    count_lines(incomplete);
+                    source.scanned.set(incomplete);
+                    return Err(pending.first().map_or(incomplete, |first| first.starts_at ));
+                }
+                Err(incomplete) if incomplete < source.read => {
+                    count_lines(incomplete);
+                    source.unclosed_quote(source.lines.get() + 1);
+                }
+                Err(_) => break,
+            }
+        }
+        if pending.is_empty() {
+            return Ok(None);
+        }
+        // a line that window-1 later lines have been merged before is merged next even if
+        // it's not the smallest, so that its part of the buffer can be reused
+        let mut chosen = 0;
+        if pending[0].passed+1 < self.window {
+            let line = |pending: &Pending| &source.buffer[pending.starts_at..pending.starts_at+pending.length];
+            for (i, candidate) in pending.iter().enumerate().skip(1) {
+                let smallest = &pending[chosen];
+                let ordering = self.comparator.compare(line(candidate), &candidate.key, line(smallest), &smallest.key);
+                if ordering == Ordering::Less {
+                    chosen = i;
+                }
+            }
+        }
+        for older in &mut pending[..chosen] {
+            older.passed += 1;
+        }
+        let chosen = pending.remove(chosen);
+        if self.unsorted != Unsorted::Allow {
+            self.check_order(source, &chosen);
+        }
+        Ok(Some(chosen))
+    }
+
+    /// For --unsorted: compare the line to the previous line merged from the same source.
+    fn check_order(&self,  source: &Source,  next: &Pending) {
+        let line = &source.buffer[next.starts_at..next.starts_at+next.length];
+        let mut previous = source.previous.borrow_mut();
+        if let Some((previous_line, previous_key, previous_number)) = &*previous {
+            let unsorted = source.unsorted_at.get().is_none()
+                    &&  self.comparator.compare(previous_line, previous_key, line, &next.key) == Ordering::Greater;
+            if unsorted {
+                source.unsorted_at.set(Some((next.number, *previous_number)));
+                if self.unsorted == Unsorted::Warn {
+                    warning(&[&source.describe_unsorted().unwrap()]);
+                }
+            }
+        }
+        match &mut *previous {
+            Some((previous_line, previous_key, previous_number)) => {
+                previous_line.clear();
+                previous_line.extend_from_slice(line);
+                *previous_key = next.key.clone();
+                *previous_number = next.number;
+            }
+            None => *previous = Some((line.to_vec(), next.key.clone(), next.number)),
+        }
+    }
+
+    /// Like next_line(), but reads more from the source when needed.
+    fn read_next_line(&self,  source: &RefCell<Source>) -> Option<Pending> {
+        loop {
+            let next = self.next_line(&source.borrow());
+            match next {
+                Ok(next) => return next,
+                Err(keep_from) => source.borrow_mut().read_more(keep_from),
+            }
+        }
+    }
+}
+
 struct FirstLine<'a> {
     /// borrows a Source.buffer[self.starts_at..Source.read]
     source: Ref<'a, Source>,
@@ -128,16 +1352,25 @@ struct FirstLine<'a> {
     /// offset of self.read in Source.buffer
     starts_at: usize,
     source_index: usize,
-    last_source: &'a Cell<usize>,
+    /// what has been parsed from the line for comparing it
+    key: Key,
+    /// the line number in the file
+    number: u64,
+    shared: &'a Shared,
 }
 impl<'a> FirstLine<'a> {
+    fn new(source: Ref<'a, Source>,  line: Pending,  source_index: usize,  shared: &'a Shared) -> Self {
+        let Pending { starts_at,  length: line_length,  key,  number,  .. } = line;
+        FirstLine { source, line_length, starts_at, source_index, key, number, shared }
+    }
+
     fn line(&self) -> &[u8] {
         &self.source.buffer[self.starts_at..self.starts_at+self.line_length]
     }
 }
 impl<'a> PartialEq for FirstLine<'a> {
     fn eq(&self,  other: &Self) -> bool {
-        self.line() == other.line()
+        self.cmp(other) == Ordering::Equal
     }
 }
 impl<'a> Eq for FirstLine<'a> {}
@@ -148,13 +1381,19 @@ impl<'a> PartialOrd for FirstLine<'a> {
 }
 impl<'a> Ord for FirstLine<'a> {
     fn cmp(&self,  rhs: &Self) -> Ordering {
-        match self.line().cmp(rhs.line()) {
+        let last_source = self.shared.last_source.get();
+        match self.shared.comparator.compare(self.line(), &self.key, rhs.line(), &rhs.key) {
             // invert because BinaryHeap is a max heap
             Ordering::Less => Ordering::Greater,
             Ordering::Greater => Ordering::Less,
+            Ordering::Equal if self.source.priority != rhs.source.priority => {
+                self.source.priority.cmp(&rhs.source.priority)
+            }
+            // the file given first, so that the output doesn't depend on what was printed before
+            Ordering::Equal if self.shared.comparator.stable => rhs.source_index.cmp(&self.source_index),
             // prefer continuing from the same file
-            Ordering::Equal if self.source_index == self.last_source.get() => Ordering::Greater,
-            Ordering::Equal if rhs.source_index == self.last_source.get() => Ordering::Less,
+            Ordering::Equal if self.source_index == last_source => Ordering::Greater,
+            Ordering::Equal if rhs.source_index == last_source => Ordering::Less,
             // make order predictable by falling back to path ordering
             Ordering::Equal if self.source_index > rhs.source_index => Ordering::Greater,
             Ordering::Equal => Ordering::Less,
@@ -175,116 +1414,756 @@ impl<'a> Debug for FirstLine<'a> {
 }
 
 fn main() {
+    let options = parse_args(args_os().skip(1));
+    let started = Instant::now();
+    VERBOSITY.store(options.verbose, AtomicOrdering::Relaxed);
+    ESCAPE_PATHS.store(!options.raw_paths, AtomicOrdering::Relaxed);
+    if options.inputs.is_empty() {
+        print_usage_and_fail();
+    }
+    if let Some(locale) = &options.locale {
+        verbose(1, &[b"comparing text in the order of the locale ", locale.as_bytes()]);
+    }
+
+    // check that the output file isn't also an input before it gets truncated
+    let output_identities = options.output.iter().chain(options.tee.iter().map(|(path, _)| path ))
+            .filter_map(|path| Some((file_identity(Path::new(path))?, path)) )
+            .collect::<Vec<_>>();
+    let output_path = match &options.output {
+        Some(path) => os_to_bytes(path.clone()),
+        None => b"stdout".to_vec(),
+    };
+
     // open files
     let mut sources = Vec::<RefCell<Source>>::new();
-    for arg in args_os().skip(1) {
-        // open the file before converting the OsString to bytes
-        let file_result = File::open(Path::new(&arg));
-        #[cfg(any(unix, wasi))]
-        let path = arg.into_vec();
-        #[cfg(not(any(unix, wasi)))]
-        let path = arg.into_string()
-            .unwrap_or_else(|bad| bad.to_string_lossy().into_owned() )
-            .into_bytes();
-        // handle potential error now that we have the path as bytes
-        let file = file_result.unwrap_or_else(|err| {
-            error("Cannot open", &path, err, 2);
-        });
+    let mut inputs = VecDeque::from(options.inputs);
+    while let Some(input) = inputs.pop_front() {
+        let label = input.label.as_deref();
+        let (arg, listed_in) = match &input.location {
+            Location::Stdin => (None, None),
+            Location::Path(arg) => (Some(arg), None),
+            Location::Listed(arg, list, position) => (Some(arg), Some((&list[..], &position[..]))),
+        };
+        let identity = arg.and_then(|arg| file_identity(Path::new(arg)) );
+        for (output, path) in &output_identities {
+            if identity.as_ref() == Some(output) {
+                let e = IoError::new(ErrorKind::InvalidInput, "the output file is also an input");
+                error("Cannot write to", &os_to_bytes((*path).clone()), e, 2);
+            }
+        }
+        let (path, file, modified, size): (Vec<u8>, Box<dyn Read>, _, _) = match arg {
+            None => (b"(standard input)".to_vec(), Box::new(stdin()), SystemTime::now(), None),
+            Some(arg) => {
+                let path = os_to_bytes(arg.clone());
+                let described = describe(&path, label, listed_in);
+                let (file, file_type) = open(arg, &described);
+                if file_type.is_dir() {
+                    verbose(1, &[&path, b": expanding directory"]);
+                    let relative = input.relative.as_deref().unwrap_or_else(|| Path::new("") );
+                    let contents = list_directory(Path::new(arg), relative, options.recursive, &options.filters);
+                    if contents.is_empty() {
+                        warning(&[b"directory ", &path, b" contains no files"]);
+                    }
+                    // files in a directory inherit the options given for it
+                    for (i, mut file) in contents.into_iter().enumerate() {
+                        file.priority = input.priority;
+                        file.time_format = input.time_format.clone();
+                        file.time_zone = input.time_zone.clone();
+                        file.format = input.format;
+                        file.encoding = input.encoding;
+                        file.skip_lines = input.skip_lines;
+                        file.grep = input.grep.clone();
+                        file.invert_grep = input.invert_grep.clone();
+                        inputs.insert(i, file);
+                    }
+                    continue;
+                }
+                if let Some(kind) = special_kind(file_type) {
+                    if options.skip_nonfiles  &&  (file.is_none() || !options.allow_special) {
+                        warning(&[b"skipping ", &described, b", which is ", kind.as_bytes()]);
+                        continue;
+                    } else if !options.allow_special {
+                        let message = format!("is {} (use --allow-special to read it anyway)", kind);
+                        error("Cannot merge", &described, IoError::new(ErrorKind::InvalidInput, message), 2);
+                    } else if file.is_none() {
+                        let message = format!("is {}, which cannot be opened", kind);
+                        error("Cannot merge", &described, IoError::new(ErrorKind::InvalidInput, message), 2);
+                    }
+                    verbose(1, &[&described, b": is ", kind.as_bytes()]);
+                } else if is_fifo(file_type) {
+                    verbose(1, &[&described, b": is a FIFO"]);
+                }
+                let file = file.unwrap();
+                let modified = file.metadata().and_then(|metadata| metadata.modified() );
+                let size = file.metadata().ok().filter(|metadata| metadata.is_file() ).map(|metadata| metadata.len() );
+                (path, Box::new(file), modified.unwrap_or_else(|_| SystemTime::now() ), size)
+            }
+        };
+        let described = describe(&path, label, None);
+        let file: Box<dyn Read> = match input.encoding {
+            Encoding::Utf8 if options.keep_bom => file,
+            encoding => Box::new(Decoder::new(file, encoding, options.keep_bom, &described)),
+        };
+        let file: Box<dyn Read> = match input.format {
+            InputFormat::Text => file,
+            InputFormat::JournalExport => Box::new(JournalExport::new(file)),
+        };
+        let priority = input.priority.to_string();
+        verbose(1, &[&described, b": opened with priority ", priority.as_bytes()]);
         sources.push(RefCell::new(Source {
-            path: path.into_boxed_slice(),
+            file_path: escape_path(&path).into(),
+            color: Box::default(),
+            // a line is at least one character and a newline
+            number_width: options.line_number_width.unwrap_or_else(|| {
+                size.map_or(6, |size| size.div_ceil(2).max(1).to_string().len() )
+            }),
+            size,
+            path: escape_path(label.unwrap_or(&path)).into(),
+            described: described.into_boxed_slice(),
             file,
             buffer: vec![0; 1024*1024].into_boxed_slice(),
             read: 0,
+            priority: input.priority,
+            modified: match modified.duration_since(UNIX_EPOCH) {
+                Ok(after) => after.as_nanos() as i64,
+                Err(before) => -(before.duration().as_nanos() as i64),
+            },
+            time_format: match input.time_format {
+                Some(time_format) => time_format,
+                None => options.comparator.time_format.clone(),
+            },
+            year: Cell::new(YearGuess::default()),
+            time_zone: match input.time_zone {
+                Some(time_zone) => Some(time_zone),
+                None => options.time_zone.clone(),
+            },
+            terminator: options.comparator.terminator().into(),
+            paragraphs: matches!(options.records, Records::Paragraphs),
+            record_size: match options.records {
+                Records::Fixed(size) => Some(size),
+                _ => None,
+            },
+            skip_lines: input.skip_lines.unwrap_or(options.skip_lines),
+            grep: input.grep.clone(),
+            invert_grep: input.invert_grep.clone(),
+            max_line_length: options.max_line_length,
+            truncate_long_lines: options.truncate_long_lines,
+            long_line_at: Cell::new(None),
+            added: 0,
+            offset: 0,
+            pending: RefCell::new(Vec::new()),
+            scanned: Cell::new(0),
+            eof: Cell::new(false),
+            lines: Cell::new(0),
+            printed: Cell::new(0),
+            printed_bytes: Cell::new(0),
+            groups: Cell::new(0),
+            growth: Vec::new(),
+            long_shifts: 0,
+            bytes_read: 0,
+            last_key: RefCell::new(None),
+            previous: RefCell::new(None),
+            unsorted_at: Cell::new(None),
         }));
     }
 
-    if sources.is_empty() {
-        eprintln!("Usage: log_merge file1 [file2]...");
-        eprintln!();
-        eprintln!("\"Sorts\" the files but prints the file name above each group of lines from a file, like `tail -f`.");
-        eprintln!("Files are merged by sorting the next unprinted line from each file,");
-        eprintln!("without reordering lines from the same file or keeping everything in RAM.");
-        eprintln!("(Memory usage is linear with the number of files, not with the file sizes.)");
-        exit(1);
+    if options.check {
+        let sorted = check(sources, &options.comparator, options.blank_lines, &options.records, options.check_quiet);
+        exit(if sorted {0} else {1});
+    }
+    if options.basename_headers {
+        basename_headers(&mut sources);
     }
+    let structured = options.output_format != OutputFormat::Text;
+    let colored = match options.color {
+        _ if structured => false,
+        ColorWhen::Never => false,
+        ColorWhen::Auto => options.output.is_none()  &&  stdout().is_terminal()  &&  !color::no_color(),
+        ColorWhen::Always => true,
+    };
+    if colored {
+        let names = sources.iter_mut().map(|source| source.get_mut().path.clone() ).collect::<Vec<_>>();
+        let names = names.iter().map(|name| &name[..] ).collect::<Vec<_>>();
+        let colors = color::assign(&names, color::Depth::detect(), &options.palette);
+        for (source, color) in sources.iter_mut().zip(colors) {
+            source.get_mut().color = color.into_boxed_slice();
+        }
+    }
+    let color_lines = colored  &&  options.color_lines;
+    // for --legend: show the number of each file instead of its path, after the colors are picked by path
+    let legend = match options.legend {
+        true => sources.iter_mut().enumerate().map(|(i, source)| {
+            std::mem::replace(&mut source.get_mut().path, format!("#{}", i+1).into_bytes().into_boxed_slice())
+        }).collect::<Vec<_>>(),
+        false => Vec::new(),
+    };
+    let prefixes = prefixes(&sources, options.align_prefixes, options.max_prefix_width);
 
     let mut first_print = true;
-    let last_printed = Cell::new(sources.len());
-    let stdout = stdout();
-    let mut stdout = stdout.lock();
+    let shared = Shared {
+        by_time: options.comparator.by_time(),
+        last_source: Cell::new(sources.len()),
+        comparator: options.comparator,
+        warn_unmatched: options.warn_unmatched,
+        blank_lines: options.blank_lines,
+        records: options.records,
+        window: options.window,
+        unsorted: options.unsorted,
+        since: options.since,
+        until: options.until,
+    };
+    let last_printed = &shared.last_source;
+    let pager = match options.pager  &&  options.output.is_none()  &&  stdout().is_terminal() {
+        true => pager::start(colored),
+        false => None,
+    };
+    let to: Box<dyn Write> = match (&options.output, pager) {
+        (Some(path), _) => match File::create(path) {
+            Ok(file) => Box::new(file),
+            Err(e) => error("Cannot create", &output_path, e, 2),
+        },
+        (None, Some(pipe)) => Box::new(pipe),
+        (None, None) => Box::new(stdout().lock()),
+    };
+    let to: Box<dyn Write> = match options.tee.is_empty() {
+        true => to,
+        false => Box::new(Tee {
+            to,
+            files: options.tee.iter().map(|(path, best_effort)| TeeFile {
+                path: os_to_bytes(path.clone()),
+                file: match File::create(path) {
+                    Ok(file) => Some(file),
+                    Err(e) if *best_effort => {
+                        let e = e.to_string();
+                        let path = os_to_bytes(path.clone());
+                        warning(&[b"Cannot create ", &escape_path(&path), b": ", e.as_bytes(), b", so not writing to it"]);
+                        None
+                    }
+                    Err(e) => error("Cannot create", &os_to_bytes(path.clone()), e, 2),
+                },
+                best_effort: *best_effort,
+            }).collect(),
+        }),
+    };
+    let mut output = Output {
+        to,
+        grouped: options.heading_range  &&  options.headers,
+        group: Vec::new(),
+        header_ends: None,
+        times: None,
+        time_format: options.rewrite_time.clone().unwrap_or(TimeFormat::Iso8601),
+        limit: options.max_bytes,
+        written: 0,
+        line_starts: (0, None, None),
+        min_group: if options.headers {options.min_group.unwrap_or(0)} else {0},
+        held: None,
+    };
 
     #[cfg(feature="debug")]
     eprintln!("sources: {:?}", &sources);
     let mut sorter = BinaryHeap::<FirstLine>::with_capacity(sources.len());
     for (i, source) in sources.iter().enumerate() {
-        let line = source.borrow_mut().read_next_line(0);
-        if let Some(line_length) = line {
-            sorter.push(FirstLine {
-                source: source.borrow(),
-                line_length: line_length,
-                starts_at: 0,
-                source_index: i,
-                last_source: &last_printed,
-            });
+        match shared.read_next_line(source) {
+            Some(line) => sorter.push(FirstLine::new(source.borrow(), line, i, &shared)),
+            None => verbose(1, &[&source.borrow().path, b": empty, skipping"]),
         }
     }
+    let source_count = sources.len();
+    let reached_end = |source: &Source,  source_index: usize| {
+        verbose(1, &[&source.path, b": reached end of file"]);
+        // with --unique the line might not have been printed
+        if last_printed.get() == source_index {
+            last_printed.set(source_count);
+        }
+    };
+
+    // headers end with the same as lines
+    let terminator = shared.comparator.terminator().to_vec();
+    // unless --line-ending changes the ending of lines
+    let header_ending = options.line_ending.replacement().unwrap_or(&terminator).to_vec();
+    if options.legend {
+        let mut manifest = Vec::new();
+        for (source, path) in sources.iter().zip(&legend) {
+            let source = source.borrow();
+            let reset = if source.color.is_empty() {&b""[..]} else {color::RESET};
+            manifest.extend_from_slice(&[&source.color[..], &source.path, reset, b" = ", path, &header_ending].concat());
+        }
+        manifest.extend_from_slice(&header_ending);
+        if let Err(e) = output.write_all(&manifest) {
+            error("Error writing to", &output_path, e, 4);
+        }
+    }
+    // what is between groups: a blank line unless --heading-format has its own
+    let separator = match (&options.group_separator, &options.heading_format) {
+        (Some(separator), _) => &separator[..],
+        (None, Some(_)) => &b""[..],
+        (None, None) => &header_ending[..],
+    };
+    // for --escape-headings
+    let marker = match &options.heading_format {
+        Some(format) => format.marker(),
+        None => b">>> ",
+    };
+    let escape_headings = options.escape_headings  &&  options.headers;
+    let escape_eof_markers = options.escape_headings  &&  options.eof_markers;
+    // for --eof-markers: write what is ready and then the marker,
+    // and print a header before the next line even if it's from the file the last line was from
+    // (with --max-bytes a marker that doesn't fit is left out, and then so is the next line)
+    let write_eof_marker = |output: &mut Output,  ready_output: &[IoSlice],  source: &Source| {
+        let lines = source.printed.get().to_string();
+        let unit = if source.printed.get() == 1 {&b" line)"[..]} else {b" lines)"};
+        let marker = [&b"<<< "[..], &source.path, b" (", lines.as_bytes(), unit, &header_ending].concat();
+        let slices = ready_output.iter().copied().chain(Some(IoSlice::new(&marker)));
+        let written = write_all_vectored(output, &slices.collect::<Vec<_>>()).and_then(|_| output.line_fits() );
+        if let Err(e) = written {
+            error("Error writing to", &output_path, e, 4);
+        }
+        last_printed.set(source_count);
+    };
+    // for --debug
+    let mut description = Vec::<u8>::new();
+    // for --rewrite-time
+    let mut rewritten = Vec::<u8>::new();
+    // for --unique and --self-check: a copy of the last printed line,
+    // as the buffer it was in might be reused, and the source it came from
+    let mut previous_line = Vec::<u8>::new();
+    let mut previous_key = None::<Key>;
+    let mut previous_source = 0;
+    // for --squeeze-blank: the last printed line was empty
+    let mut previous_blank = false;
+    // for --heading-format
+    let mut heading = Vec::<u8>::new();
+    // for --line-numbers
+    let mut number = Vec::<u8>::new();
+    // for --output-format
+    let mut record = Vec::<u8>::new();
+    let mut printed_lines = 0u64;
+    // for --stats: the file the last printed line came from
+    let mut group_source = source_count;
+    // for --memory-report
+    let mut peak_memory = 0;
+
+    if options.max_lines == Some(0) {
+        sorter.clear();
+    }
 
     // merge as many available lines as possible
     while ! sorter.is_empty() {
         let borrows = sources.iter().map(|source| source.borrow() ).collect::<Vec<_>>();
         let mut ready_output = Vec::<IoSlice>::new();
-        let (source_index, written) = loop {
+        let mut merged_lines = 0usize;
+        let refill = loop {
             #[cfg(feature="debug")]
             eprintln!("sorter before: {:?}", &sorter);
 
-            let FirstLine { line_length, starts_at, source_index, source, .. } = sorter.pop().unwrap();
-            if source_index != last_printed.get() {
-                ready_output.push(IoSlice::new(&b"\n>>> "[first_print as usize..]));
-                ready_output.push(IoSlice::new(&borrows[source_index].path));
-                ready_output.push(IoSlice::new(b"\n"));
+            let FirstLine { line_length, starts_at, source_index, source, key, number: line_number, .. } = sorter.pop().unwrap();
+            let line = &source.buffer[starts_at..starts_at+line_length];
+            // for --since and --until
+            let time = key.time();
+            let past_until = time.zip(shared.until).is_some_and(|(time, until)| time >= until );
+            let outside = match time {
+                Some(time) => past_until  ||  shared.since.is_some_and(|since| time < since ),
+                None => shared.since.is_some()  ||  shared.until.is_some(),
+            };
+            // lines that no --grep matches are left out, and then lines that any --invert-grep matches
+            // (--file-grep and --file-invert-grep replace them for a file)
+            let content = line_content(line, &terminator);
+            let grep = source.grep.as_deref().unwrap_or(&options.grep);
+            let invert_grep = source.invert_grep.as_deref().unwrap_or(&options.invert_grep);
+            let filtered = (!grep.is_empty()  &&  !grep.iter().any(|regex| regex.is_match(content) ))
+                    ||  invert_grep.iter().any(|regex| regex.is_match(content) );
+            let dropped = outside  ||  filtered;
+            let duplicate = options.unique  &&  !dropped  &&  previous_key.as_ref().is_some_and(|previous_key| {
+                shared.comparator.keys_equal(&previous_line, previous_key, line, &key)
+            });
+            let blank = options.squeeze_blank  &&  line_content(line, &terminator).is_empty();
+            let squeezed = blank  &&  previous_blank  &&  !duplicate  &&  !dropped;
+            let skip = duplicate  ||  dropped  ||  squeezed;
+            if !skip {
+                previous_blank = blank;
+            }
+            let timestamp = match &options.rewrite_time {
+                Some(format) if !skip => key.timestamp().map(|(span, time)| {
+                    rewritten.clear();
+                    format.format(time, &mut rewritten);
+                    span
+                }),
+                _ => None,
+            };
+            if options.self_check  &&  !skip {
+                let unsorted = previous_key.as_ref().is_some_and(|previous_key| {
+                    shared.comparator.compare(&previous_line, previous_key, line, &key) == Ordering::Greater
+                });
+                if unsorted {
+                    if let Err(e) = write_all_vectored(&mut output, &ready_output).and_then(|_| output.flush() ) {
+                        error("Error writing to", &output_path, e, 4);
+                    }
+                    let stderr = stderr();
+                    let _ = write_all_vectored(&mut stderr.lock(), &[
+                            IoSlice::new(b"Error: the merged output is not sorted, because of these lines:\n"),
+                            IoSlice::new(&borrows[previous_source].described),
+                            IoSlice::new(b": "),
+                            IoSlice::new(line_content(&previous_line, &terminator)),
+                            IoSlice::new(b"\n"),
+                            IoSlice::new(&borrows[source_index].described),
+                            IoSlice::new(b": "),
+                            IoSlice::new(line_content(line, &terminator)),
+                            IoSlice::new(b"\n"),
+                    ]);
+                    exit(5);
+                }
+            }
+            if options.debug  &&  !skip {
+                description.clear();
+                shared.comparator.describe(line, &key, &mut description);
+            }
+            if (options.unique || options.self_check)  &&  !skip {
+                previous_line.clear();
+                previous_line.extend_from_slice(line);
+                previous_key = Some(key);
+                previous_source = source_index;
+            }
+            if skip {
+                // don't print it, or a header for it
+            } else if options.headers  &&  source_index != last_printed.get() {
+                let source = &borrows[source_index];
+                if output.grouped  ||  output.min_group > 1 {
+                    let ended = write_all_vectored(&mut output, &ready_output).and_then(|_| output.end_group() );
+                    if let Err(e) = ended {
+                        error("Error writing to", &output_path, e, 4);
+                    }
+                    ready_output.clear();
+                }
+                if output.min_group > 1 {
+                    let prefix = match (options.quiet_small_groups, source.color.is_empty()) {
+                        (true, _) => Vec::new(),
+                        (false, true) => [&source.path[..], b": "].concat(),
+                        (false, false) => [&source.color[..], &source.path, b": ", color::RESET].concat(),
+                    };
+                    output.hold(prefix);
+                }
+                // the part of the header after where --heading-range puts the times
+                let mut after = header_ending.len();
+                if let Some(format) = &options.heading_format {
+                    heading.clear();
+                    format.render(&source.file_path, &source.path, source_index, printed_lines, &mut heading);
+                    let leading = if first_print && heading.starts_with(&header_ending) {header_ending.len()} else {0};
+                    let heading = &heading[leading..];
+                    // color what is between the blank line before and the line ending after
+                    let starts = if heading.starts_with(&header_ending) {header_ending.len()} else {0};
+                    let ends = match heading.strip_suffix(&header_ending[..]) {
+                        Some(without) if without.len() >= starts => without.len(),
+                        _ => heading.len(),
+                    };
+                    let reset = if source.color.is_empty() {&b""[..]} else {color::RESET};
+                    after = reset.len() + heading.len() - ends;
+                    let heading = [
+                            IoSlice::new(if first_print {b""} else {separator}),
+                            IoSlice::new(&heading[..starts]),
+                            IoSlice::new(&source.color),
+                            IoSlice::new(&heading[starts..ends]),
+                            IoSlice::new(reset),
+                            IoSlice::new(&heading[ends..]),
+                    ];
+                    // write now, as the header is overwritten by the next one
+                    let slices = ready_output.iter().copied().chain(heading.iter().copied());
+                    if let Err(e) = write_all_vectored(&mut output, &slices.collect::<Vec<_>>()) {
+                        error("Error writing to", &output_path, e, 4);
+                    }
+                    ready_output.clear();
+                } else if !source.color.is_empty() {
+                    if !first_print {
+                        ready_output.push(IoSlice::new(separator));
+                    }
+                    ready_output.push(IoSlice::new(&source.color));
+                    ready_output.push(IoSlice::new(marker));
+                    ready_output.push(IoSlice::new(&source.path));
+                    ready_output.push(IoSlice::new(color::RESET));
+                    ready_output.push(IoSlice::new(&header_ending));
+                    after += color::RESET.len();
+                } else {
+                    if !first_print {
+                        ready_output.push(IoSlice::new(separator));
+                    }
+                    ready_output.push(IoSlice::new(marker));
+                    ready_output.push(IoSlice::new(&source.path));
+                    ready_output.push(IoSlice::new(&header_ending));
+                }
                 #[cfg(feature="debug")] {
-                    write_all_vectored(&mut stdout, &ready_output).expect("write path");
+                    write_all_vectored(&mut output, &ready_output).expect("write path");
+                    ready_output.clear();
+                }
+                if output.grouped {
+                    if let Err(e) = write_all_vectored(&mut output, &ready_output) {
+                        error("Error writing to", &output_path, e, 4);
+                    }
                     ready_output.clear();
+                    output.header_written(after);
+                }
+                if output.held.is_some() {
+                    if let Err(e) = write_all_vectored(&mut output, &ready_output) {
+                        error("Error writing to", &output_path, e, 4);
+                    }
+                    ready_output.clear();
+                    output.header_held();
                 }
                 last_printed.set(source_index);
                 first_print = false;
             }
+            if output.grouped  &&  !skip {
+                let time = match shared.by_time {
+                    true => time,
+                    false => source.line_time(&shared.comparator, line),
+                };
+                if let Some(time) = time {
+                    output.line_time(time);
+                }
+            }
+            if !skip {
+                merged_lines += 1;
+                printed_lines += 1;
+                source.printed.set(source.printed.get() + 1);
+                source.printed_bytes.set(source.printed_bytes.get() + line_length as u64);
+                if source_index != group_source {
+                    source.groups.set(source.groups.get() + 1);
+                    group_source = source_index;
+                }
+            }
+            // --line-ending replaces the ending of each line
+            let buffered = &borrows[source_index].buffer[starts_at..starts_at+line_length];
+            let unterminated = !options.add_newline  &&  starts_at+line_length == source.read  &&  source.added != 0;
+            let (content, ending) = match options.line_ending.replacement() {
+                // --no-add-newline prints the last line as it is in the file
+                _ if unterminated => (&buffered[..line_length-source.added], &b""[..]),
+                Some(ending) => (line_content(buffered, &terminator), ending),
+                None => (buffered, &b""[..]),
+            };
+            // --color=lines resets the color before the line ending
+            let (content, ending) = match ending.is_empty()  &&  color_lines {
+                true => content.split_at(line_content(content, &terminator).len()),
+                false => (content, ending),
+            };
+            let color = &borrows[source_index].color;
+            if let (Some(separator), false) = (&options.prefix, skip) {
+                if !color.is_empty() {
+                    ready_output.push(IoSlice::new(color));
+                }
+                ready_output.push(IoSlice::new(&prefixes[source_index]));
+                ready_output.push(IoSlice::new(separator));
+                if !color.is_empty()  &&  !color_lines {
+                    ready_output.push(IoSlice::new(color::RESET));
+                }
+            } else if color_lines  &&  !skip {
+                ready_output.push(IoSlice::new(color));
+            }
+            let escape = (escape_headings  &&  looks_like_heading(content, marker))
+                    ||  (escape_eof_markers  &&  looks_like_heading(content, b"<<< "));
+            if escape  &&  !skip {
+                ready_output.push(IoSlice::new(b"\\"));
+            }
+            let reset = if color_lines {color::RESET} else {&b""[..]};
+            if structured  &&  !skip {
+                let source = &borrows[source_index];
+                let body = line_content(buffered, &terminator);
+                let parts = match &timestamp {
+                    Some(span) => [&body[..span.start], &rewritten[..], &body[span.end..]],
+                    None => [body, b"", b""],
+                };
+                record.clear();
+                let parts = match options.output_format {
+                    OutputFormat::Tsv => {
+                        escape_tsv(&source.path, &mut record);
+                        record.extend_from_slice(format!("\t{}\t", line_number).as_bytes());
+                        // lines that don't need to be escaped aren't copied
+                        if parts.iter().any(|part| needs_tsv_escape(part) ) {
+                            for part in &parts {
+                                escape_tsv(part, &mut record);
+                            }
+                            [&b""[..]; 3]
+                        } else {
+                            parts
+                        }
+                    }
+                    _ => {
+                        record.extend_from_slice(b"{\"source\":\"");
+                        json::escape(&source.path, &mut record);
+                        record.extend_from_slice(format!("\",\"line_number\":{},\"line\":\"", line_number).as_bytes());
+                        for part in &parts {
+                            json::escape(part, &mut record);
+                        }
+                        record.extend_from_slice(b"\"}");
+                        [&b""[..]; 3]
+                    }
+                };
+                let slices = ready_output.iter().copied()
+                        .chain(Some(IoSlice::new(&record)))
+                        .chain(parts.iter().map(|part| IoSlice::new(part) ))
+                        .chain(Some(IoSlice::new(options.line_ending.replacement().unwrap_or(b"\n"))));
+                if let Err(e) = write_all_vectored(&mut output, &slices.collect::<Vec<_>>()) {
+                    error("Error writing to", &output_path, e, 4);
+                }
+                ready_output.clear();
+            }
+            let write_now = options.debug  ||  timestamp.is_some()  ||  options.line_numbers  ||  structured
+                    ||  options.max_bytes.is_some()  ||  output.min_group > 1;
             #[cfg(not(feature="debug"))]
-            ready_output.push(IoSlice::new(&borrows[source_index].buffer[starts_at..starts_at+line_length]));
+            if !skip  &&  !write_now {
+                ready_output.push(IoSlice::new(content));
+                if color_lines {
+                    ready_output.push(IoSlice::new(reset));
+                }
+                ready_output.push(IoSlice::new(ending));
+            }
+            if write_now  &&  !skip  &&  !structured {
+                // write everything now, as the description, the rewritten timestamp
+                // and the line number are overwritten for the next line
+                number.clear();
+                if options.line_numbers {
+                    let width = borrows[source_index].number_width;
+                    number.extend_from_slice(format!("{:0width$}:", line_number, width=width).as_bytes());
+                }
+                let line = match timestamp {
+                    Some(span) => [
+                            IoSlice::new(&content[..span.start]),
+                            IoSlice::new(&rewritten),
+                            IoSlice::new(&content[span.end..]),
+                    ],
+                    None => [IoSlice::new(content), IoSlice::new(b""), IoSlice::new(b"")],
+                };
+                let slices = ready_output.iter().copied()
+                        .chain(Some(IoSlice::new(&number)))
+                        .chain(line.iter().copied())
+                        .chain([IoSlice::new(reset), IoSlice::new(ending), IoSlice::new(&description)]);
+                if let Err(e) = write_all_vectored(&mut output, &slices.collect::<Vec<_>>()) {
+                    error("Error writing to", &output_path, e, 4);
+                }
+                ready_output.clear();
+            }
             #[cfg(feature="debug")]
-            stdout.write_all(next.line()).expect("write line");
-            let after = &source.buffer[starts_at+line_length..source.read];
-            if let Some(line_len) = after.iter().position(|&b| b == b'\n' ) {
-                sorter.push(FirstLine {
-                    source: source,
-                    line_length: line_len + 1,
-                    starts_at: starts_at + line_length,
-                    source_index,
-                    last_source: &last_printed,
-                });
-            } else {
-                // actually write the merged lines
-                if let Err(e) = write_all_vectored(&mut stdout, &ready_output) {
-                    error("Error writing to", b"stdout", e, 4);
+            if !skip {
+                output.write_all(line).expect("write line");
+            }
+            if !skip {
+                if let Err(e) = output.line_done() {
+                    error("Error writing to", &output_path, e, 4);
+                }
+            }
+            let fits = match output.line_fits() {
+                Ok(fits) => fits,
+                Err(e) => error("Error writing to", &output_path, e, 4),
+            };
+            if !fits {
+                let mut stopped_at = sorter.drain()
+                        .map(|first| (first.source_index, first.source.offset as u64 + first.starts_at as u64) )
+                        .collect::<Vec<_>>();
+                stopped_at.push((source_index, source.offset as u64 + starts_at as u64));
+                unread_note(&borrows, stopped_at);
+                break None;
+            }
+            if options.max_lines == Some(printed_lines)  &&  !skip {
+                verbose(1, &[b"reached --max-lines after ", &source.path]);
+                sorter.clear();
+                break None;
+            }
+            if past_until {
+                // the rest of the file is later
+                verbose(1, &[&source.path, b": reached --until"]);
+                if options.eof_markers {
+                    write_eof_marker(&mut output, &ready_output, &source);
+                    ready_output.clear();
+                }
+                reached_end(&source, source_index);
+                if sorter.is_empty() {
+                    break None;
+                }
+                continue;
+            }
+            match shared.next_line(&source) {
+                Ok(Some(next)) => {
+                    if shared.unsorted == Unsorted::Error  &&  source.unsorted_at.get().is_some() {
+                        if let Err(e) = write_all_vectored(&mut output, &ready_output).and_then(|_| output.flush() ) {
+                            error("Error writing to", &output_path, e, 4);
+                        }
+                        unsorted_error(&source);
+                    }
+                    sorter.push(FirstLine::new(source, next, source_index, &shared));
+                }
+                Ok(None) => {
+                    if options.eof_markers {
+                        write_eof_marker(&mut output, &ready_output, &source);
+                        ready_output.clear();
+                    }
+                    reached_end(&source, source_index);
+                    if sorter.is_empty() {
+                        break None;
+                    }
+                }
+                Err(keep_from) => {
+                    let merged_lines = merged_lines.to_string();
+                    verbose(2, &[b"merged ", merged_lines.as_bytes(), b" lines before reading more from ", &source.path]);
+                    break Some((source_index, keep_from));
                 }
-                break (source_index, starts_at+line_length);
             }
             #[cfg(feature="debug")]
             eprintln!("sorter after: {:?}", &sorter);
         };
+        // actually write the merged lines
+        if let Err(e) = write_all_vectored(&mut output, &ready_output) {
+            error("Error writing to", &output_path, e, 4);
+        }
+        if options.memory_report {
+            let buffers = borrows.iter().map(|source| source.buffer.len() ).sum::<usize>();
+            let slices = ready_output.capacity() * std::mem::size_of::<IoSlice>();
+            peak_memory = peak_memory.max(buffers + slices + output.group.capacity());
+        }
+        if output.group.len() > MAX_GROUP {
+            if let Err(e) = output.end_group() {
+                error("Error writing to", &output_path, e, 4);
+            }
+            last_printed.set(source_count);
+        }
         drop(ready_output);
         drop(borrows);
-        let mut source = sources[source_index].borrow_mut();
-        if let Some(line_length) = source.read_next_line(written) {
-            sorter.push(FirstLine {
-                source: sources[source_index].borrow(),
-                line_length,
-                starts_at: 0,
-                source_index,
-                last_source: &last_printed,
-            });
-        } else {
-            last_printed.set(sources.len());
+        if let Some((source_index, keep_from)) = refill {
+            let source = &sources[source_index];
+            source.borrow_mut().read_more(keep_from);
+            match shared.read_next_line(source) {
+                Some(_) if shared.unsorted == Unsorted::Error  &&  source.borrow().unsorted_at.get().is_some() => {
+                    if let Err(e) = output.flush() {
+                        error("Error writing to", &output_path, e, 4);
+                    }
+                    unsorted_error(&source.borrow());
+                }
+                Some(next) => sorter.push(FirstLine::new(source.borrow(), next, source_index, &shared)),
+                None => {
+                    if options.eof_markers {
+                        write_eof_marker(&mut output, &[], &source.borrow());
+                    }
+                    reached_end(&source.borrow(), source_index);
+                }
+            }
         }
     }
+    if let Err(e) = output.flush() {
+        error("Error writing to", &output_path, e, 4);
+    }
+    let elapsed = started.elapsed().as_secs_f64();
+    let written = output.written;
+    drop(output);
+    pager::wait();
+    if options.stats {
+        print_stats(&sources, started);
+    }
+    if options.memory_report {
+        let buffers = sources.iter().map(|source| source.borrow().buffer.len() ).sum::<usize>();
+        print_memory_report(&sources, peak_memory.max(buffers));
+    }
+    if options.time {
+        let read = sources.iter().map(|source| source.borrow().bytes_read ).sum::<u64>();
+        let summary = format!("merged {} files, {} in, {} out, {}/s, {:.1} s\n",
+                sources.len(), human_size(read as f64), human_size(written as f64),
+                human_size(read as f64 / elapsed.max(1e-9)), elapsed
+        );
+        let _ = stderr().lock().write_all(summary.as_bytes());
+    }
 }
@@ -0,0 +1,140 @@
+/* logmerge - A program to merge files like tail -f
+ * Copyright (C) 2021 Torbjørn Birch Moltu
+ *
+ * licenced under the terms of the GNU Lesser General Public License
+ * as published by the Free Software Foundation,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Unicode normalization to NFC or NFD for --normalize.
+//!
+//! Only enabled with the normalize feature, because of the size of the tables.
+
+use crate::normalize_tables::{COMBINING_CLASSES, DECOMPOSITIONS, COMPOSITIONS};
+use std::cmp::Ordering;
+
+const HANGUL_FIRST: u32 = 0xac00;
+const HANGUL_COUNT: u32 = 11172;
+const LEADING_FIRST: u32 = 0x1100;
+const VOWEL_FIRST: u32 = 0x1161;
+const VOWEL_COUNT: u32 = 21;
+const TRAILING_FIRST: u32 = 0x11a7;
+const TRAILING_COUNT: u32 = 28;
+
+fn combining_class(c: char) -> u8 {
+    let found = COMBINING_CLASSES.binary_search_by(|&(first, last, _)| {
+        if last < c {
+            Ordering::Less
+        } else if first > c {
+            Ordering::Greater
+        } else {
+            Ordering::Equal
+        }
+    });
+    found.map_or(0, |index| COMBINING_CLASSES[index].2 )
+}
+
+fn decompose_into(c: char,  to: &mut Vec<char>) {
+    let syllable = (c as u32).wrapping_sub(HANGUL_FIRST);
+    if syllable < HANGUL_COUNT {
+        let leading = LEADING_FIRST + syllable / (VOWEL_COUNT*TRAILING_COUNT);
+        let vowel = VOWEL_FIRST + syllable % (VOWEL_COUNT*TRAILING_COUNT) / TRAILING_COUNT;
+        let trailing = syllable % TRAILING_COUNT;
+        to.extend(char::from_u32(leading));
+        to.extend(char::from_u32(vowel));
+        if trailing != 0 {
+            to.extend(char::from_u32(TRAILING_FIRST + trailing));
+        }
+        return;
+    }
+    match DECOMPOSITIONS.binary_search_by_key(&c, |&(composite, _)| composite ) {
+        Ok(index) => to.extend_from_slice(DECOMPOSITIONS[index].1),
+        Err(_) => to.push(c),
+    }
+}
+
+fn compose(first: char,  second: char) -> Option<char> {
+    let (first_code, second_code) = (first as u32, second as u32);
+    let leading = first_code.wrapping_sub(LEADING_FIRST);
+    let vowel = second_code.wrapping_sub(VOWEL_FIRST);
+    if leading < 19  &&  vowel < VOWEL_COUNT {
+        return char::from_u32(HANGUL_FIRST + (leading*VOWEL_COUNT + vowel) * TRAILING_COUNT);
+    }
+    let syllable = first_code.wrapping_sub(HANGUL_FIRST);
+    let trailing = second_code.wrapping_sub(TRAILING_FIRST);
+    if syllable < HANGUL_COUNT  &&  syllable % TRAILING_COUNT == 0  &&  trailing > 0  &&  trailing < TRAILING_COUNT {
+        return char::from_u32(first_code + trailing);
+    }
+    COMPOSITIONS.binary_search_by_key(&(first, second), |&(pair, _)| pair )
+        .ok()
+        .map(|index| COMPOSITIONS[index].1 )
+}
+
+/// Canonical decomposition followed by canonical ordering.
+fn decompose(text: &str) -> Vec<char> {
+    let mut chars = Vec::with_capacity(text.len());
+    for c in text.chars() {
+        decompose_into(c, &mut chars);
+    }
+    // sort each run of combining characters by class, which must be stable
+    let mut start = 0;
+    while start < chars.len() {
+        if combining_class(chars[start]) == 0 {
+            start += 1;
+            continue;
+        }
+        let mut end = start + 1;
+        while end < chars.len()  &&  combining_class(chars[end]) != 0 {
+            end += 1;
+        }
+        chars[start..end].sort_by_key(|&c| combining_class(c) );
+        start = end;
+    }
+    chars
+}
+
+fn composed(chars: Vec<char>) -> Vec<char> {
+    let mut result = Vec::<char>::with_capacity(chars.len());
+    let mut starter = None::<usize>;
+    // class of the last character after the starter, which can block composition
+    let mut last_class = None::<u8>;
+    for c in chars {
+        let class = combining_class(c);
+        if let Some(starter) = starter {
+            let blocked = match last_class {
+                None => false,
+                Some(last) => last == 0  ||  last >= class,
+            };
+            if !blocked {
+                if let Some(composite) = compose(result[starter], c) {
+                    result[starter] = composite;
+                    continue;
+                }
+            }
+        }
+        if class == 0 {
+            starter = Some(result.len());
+            last_class = None;
+        } else {
+            last_class = Some(class);
+        }
+        result.push(c);
+    }
+    result
+}
+
+pub fn nfd(text: &str) -> String {
+    decompose(text).into_iter().collect()
+}
+
+pub fn nfc(text: &str) -> String {
+    composed(decompose(text)).into_iter().collect()
+}
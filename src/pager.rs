@@ -0,0 +1,118 @@
+/* logmerge - A program to merge files like tail -f
+ * Copyright (C) 2021 Torbjørn Birch Moltu
+ *
+ * licenced under the terms of the GNU Lesser General Public License
+ * as published by the Free Software Foundation,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Running the pager for --pager, and not leaving it behind when exiting.
+//!
+//! The pager is kept here instead of in main() so that exiting because of an error can also close
+//! its input and wait for it, and Ctrl-C is ignored while it runs (like git does),
+//! as it goes to the pager too, which should then be the one to decide when to stop.
+
+use crate::warning;
+
+use std::env::var_os;
+use std::io::{Write, Error as IoError, ErrorKind, IoSlice};
+use std::process::{Command, Child, Stdio};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+
+/// Set when the output goes to a pager
+pub static PAGED: AtomicBool = AtomicBool::new(false);
+
+/// The pager, with the pipe to it
+static PAGER: Mutex<Option<Child>> = Mutex::new(None);
+
+#[cfg(unix)]
+extern "C" {
+    fn signal(signum: std::os::raw::c_int,  handler: usize) -> usize;
+}
+#[cfg(unix)]
+const SIGINT: std::os::raw::c_int = 2;
+#[cfg(unix)]
+const SIG_IGN: usize = 1;
+
+/// Start $PAGER, or less if it isn't set, with its input from a pipe,
+/// and return something that writes to that pipe.
+pub fn start(colored: bool) -> Option<Pipe> {
+    let mut command = match var_os("PAGER") {
+        Some(pager) if !pager.is_empty() => {
+            let mut command = Command::new("sh");
+            command.arg("-c").arg(pager);
+            command
+        }
+        _ => {
+            let mut command = Command::new("less");
+            if colored {
+                command.arg("-RFX");
+            }
+            command
+        }
+    };
+    match command.stdin(Stdio::piped()).spawn() {
+        Ok(pager) => {
+            *PAGER.lock().unwrap() = Some(pager);
+            PAGED.store(true, AtomicOrdering::Relaxed);
+            // SAFETY: ignoring a signal doesn't affect any Rust code, and the pager was started before
+            #[cfg(unix)]
+            unsafe { signal(SIGINT, SIG_IGN) };
+            Some(Pipe)
+        }
+        Err(e) => {
+            warning(&[b"Cannot start the pager: ", e.to_string().as_bytes()]);
+            None
+        }
+    }
+}
+
+/// Close the pipe so that the pager knows it has everything, and wait for it to be closed.
+/// Does nothing if there is no pager.
+pub fn wait() {
+    // don't hang if the lock is held by a write that failed, which shouldn't happen
+    let pager = match PAGER.try_lock() {
+        Ok(mut pager) => pager.take(),
+        Err(_) => None,
+    };
+    if let Some(mut pager) = pager {
+        drop(pager.stdin.take());
+        let _ = pager.wait();
+    }
+}
+
+/// Writes to the pager while it is running.
+pub struct Pipe;
+
+impl Pipe {
+    fn with_stdin<R>(&mut self,  action: impl FnOnce(&mut dyn Write) -> Result<R, IoError>) -> Result<R, IoError> {
+        let mut pager = PAGER.lock().unwrap();
+        match pager.as_mut().and_then(|pager| pager.stdin.as_mut() ) {
+            Some(stdin) => action(stdin),
+            None => Err(IoError::new(ErrorKind::BrokenPipe, "the pager has been closed")),
+        }
+    }
+}
+
+impl Write for Pipe {
+    fn write(&mut self,  buf: &[u8]) -> Result<usize, IoError> {
+        self.with_stdin(|stdin| stdin.write(buf) )
+    }
+
+    fn write_vectored(&mut self,  bufs: &[IoSlice]) -> Result<usize, IoError> {
+        self.with_stdin(|stdin| stdin.write_vectored(bufs) )
+    }
+
+    fn flush(&mut self) -> Result<(), IoError> {
+        self.with_stdin(|stdin| stdin.flush() )
+    }
+}
@@ -0,0 +1,510 @@
+/* logmerge - A program to merge files like tail -f
+ * Copyright (C) 2021 Torbjørn Birch Moltu
+ *
+ * licenced under the terms of the GNU Lesser General Public License
+ * as published by the Free Software Foundation,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! A small regular expression engine for --key-regex.
+//!
+//! It matches bytes, so lines don't need to be valid UTF-8,
+//! and simulates all alternatives at once (a Pike VM),
+//! so matching takes time linear in the length of the line.
+//!
+//! Supports `.`, `[...]`, `[^...]`, `*`, `+`, `?`, `{n,m}` and their lazy variants,
+//! `|`, `(...)`, `(?:...)`, `^`, `$`, `\d`, `\w`, `\s`, `\b` and their negations,
+//! `\n`, `\t`, `\r`, `\xHH` and escaped punctuation.
+
+use std::mem::swap;
+use std::ops::Range;
+
+/// Limit how much counted repetitions can expand the program.
+const MAX_INSTRUCTIONS: usize = 100_000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct ByteSet([u64; 4]);
+impl ByteSet {
+    const EMPTY: Self = ByteSet([0; 4]);
+
+    fn single(b: u8) -> Self {
+        let mut set = Self::EMPTY;
+        set.insert(b);
+        set
+    }
+
+    fn from_fn(f: impl Fn(u8) -> bool) -> Self {
+        let mut set = Self::EMPTY;
+        for b in (0..=255).filter(|&b| f(b) ) {
+            set.insert(b);
+        }
+        set
+    }
+
+    fn insert(&mut self,  b: u8) {
+        self.0[b as usize / 64] |= 1 << (b % 64);
+    }
+
+    fn union(&mut self,  other: &Self) {
+        for (word, other) in self.0.iter_mut().zip(&other.0) {
+            *word |= other;
+        }
+    }
+
+    fn invert(mut self) -> Self {
+        for word in &mut self.0 {
+            *word = !*word;
+        }
+        self
+    }
+
+    fn contains(&self,  b: u8) -> bool {
+        self.0[b as usize / 64] & (1 << (b % 64)) != 0
+    }
+}
+
+fn is_word(b: u8) -> bool {
+    b.is_ascii_alphanumeric()  ||  b == b'_'
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Assertion {
+    /// ^
+    Start,
+    /// $
+    End,
+    /// \b
+    WordBoundary,
+    /// \B
+    NotWordBoundary,
+}
+impl Assertion {
+    fn holds(self,  text: &[u8],  at: usize) -> bool {
+        let boundary = || {
+            let before = at > 0  &&  is_word(text[at-1]);
+            let after = at < text.len()  &&  is_word(text[at]);
+            before != after
+        };
+        match self {
+            Assertion::Start => at == 0,
+            Assertion::End => at == text.len(),
+            Assertion::WordBoundary => boundary(),
+            Assertion::NotWordBoundary => !boundary(),
+        }
+    }
+}
+
+enum Node {
+    Bytes(ByteSet),
+    Assert(Assertion),
+    /// a group, with its number if it captures
+    Group(Box<Node>, Option<usize>),
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat { node: Box<Node>,  min: u32,  max: Option<u32>,  greedy: bool },
+}
+
+/// What a backslash escape means.
+enum Escape {
+    Byte(u8),
+    Set(ByteSet),
+    Assert(Assertion),
+}
+
+struct Parser<'a> {
+    pattern: &'a [u8],
+    i: usize,
+    /// the number of capture groups so far
+    groups: usize,
+}
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.pattern.get(self.i).copied()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let b = self.peek()?;
+        self.i += 1;
+        Some(b)
+    }
+
+    fn alternation(&mut self) -> Result<Node, String> {
+        let mut alternatives = vec![self.concatenation()?];
+        while self.peek() == Some(b'|') {
+            self.i += 1;
+            alternatives.push(self.concatenation()?);
+        }
+        Ok(match alternatives.len() {
+            1 => alternatives.pop().unwrap(),
+            _ => Node::Alternate(alternatives),
+        })
+    }
+
+    fn concatenation(&mut self) -> Result<Node, String> {
+        let mut nodes = Vec::new();
+        while let Some(b) = self.peek() {
+            if b == b'|'  ||  b == b')' {
+                break;
+            }
+            let atom = self.atom()?;
+            nodes.push(self.repetition(atom)?);
+        }
+        Ok(Node::Concat(nodes))
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        Ok(match self.next().unwrap() {
+            b'.' => Node::Bytes(ByteSet::single(b'\n').invert()),
+            b'^' => Node::Assert(Assertion::Start),
+            b'$' => Node::Assert(Assertion::End),
+            b'(' => {
+                let number = match self.pattern[self.i..].starts_with(b"?:") {
+                    true => {
+                        self.i += 2;
+                        None
+                    }
+                    false => {
+                        self.groups += 1;
+                        Some(self.groups)
+                    }
+                };
+                let inner = self.alternation()?;
+                if self.next() != Some(b')') {
+                    return Err("unclosed (".to_string());
+                }
+                Node::Group(Box::new(inner), number)
+            }
+            b'[' => Node::Bytes(self.class()?),
+            b'\\' => match self.escape()? {
+                Escape::Byte(b) => Node::Bytes(ByteSet::single(b)),
+                Escape::Set(set) => Node::Bytes(set),
+                Escape::Assert(assertion) => Node::Assert(assertion),
+            },
+            b'*' | b'+' | b'?' => return Err("nothing to repeat".to_string()),
+            b => Node::Bytes(ByteSet::single(b)),
+        })
+    }
+
+    /// Parse what comes after a backslash.
+    fn escape(&mut self) -> Result<Escape, String> {
+        let b = self.next().ok_or("trailing backslash")?;
+        Ok(match b {
+            b'd' => Escape::Set(ByteSet::from_fn(|b| b.is_ascii_digit() )),
+            b'D' => Escape::Set(ByteSet::from_fn(|b| b.is_ascii_digit() ).invert()),
+            b'w' => Escape::Set(ByteSet::from_fn(is_word)),
+            b'W' => Escape::Set(ByteSet::from_fn(is_word).invert()),
+            b's' => Escape::Set(ByteSet::from_fn(|b| b.is_ascii_whitespace() )),
+            b'S' => Escape::Set(ByteSet::from_fn(|b| b.is_ascii_whitespace() ).invert()),
+            b'b' => Escape::Assert(Assertion::WordBoundary),
+            b'B' => Escape::Assert(Assertion::NotWordBoundary),
+            b'n' => Escape::Byte(b'\n'),
+            b't' => Escape::Byte(b'\t'),
+            b'r' => Escape::Byte(b'\r'),
+            b'x' => {
+                let hex = self.pattern.get(self.i..self.i+2)
+                    .and_then(|hex| std::str::from_utf8(hex).ok() )
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok() )
+                    .ok_or("\\x must be followed by two hexadecimal digits")?;
+                self.i += 2;
+                Escape::Byte(hex)
+            }
+            b if b.is_ascii_alphanumeric() => return Err(format!("unknown escape \\{}", b as char)),
+            b => Escape::Byte(b),
+        })
+    }
+
+    /// Parse a single byte or escape in a [...] class.
+    fn class_member(&mut self) -> Result<Escape, String> {
+        match self.next() {
+            Some(b'\\') => match self.escape()? {
+                Escape::Assert(_) => Err("\\b and \\B cannot be used in [...]".to_string()),
+                escape => Ok(escape),
+            },
+            Some(b) => Ok(Escape::Byte(b)),
+            None => Err("unclosed [".to_string()),
+        }
+    }
+
+    /// Parse the rest of a [...] class.
+    fn class(&mut self) -> Result<ByteSet, String> {
+        let negated = self.peek() == Some(b'^');
+        if negated {
+            self.i += 1;
+        }
+        let mut set = ByteSet::EMPTY;
+        let mut first = true;
+        // ] is a member if it comes first
+        while self.peek() != Some(b']')  ||  first {
+            first = false;
+            let from = match self.class_member()? {
+                Escape::Byte(b) => b,
+                Escape::Set(other) => {
+                    set.union(&other);
+                    continue;
+                }
+                Escape::Assert(_) => unreachable!(),
+            };
+            let is_range = self.peek() == Some(b'-')
+                    &&  !matches!(self.pattern.get(self.i+1), None | Some(b']'));
+            if !is_range {
+                set.insert(from);
+                continue;
+            }
+            self.i += 1;
+            match self.class_member()? {
+                Escape::Byte(to) if to >= from => set.union(&ByteSet::from_fn(|b| b >= from  &&  b <= to )),
+                _ => return Err("invalid range in [...]".to_string()),
+            }
+        }
+        self.i += 1;
+        Ok(if negated {set.invert()} else {set})
+    }
+
+    /// Parse the inside of {n}, {n,} or {n,m}, or None if it isn't one.
+    fn counts(&mut self) -> Result<Option<(u32, Option<u32>)>, String> {
+        let rest = &self.pattern[self.i+1..];
+        let end = match rest.iter().position(|&b| b == b'}' ) {
+            Some(end) => end,
+            None => return Ok(None),
+        };
+        let inside = match std::str::from_utf8(&rest[..end]) {
+            Ok(inside) if !inside.is_empty() && inside.bytes().all(|b| b.is_ascii_digit() || b == b',' ) => inside,
+            _ => return Ok(None),
+        };
+        let number = |n: &str| match n.parse::<u32>() {
+            Ok(n) if n <= 1000 => Ok(n),
+            _ => Err(format!("invalid repetition count {{{}}}", inside)),
+        };
+        let (min, max) = match inside.split_once(',') {
+            None => (number(inside)?, Some(number(inside)?)),
+            Some((min, "")) => (number(min)?, None),
+            Some((min, max)) => (number(min)?, Some(number(max)?)),
+        };
+        if max.is_some_and(|max| max < min ) {
+            return Err(format!("invalid repetition count {{{}}}", inside));
+        }
+        self.i += end+2;
+        Ok(Some((min, max)))
+    }
+
+    fn repetition(&mut self,  mut node: Node) -> Result<Node, String> {
+        loop {
+            let (min, max) = match self.peek() {
+                Some(b'{') => match self.counts()? {
+                    Some(counts) => counts,
+                    None => return Ok(node),
+                },
+                Some(b) => {
+                    let counts = match b {
+                        b'*' => (0, None),
+                        b'+' => (1, None),
+                        b'?' => (0, Some(1)),
+                        _ => return Ok(node),
+                    };
+                    self.i += 1;
+                    counts
+                }
+                None => return Ok(node),
+            };
+            let greedy = self.peek() != Some(b'?');
+            if !greedy {
+                self.i += 1;
+            }
+            node = Node::Repeat { node: Box::new(node),  min,  max,  greedy };
+        }
+    }
+}
+
+enum Instruction {
+    Bytes(ByteSet),
+    Assert(Assertion),
+    /// continue at both, preferring the first
+    Split(usize, usize),
+    Jump(usize),
+    /// store the position in a capture slot
+    Save(usize),
+    Match,
+}
+
+fn compile(node: &Node,  program: &mut Vec<Instruction>) -> Result<(), String> {
+    if program.len() > MAX_INSTRUCTIONS {
+        return Err("the repetitions make it too big".to_string());
+    }
+    match node {
+        Node::Bytes(set) => program.push(Instruction::Bytes(*set)),
+        Node::Assert(assertion) => program.push(Instruction::Assert(*assertion)),
+        Node::Group(inner, None) => compile(inner, program)?,
+        Node::Group(inner, Some(number)) => {
+            program.push(Instruction::Save(number*2));
+            compile(inner, program)?;
+            program.push(Instruction::Save(number*2+1));
+        }
+        Node::Concat(nodes) => {
+            for node in nodes {
+                compile(node, program)?;
+            }
+        }
+        Node::Alternate(alternatives) => {
+            let mut jumps = Vec::new();
+            let (last, others) = alternatives.split_last().unwrap();
+            for alternative in others {
+                let split = program.len();
+                program.push(Instruction::Split(split+1, 0));
+                compile(alternative, program)?;
+                jumps.push(program.len());
+                program.push(Instruction::Jump(0));
+                program[split] = Instruction::Split(split+1, program.len());
+            }
+            compile(last, program)?;
+            for jump in jumps {
+                program[jump] = Instruction::Jump(program.len());
+            }
+        }
+        &Node::Repeat { ref node,  min,  max,  greedy } => {
+            for _ in 0..min {
+                compile(node, program)?;
+            }
+            // each optional repetition can be skipped
+            let optional = match max {
+                Some(max) => max - min,
+                None => 1,
+            };
+            for _ in 0..optional {
+                let split = program.len();
+                program.push(Instruction::Split(0, 0));
+                compile(node, program)?;
+                if max.is_none() {
+                    program.push(Instruction::Jump(split));
+                }
+                let (more, done) = (split+1, program.len());
+                program[split] = match greedy {
+                    true => Instruction::Split(more, done),
+                    false => Instruction::Split(done, more),
+                };
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The threads that are at one position in the text, in order of priority.
+struct Threads {
+    list: Vec<(usize, Box<[Option<usize>]>)>,
+    /// the instructions that have been visited at this position are those equal to stamp
+    visited: Vec<usize>,
+    stamp: usize,
+}
+impl Threads {
+    fn new(instructions: usize) -> Self {
+        Threads { list: Vec::new(),  visited: vec![0; instructions],  stamp: 1 }
+    }
+
+    fn clear(&mut self) {
+        self.list.clear();
+        self.stamp += 1;
+    }
+}
+
+pub struct Regex {
+    program: Vec<Instruction>,
+    groups: usize,
+}
+impl Regex {
+    pub fn new(pattern: &[u8]) -> Result<Self, String> {
+        let mut parser = Parser { pattern,  i: 0,  groups: 0 };
+        let node = parser.alternation()?;
+        if parser.i < pattern.len() {
+            return Err("unmatched )".to_string());
+        }
+        let mut program = vec![Instruction::Save(0)];
+        compile(&node, &mut program)?;
+        program.push(Instruction::Save(1));
+        program.push(Instruction::Match);
+        Ok(Regex { program,  groups: parser.groups })
+    }
+
+    /// Follow jumps, splits and assertions from `pc` and add the threads that end up
+    /// at an instruction which consumes a byte or matches.
+    fn add(&self,  threads: &mut Threads,  pc: usize,  slots: Box<[Option<usize>]>,  text: &[u8],  at: usize) {
+        let mut stack = vec![(pc, slots)];
+        while let Some((mut pc, mut slots)) = stack.pop() {
+            while threads.visited[pc] != threads.stamp {
+                threads.visited[pc] = threads.stamp;
+                match self.program[pc] {
+                    Instruction::Jump(to) => pc = to,
+                    Instruction::Split(first, second) => {
+                        stack.push((second, slots.clone()));
+                        pc = first;
+                    }
+                    Instruction::Save(slot) => {
+                        slots[slot] = Some(at);
+                        pc += 1;
+                    }
+                    Instruction::Assert(assertion) if assertion.holds(text, at) => pc += 1,
+                    Instruction::Assert(_) => break,
+                    Instruction::Bytes(_) | Instruction::Match => {
+                        threads.list.push((pc, slots));
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Find the leftmost match, preferring alternatives in the order they're written.
+    /// Returns the start and end of each group, where the whole match is group 0.
+    fn search(&self,  text: &[u8]) -> Option<Box<[Option<usize>]>> {
+        let mut current = Threads::new(self.program.len());
+        let mut next = Threads::new(self.program.len());
+        let mut matched = None;
+        for at in 0..=text.len() {
+            if matched.is_none() {
+                let slots = vec![None; self.groups*2+2].into_boxed_slice();
+                self.add(&mut current, 0, slots, text, at);
+            } else if current.list.is_empty() {
+                break;
+            }
+            next.clear();
+            for (pc, slots) in current.list.drain(..) {
+                match &self.program[pc] {
+                    Instruction::Match => {
+                        // the remaining threads have lower priority
+                        matched = Some(slots);
+                        break;
+                    }
+                    Instruction::Bytes(set) if at < text.len()  &&  set.contains(text[at]) => {
+                        self.add(&mut next, pc+1, slots, text, at+1);
+                    }
+                    _ => {}
+                }
+            }
+            swap(&mut current, &mut next);
+        }
+        matched
+    }
+
+    /// Whether the regex matches anywhere in `text`.
+    pub fn is_match(&self,  text: &[u8]) -> bool {
+        self.search(text).is_some()
+    }
+
+    /// The part of `text` matched by the first capture group, or by the entire regex
+    /// if it has no groups, or None if the regex doesn't match.
+    /// If the group is not part of the match the range is empty.
+    pub fn key_range(&self,  text: &[u8]) -> Option<Range<usize>> {
+        let slots = self.search(text)?;
+        let group = self.groups.min(1);
+        Some(match (slots[group*2], slots[group*2+1]) {
+            (Some(start), Some(end)) => start..end,
+            _ => slots[0].unwrap()..slots[0].unwrap(),
+        })
+    }
+}
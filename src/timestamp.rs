@@ -0,0 +1,617 @@
+/* logmerge - A program to merge files like tail -f
+ * Copyright (C) 2021 Torbjørn Birch Moltu
+ *
+ * licenced under the terms of the GNU Lesser General Public License
+ * as published by the Free Software Foundation,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Finding and parsing timestamps at the start of log lines.
+//!
+//! Recognizes ISO 8601 (`2024-05-01T12:00:00.123+02:00`, with a space instead of T,
+//! or only the date) and syslog (`May  1 12:00:00`) timestamps,
+//! optionally inside square brackets, numbers of seconds since 1970, access log timestamps,
+//! and custom formats described by strftime-style patterns.
+
+use std::convert::TryFrom;
+use std::ops::Range;
+
+const MONTHS: [&[u8]; 12] = [
+    b"Jan", b"Feb", b"Mar", b"Apr", b"May", b"Jun",
+    b"Jul", b"Aug", b"Sep", b"Oct", b"Nov", b"Dec",
+];
+
+const NANOSECONDS_PER_SECOND: i64 = 1_000_000_000;
+const NANOSECONDS_PER_DAY: i64 = 86400 * NANOSECONDS_PER_SECOND;
+
+/// Syslog timestamps are parsed as if they were in this year,
+/// which is a leap year so that February 29 is valid.
+const YEARLESS: i64 = 2000;
+
+/// --time-format
+#[derive(Clone, PartialEq, Eq, Default, Debug)]
+pub enum TimeFormat {
+    #[default]
+    Iso8601,
+    /// without a year, which has to be guessed with `YearGuess`
+    Syslog,
+    /// seconds, milliseconds or microseconds since 1970
+    Epoch,
+    /// `[02/May/2024:13:37:00 +0200]` anywhere near the start of the line, like in web server access logs
+    Clf,
+    Pattern(Pattern),
+}
+impl TimeFormat {
+    /// Whether timestamps don't include the year, so that it has to be guessed with `YearGuess`.
+    pub fn yearless(&self) -> bool {
+        match self {
+            TimeFormat::Syslog => true,
+            TimeFormat::Pattern(pattern) => !pattern.fields.contains(&Field::Year),
+            _ => false,
+        }
+    }
+
+    /// Parse a timestamp at the start of the line.
+    pub fn parse(&self,  line: &[u8]) -> Option<Time> {
+        match self {
+            TimeFormat::Iso8601 => {
+                let (ends, datetime) = bracketed(line, iso_8601)?;
+                datetime.time(unbracketed(line, ends))
+            }
+            TimeFormat::Syslog => {
+                let (ends, datetime) = bracketed(line, syslog)?;
+                DateTime { year: Some(YEARLESS),  ..datetime }.time(unbracketed(line, ends))
+            }
+            TimeFormat::Epoch => {
+                let (nanoseconds, ends) = parse_epoch(line)?;
+                Some(Time { nanoseconds,  naive: false,  starts: 0,  ends })
+            }
+            TimeFormat::Clf => {
+                // don't look through all of long lines
+                let bracket = line[..line.len().min(256)].iter().position(|&b| b == b'[' )?;
+                let (ends, datetime) = clf(line, bracket+1)?;
+                byte(line, ends, b']')?;
+                datetime.time(bracket+1..ends)
+            }
+            TimeFormat::Pattern(pattern) => {
+                let (datetime, span) = pattern.parse(line)?;
+                datetime.time(span)
+            }
+        }
+    }
+
+    /// Format nanoseconds since 1970 in UTC, for --rewrite-time.
+    pub fn format(&self,  nanoseconds: i64,  out: &mut Vec<u8>) {
+        match self {
+            TimeFormat::Iso8601 => out.extend_from_slice(format(nanoseconds).as_bytes()),
+            TimeFormat::Syslog => {
+                let datetime = DateTime::utc(nanoseconds);
+                out.extend_from_slice(MONTHS[datetime.month as usize - 1]);
+                let time = format!(" {:2} {:02}:{:02}:{:02}", datetime.day, datetime.hour, datetime.minute, datetime.second);
+                out.extend_from_slice(time.as_bytes());
+            }
+            TimeFormat::Epoch => {
+                let seconds = nanoseconds.div_euclid(NANOSECONDS_PER_SECOND);
+                out.extend_from_slice(seconds.to_string().as_bytes());
+                let fraction = format!(".{:09}", nanoseconds.rem_euclid(NANOSECONDS_PER_SECOND));
+                out.extend_from_slice(fraction.trim_end_matches('0').trim_end_matches('.').as_bytes());
+            }
+            TimeFormat::Clf => {
+                let datetime = DateTime::utc(nanoseconds);
+                let time = format!("{:02}/{}/{:04}:{:02}:{:02}:{:02} +0000",
+                        datetime.day, String::from_utf8_lossy(MONTHS[datetime.month as usize - 1]),
+                        datetime.year.unwrap(), datetime.hour, datetime.minute, datetime.second
+                );
+                out.extend_from_slice(time.as_bytes());
+            }
+            TimeFormat::Pattern(pattern) => pattern.format(nanoseconds, out),
+        }
+    }
+}
+
+/// Where the timestamp is when bracketed() returned `ends`.
+fn unbracketed(line: &[u8],  ends: usize) -> Range<usize> {
+    match line.first() {
+        Some(b'[') => 1..ends-1,
+        _ => 0..ends,
+    }
+}
+
+/// A parsed timestamp.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Time {
+    /// since 1970 in UTC, or in an unknown time zone if `naive`
+    pub nanoseconds: i64,
+    /// the timestamp didn't have an offset from UTC, so --tz applies to it
+    pub naive: bool,
+    /// where the timestamp is in what was parsed, for --rewrite-time;
+    /// both are 0 if the time is from a line before
+    pub starts: usize,
+    pub ends: usize,
+}
+
+/// A part of a --time-format pattern.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Field {
+    Literal(u8),
+    /// %Y
+    Year,
+    /// %m
+    Month,
+    /// %b
+    MonthName,
+    /// %d or %e, which can be padded with a space
+    Day,
+    /// %H
+    Hour,
+    /// %M
+    Minute,
+    /// %S
+    Second,
+    /// %f: any number of digits
+    Fraction,
+    /// %z: Z, +HH, +HHMM or +HH:MM
+    Zone,
+}
+
+/// A strftime-style --time-format such as `[%d/%b/%Y %H:%M:%S]`, parsed once.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Pattern {
+    fields: Box<[Field]>,
+}
+impl Pattern {
+    pub fn new(pattern: &[u8]) -> Result<Self, String> {
+        let mut fields = Vec::new();
+        let mut bytes = pattern.iter();
+        while let Some(&b) = bytes.next() {
+            if b != b'%' {
+                fields.push(Field::Literal(b));
+                continue;
+            }
+            fields.push(match bytes.next() {
+                Some(b'Y') => Field::Year,
+                Some(b'm') => Field::Month,
+                Some(b'b') => Field::MonthName,
+                Some(b'd') | Some(b'e') => Field::Day,
+                Some(b'H') => Field::Hour,
+                Some(b'M') => Field::Minute,
+                Some(b'S') => Field::Second,
+                Some(b'f') => Field::Fraction,
+                Some(b'z') => Field::Zone,
+                Some(b'%') => Field::Literal(b'%'),
+                Some(&other) => return Err(format!("unsupported conversion %{}", other as char)),
+                None => return Err("ends with %".to_string()),
+            });
+        }
+        if fields.iter().all(|field| matches!(field, Field::Literal(_)) ) {
+            return Err("has no conversions such as %H".to_string());
+        }
+        Ok(Pattern { fields: fields.into_boxed_slice() })
+    }
+
+    /// Match the pattern against the start of the line,
+    /// and return where the first conversion starts and the last ends.
+    fn parse(&self,  line: &[u8]) -> Option<(DateTime, Range<usize>)> {
+        let mut datetime = DateTime { year: Some(YEARLESS),  month: 1,  day: 1,  ..DateTime::default() };
+        let mut i = 0;
+        let mut span = None::<Range<usize>>;
+        for &field in self.fields.iter() {
+            let starts = i;
+            i = match field {
+                Field::Literal(b) => byte(line, i, b)?,
+                Field::Year => {
+                    let (i, year) = number(line, i, 4)?;
+                    datetime.year = Some(year as i64);
+                    i
+                }
+                Field::MonthName => {
+                    let month = line.get(i..i+3)?;
+                    datetime.month = MONTHS.iter().position(|&name| name == month )? as u32 + 1;
+                    i+3
+                }
+                Field::Day => {
+                    let (i, day) = match number(line, i, 2) {
+                        Some(day) => day,
+                        None => number(line, byte(line, i, b' ')?, 1)?,
+                    };
+                    datetime.day = day;
+                    i
+                }
+                Field::Month | Field::Hour | Field::Minute | Field::Second => {
+                    let (i, value) = number(line, i, 2)?;
+                    match field {
+                        Field::Month => datetime.month = value,
+                        Field::Hour => datetime.hour = value,
+                        Field::Minute => datetime.minute = value,
+                        _ => datetime.second = value,
+                    }
+                    i
+                }
+                Field::Fraction => fraction(line, i, &mut datetime)?,
+                Field::Zone => match line.get(i) {
+                    Some(b'Z') => {
+                        datetime.offset = Some(0);
+                        i+1
+                    }
+                    _ => zone(line, i, &mut datetime)?,
+                },
+            };
+            if !matches!(field, Field::Literal(_)) {
+                span = Some(span.map_or(starts, |span| span.start )..i);
+            }
+        }
+        Some((datetime, span.unwrap()))
+    }
+
+    fn format(&self,  nanoseconds: i64,  out: &mut Vec<u8>) {
+        let datetime = DateTime::utc(nanoseconds);
+        for &field in self.fields.iter() {
+            let formatted = match field {
+                Field::Literal(b) => {
+                    out.push(b);
+                    continue;
+                }
+                Field::MonthName => {
+                    out.extend_from_slice(MONTHS[datetime.month as usize - 1]);
+                    continue;
+                }
+                Field::Year => format!("{:04}", datetime.year.unwrap()),
+                Field::Month => format!("{:02}", datetime.month),
+                Field::Day => format!("{:02}", datetime.day),
+                Field::Hour => format!("{:02}", datetime.hour),
+                Field::Minute => format!("{:02}", datetime.minute),
+                Field::Second => format!("{:02}", datetime.second),
+                Field::Fraction => format!("{:09}", datetime.nanosecond),
+                Field::Zone => "+0000".to_string(),
+            };
+            out.extend_from_slice(formatted.as_bytes());
+        }
+    }
+}
+/// The parts of a timestamp.
+#[derive(Clone, Copy, Default, Debug)]
+struct DateTime {
+    /// None for syslog timestamps
+    year: Option<i64>,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+    nanosecond: u32,
+    /// seconds east of UTC, or None if the timestamp doesn't say
+    offset: Option<i64>,
+}
+impl DateTime {
+    /// Nanoseconds since 1970 in UTC, or None if a part is out of range or the year is unknown.
+    fn nanoseconds(&self) -> Option<i64> {
+        let valid = (1..=12).contains(&self.month)  &&  (1..=31).contains(&self.day)
+                &&  self.hour <= 23  &&  self.minute <= 59  &&  self.second <= 60;
+        if !valid {
+            return None;
+        }
+        let days = days_from_civil(self.year?, self.month, self.day);
+        let seconds = days*86400 + self.hour as i64*3600 + self.minute as i64*60 + self.second as i64
+                - self.offset.unwrap_or(0);
+        seconds.checked_mul(NANOSECONDS_PER_SECOND)?.checked_add(self.nanosecond as i64)
+    }
+
+    fn time(&self,  span: Range<usize>) -> Option<Time> {
+        Some(Time {
+                nanoseconds: self.nanoseconds()?,  naive: self.offset.is_none(),
+                starts: span.start,  ends: span.end,
+        })
+    }
+
+    /// The date and time in UTC of nanoseconds since 1970.
+    fn utc(nanoseconds: i64) -> Self {
+        let seconds = nanoseconds.div_euclid(NANOSECONDS_PER_SECOND);
+        let (year, month, day) = civil_from_days(seconds.div_euclid(86400));
+        let time = seconds.rem_euclid(86400) as u32;
+        DateTime {
+            year: Some(year),  month,  day,
+            hour: time/3600,  minute: time/60%60,  second: time%60,
+            nanosecond: nanoseconds.rem_euclid(NANOSECONDS_PER_SECOND) as u32,
+            offset: Some(0),
+        }
+    }
+}
+
+/// The number of days since 1970-01-01 in the proleptic Gregorian calendar.
+pub fn days_from_civil(year: i64,  month: u32,  day: u32) -> i64 {
+    let year = if month <= 2 {year-1} else {year};
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153*((month as i64 + 9) % 12) + 2)/5 + day as i64 - 1;
+    let day_of_era = year_of_era*365 + year_of_era/4 - year_of_era/100 + day_of_year;
+    era*146097 + day_of_era - 719468
+}
+
+/// The inverse of days_from_civil().
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era/1460 + day_of_era/36524 - day_of_era/146096) / 365;
+    let day_of_year = day_of_era - (365*year_of_era + year_of_era/4 - year_of_era/100);
+    let shifted_month = (5*day_of_year + 2)/153;
+    let day = day_of_year - (153*shifted_month + 2)/5 + 1;
+    let month = if shifted_month < 10 {shifted_month+3} else {shifted_month-9};
+    let year = year_of_era + era*400 + (month <= 2) as i64;
+    (year, month as u32, day as u32)
+}
+
+/// Where `n` digits starting at `i` end, or None if there aren't that many.
+fn digits(line: &[u8],  i: usize,  n: usize) -> Option<usize> {
+    let end = i.checked_add(n)?;
+    match line.get(i..end) {
+        Some(digits) if digits.iter().all(u8::is_ascii_digit) => Some(end),
+        _ => None,
+    }
+}
+
+/// Parse `n` digits starting at `i`, returning where they end and their value.
+fn number(line: &[u8],  i: usize,  n: usize) -> Option<(usize, u32)> {
+    let end = digits(line, i, n)?;
+    let value = line[i..end].iter().fold(0, |value, &digit| value*10 + (digit - b'0') as u32 );
+    Some((end, value))
+}
+
+/// Where `b` at `i` ends, or None if it isn't there.
+fn byte(line: &[u8],  i: usize,  b: u8) -> Option<usize> {
+    match line.get(i) {
+        Some(&found) if found == b => Some(i+1),
+        _ => None,
+    }
+}
+
+/// Parse HH:MM, :SS and .fraction, returning where they end.
+fn time(line: &[u8],  i: usize,  datetime: &mut DateTime) -> Option<usize> {
+    let (i, hour) = number(line, i, 2)?;
+    let i = byte(line, i, b':')?;
+    let (i, minute) = number(line, i, 2)?;
+    datetime.hour = hour;
+    datetime.minute = minute;
+    let (i, second) = match byte(line, i, b':').and_then(|i| number(line, i, 2) ) {
+        Some(second) => second,
+        None => return Some(i),
+    };
+    datetime.second = second;
+    match byte(line, i, b'.').or_else(|| byte(line, i, b',') ) {
+        Some(fraction) => Some(self::fraction(line, fraction, datetime).unwrap_or(i)),
+        None => Some(i),
+    }
+}
+
+/// Parse the digits of a fraction of a second, returning where they end.
+fn fraction(line: &[u8],  i: usize,  datetime: &mut DateTime) -> Option<usize> {
+    let length = line[i.min(line.len())..].iter().take_while(|b| b.is_ascii_digit() ).count();
+    if length == 0 {
+        return None;
+    }
+    // ignore digits beyond nanoseconds
+    let (_, value) = number(line, i, length.min(9)).unwrap();
+    datetime.nanosecond = value * 10u32.pow(9 - length.min(9) as u32);
+    Some(i + length)
+}
+
+/// Parse a +HH, +HHMM or +HH:MM offset from UTC, returning where it ends.
+fn zone(line: &[u8],  i: usize,  datetime: &mut DateTime) -> Option<usize> {
+    let sign = *line.get(i).filter(|&&sign| sign == b'+'  ||  sign == b'-' )?;
+    let (hours_end, hours) = number(line, i+1, 2)?;
+    let minutes_start = byte(line, hours_end, b':').unwrap_or(hours_end);
+    let (ends, minutes) = number(line, minutes_start, 2).unwrap_or((hours_end, 0));
+    let offset = hours as i64*3600 + minutes as i64*60;
+    datetime.offset = Some(if sign == b'-' {-offset} else {offset});
+    Some(ends)
+}
+
+/// Parse an ISO 8601 date and optional time, returning where it ends.
+fn iso_8601(line: &[u8],  i: usize) -> Option<(usize, DateTime)> {
+    let (i, year) = number(line, i, 4)?;
+    let i = byte(line, i, b'-')?;
+    let (i, month) = number(line, i, 2)?;
+    let i = byte(line, i, b'-')?;
+    let (date_ends, day) = number(line, i, 2)?;
+    let mut datetime = DateTime { year: Some(year as i64),  month,  day,  ..DateTime::default() };
+    let time_ends = match byte(line, date_ends, b'T').or_else(|| byte(line, date_ends, b' ') ) {
+        Some(time) => match self::time(line, time, &mut datetime) {
+            Some(time_ends) => time_ends,
+            None => return Some((date_ends, datetime)),
+        },
+        None => return Some((date_ends, datetime)),
+    };
+    // time zone
+    let ends = match line.get(time_ends) {
+        Some(b'Z') => {
+            datetime.offset = Some(0);
+            time_ends+1
+        }
+        _ => zone(line, time_ends, &mut datetime).unwrap_or(time_ends),
+    };
+    Some((ends, datetime))
+}
+
+/// Parse a syslog timestamp such as `May  1 12:00:00`, returning where it ends.
+fn syslog(line: &[u8],  i: usize) -> Option<(usize, DateTime)> {
+    let month = line.get(i..i+3)?;
+    let month = MONTHS.iter().position(|&name| name == month )? as u32 + 1;
+    let i = byte(line, i+3, b' ')?;
+    let (i, day) = match number(line, i, 2) {
+        Some(day) => day,
+        None => number(line, byte(line, i, b' ').unwrap_or(i), 1)?,
+    };
+    let i = byte(line, i, b' ')?;
+    let mut datetime = DateTime { month,  day,  ..DateTime::default() };
+    let ends = time(line, i, &mut datetime)?;
+    Some((ends, datetime))
+}
+
+/// Parse a common log format timestamp such as `02/May/2024:13:37:00 +0200`, returning where it ends.
+fn clf(line: &[u8],  i: usize) -> Option<(usize, DateTime)> {
+    let (i, day) = number(line, i, 2)?;
+    let i = byte(line, i, b'/')?;
+    let month = line.get(i..i+3)?;
+    let month = MONTHS.iter().position(|&name| name == month )? as u32 + 1;
+    let i = byte(line, i+3, b'/')?;
+    let (i, year) = number(line, i, 4)?;
+    let i = byte(line, i, b':')?;
+    let mut datetime = DateTime { year: Some(year as i64),  month,  day,  ..DateTime::default() };
+    let i = time(line, i, &mut datetime)?;
+    let ends = match byte(line, i, b' ') {
+        Some(zone) => self::zone(line, zone, &mut datetime).unwrap_or(i),
+        None => i,
+    };
+    Some((ends, datetime))
+}
+
+/// Parses a timestamp starting at an index, and returns where it ends.
+type Parser = fn(&[u8], usize) -> Option<(usize, DateTime)>;
+
+/// Apply a parser to the start of the line, or inside square brackets.
+fn bracketed(line: &[u8],  parse: Parser) -> Option<(usize, DateTime)> {
+    match line.first() {
+        Some(b'[') => {
+            let (ends, datetime) = parse(line, 1)?;
+            Some((byte(line, ends, b']')?, datetime))
+        }
+        _ => parse(line, 0),
+    }
+}
+
+/// The length of the timestamp at the start of the line and any whitespace after it,
+/// or 0 if the line doesn't start with a timestamp.
+pub fn leading(line: &[u8]) -> usize {
+    match bracketed(line, iso_8601).or_else(|| bracketed(line, syslog) ) {
+        Some((ends, _)) => {
+            let whitespace = line[ends..].iter().take_while(|&&b| b == b' '  ||  b == b'\t' ).count();
+            ends + whitespace
+        }
+        None => 0,
+    }
+}
+
+/// Nanoseconds since 1970 of a number at the start of the line, with an optional fraction.
+/// Whether it's seconds, milliseconds, microseconds or nanoseconds is guessed from how big it is,
+/// so that 1714569600 and 1714569600123 are both in May 2024.
+fn parse_epoch(line: &[u8]) -> Option<(i64, usize)> {
+    let integer = line.iter().take_while(|b| b.is_ascii_digit() ).count();
+    if integer == 0  ||  integer > 19 {
+        return None;
+    }
+    let value = line[..integer].iter().fold(0i128, |value, &digit| value*10 + (digit - b'0') as i128 );
+    let unit = match value {
+        0..=99_999_999_999 => NANOSECONDS_PER_SECOND as i128,
+        100_000_000_000..=99_999_999_999_999 => 1_000_000,
+        100_000_000_000_000..=99_999_999_999_999_999 => 1_000,
+        _ => 1,
+    };
+    let mut nanoseconds = value * unit;
+    let mut ends = integer;
+    if let Some(b'.') = line.get(integer) {
+        // digits beyond what fits in a nanosecond are ignored
+        let mut scale = unit;
+        for &digit in line[integer+1..].iter().take_while(|b| b.is_ascii_digit() ) {
+            scale /= 10;
+            nanoseconds += (digit - b'0') as i128 * scale;
+            ends += 1;
+        }
+        ends += 1;
+    }
+    Some((i64::try_from(nanoseconds).ok()?, ends))
+}
+
+/// The year of a time in nanoseconds since 1970.
+fn year_of(nanoseconds: i64) -> i64 {
+    civil_from_days(nanoseconds.div_euclid(NANOSECONDS_PER_DAY)).0
+}
+
+/// Move a time parsed by `TimeFormat::Syslog` to the same date and time in another year.
+fn in_year(yearless: i64,  year: i64) -> i64 {
+    let days = yearless.div_euclid(NANOSECONDS_PER_DAY);
+    let (_, month, day) = civil_from_days(days);
+    let moved = days_from_civil(year, month, day) - days;
+    yearless + moved*NANOSECONDS_PER_DAY
+}
+
+/// Syslog timestamps don't have a year, so for each file it's guessed from when the file
+/// was last modified, unless --assume-year is given, and is increased when
+/// the time jumps backwards by more than eleven months.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct YearGuess {
+    year: Option<i64>,
+    /// the previous time as parsed by `TimeFormat::Syslog`
+    previous: Option<i64>,
+}
+impl YearGuess {
+    /// Get the full time from one parsed by `TimeFormat::Syslog`.
+    pub fn apply(&mut self,  yearless: i64,  assumed: Option<i64>,  modified: i64) -> i64 {
+        let year = match (self.year, self.previous) {
+            (Some(year), Some(previous)) if previous - yearless > 335*NANOSECONDS_PER_DAY => year+1,
+            (Some(year), _) => year,
+            // lines can't be newer than the file, so they must be from the year before
+            (None, _) => assumed.unwrap_or_else(|| {
+                let year = year_of(modified);
+                if in_year(yearless, year) > modified + NANOSECONDS_PER_DAY {year-1} else {year}
+            }),
+        };
+        self.year = Some(year);
+        self.previous = Some(yearless);
+        in_year(yearless, year)
+    }
+}
+
+/// Nanoseconds since 1970 of a time relative to now like `-15min`, `+2h` or `now`.
+pub fn parse_relative(text: &[u8],  now: i64) -> Option<i64> {
+    let (sign, text) = match text {
+        b"now" => return Some(now),
+        [b'-', rest @ ..] => (-1, rest),
+        [b'+', rest @ ..] => (1, rest),
+        _ => return None,
+    };
+    let length = text.iter().take_while(|b| b.is_ascii_digit() ).count();
+    let amount = std::str::from_utf8(&text[..length]).ok()?.parse::<i64>().ok()?;
+    let unit = match &text[length..] {
+        b"s" | b"sec" | b"second" | b"seconds" => NANOSECONDS_PER_SECOND,
+        b"m" | b"min" | b"minute" | b"minutes" => 60 * NANOSECONDS_PER_SECOND,
+        b"h" | b"hour" | b"hours" => 3600 * NANOSECONDS_PER_SECOND,
+        b"d" | b"day" | b"days" => NANOSECONDS_PER_DAY,
+        _ => return None,
+    };
+    now.checked_add(sign * amount.checked_mul(unit)?)
+}
+
+/// Format microseconds since 1970 as an ISO 8601 timestamp in UTC with all six digits of the fraction,
+/// so that they sort correctly as bytes.
+pub fn format_microseconds(microseconds: i64) -> String {
+    let datetime = DateTime::utc(microseconds.saturating_mul(1000));
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+            datetime.year.unwrap(), datetime.month, datetime.day,
+            datetime.hour, datetime.minute, datetime.second, datetime.nanosecond/1000
+    )
+}
+
+/// Format nanoseconds since 1970 as an ISO 8601 timestamp in UTC, for --debug.
+pub fn format(nanoseconds: i64) -> String {
+    let seconds = nanoseconds.div_euclid(NANOSECONDS_PER_SECOND);
+    let fraction = nanoseconds.rem_euclid(NANOSECONDS_PER_SECOND);
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86400));
+    let time = seconds.rem_euclid(86400);
+    let mut formatted = format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            year, month, day, time/3600, time/60%60, time%60
+    );
+    if fraction != 0 {
+        let fraction = format!(".{:09}", fraction);
+        formatted.push_str(fraction.trim_end_matches('0'));
+    }
+    formatted.push('Z');
+    formatted
+}
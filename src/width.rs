@@ -0,0 +1,93 @@
+/* logmerge - A program to merge files like tail -f
+ * Copyright (C) 2021 Torbjørn Birch Moltu
+ *
+ * licenced under the terms of the GNU Lesser General Public License
+ * as published by the Free Software Foundation,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+
+//! How many columns labels take up in a terminal, for --align-prefixes and --max-prefix-width.
+
+/// What truncated labels end with, which is one column wide.
+const ELLIPSIS: &str = "…";
+/// What truncated labels that aren't UTF-8 end with, as the width of those is in bytes.
+const BYTE_ELLIPSIS: u8 = b'~';
+
+/// Code points that are zero or two columns wide, as inclusive ranges.
+/// This covers combining marks, CJK, Hangul, fullwidth forms and emoji,
+/// but doesn't try to be as complete as the Unicode data files.
+const ZERO: &[(u32, u32)] = &[
+    (0x0300, 0x036f), (0x0483, 0x0489), (0x0591, 0x05bd), (0x0610, 0x061a), (0x064b, 0x065f),
+    (0x0e31, 0x0e31), (0x0e34, 0x0e3a), (0x0e47, 0x0e4e), (0x1ab0, 0x1aff), (0x1dc0, 0x1dff),
+    (0x200b, 0x200f), (0x20d0, 0x20ff), (0xfe00, 0xfe0f), (0xfe20, 0xfe2f), (0xfeff, 0xfeff),
+];
+const WIDE: &[(u32, u32)] = &[
+    (0x1100, 0x115f), (0x231a, 0x231b), (0x2329, 0x232a), (0x23e9, 0x23ec), (0x25fd, 0x25fe),
+    (0x2614, 0x2615), (0x2648, 0x2653), (0x26aa, 0x26ab), (0x26bd, 0x26be), (0x26f5, 0x26f5),
+    (0x2705, 0x2705), (0x274c, 0x274c), (0x2753, 0x2755), (0x2795, 0x2797), (0x2b50, 0x2b50),
+    (0x2e80, 0x303e), (0x3041, 0x33ff), (0x3400, 0x4dbf), (0x4e00, 0x9fff), (0xa000, 0xa4cf),
+    (0xa960, 0xa97f), (0xac00, 0xd7a3), (0xf900, 0xfaff), (0xfe10, 0xfe19), (0xfe30, 0xfe6f),
+    (0xff00, 0xff60), (0xffe0, 0xffe6), (0x1f300, 0x1f64f), (0x1f900, 0x1f9ff), (0x20000, 0x3fffd),
+];
+
+fn in_ranges(c: char,  ranges: &[(u32, u32)]) -> bool {
+    let c = c as u32;
+    ranges.binary_search_by(|&(start, end)| {
+        if end < c {
+            std::cmp::Ordering::Less
+        } else if start > c {
+            std::cmp::Ordering::Greater
+        } else {
+            std::cmp::Ordering::Equal
+        }
+    }).is_ok()
+}
+
+fn char_width(c: char) -> usize {
+    if in_ranges(c, ZERO) {
+        0
+    } else if in_ranges(c, WIDE) {
+        2
+    } else {
+        1
+    }
+}
+
+/// The display width of UTF-8 text, or the number of bytes if it isn't UTF-8.
+pub fn width(text: &[u8]) -> usize {
+    match std::str::from_utf8(text) {
+        Ok(text) => text.chars().map(char_width).sum(),
+        Err(_) => text.len(),
+    }
+}
+
+/// Shorten text to at most max_width columns by cutting off the end and adding an ellipsis.
+/// max_width must not be zero.
+pub fn truncate(text: &[u8],  max_width: usize) -> Vec<u8> {
+    if width(text) <= max_width {
+        return text.to_vec();
+    }
+    let keep = max_width.saturating_sub(1);
+    match std::str::from_utf8(text) {
+        Ok(text) => {
+            let mut used = 0;
+            let end = text.char_indices()
+                    .find(|&(_, c)| {
+                        used += char_width(c);
+                        used > keep
+                    })
+                    .map_or(text.len(), |(end, _)| end );
+            [&text[..end], ELLIPSIS].concat().into_bytes()
+        }
+        Err(_) => [&text[..keep], &[BYTE_ELLIPSIS]].concat(),
+    }
+}
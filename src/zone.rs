@@ -0,0 +1,350 @@
+/* logmerge - A program to merge files like tail -f
+ * Copyright (C) 2021 Torbjørn Birch Moltu
+ *
+ * licenced under the terms of the GNU Lesser General Public License
+ * as published by the Free Software Foundation,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Time zones for --tz: fixed offsets, POSIX TZ strings such as `CET-1CEST,M3.5.0,M10.5.0/3`
+//! and files from the tz database in /usr/share/zoneinfo.
+//!
+//! Local times that happen twice when daylight saving time ends are taken as the first,
+//! and local times that are skipped when it starts are taken as if the clocks hadn't changed yet,
+//! which puts them after the change.
+
+use crate::timestamp::{days_from_civil, civil_from_days};
+use std::env;
+use std::fs;
+use std::path::{Path, Component};
+
+const NANOSECONDS_PER_SECOND: i64 = 1_000_000_000;
+
+/// When daylight saving time starts or ends in a POSIX TZ string.
+#[derive(Clone, Copy, Debug)]
+enum Rule {
+    /// Jn: day 1-365, not counting February 29
+    Julian(i64),
+    /// n: day 0-365, counting February 29
+    Day(i64),
+    /// Mm.w.d: day d (0 is Sunday) of week w (5 means the last) of month m
+    Weekday { month: u32,  week: i64,  weekday: i64 },
+}
+impl Rule {
+    /// The days since 1970 of the date in a year.
+    fn date(self,  year: i64) -> i64 {
+        let january = days_from_civil(year, 1, 1);
+        match self {
+            Rule::Julian(day) => {
+                let leap = days_from_civil(year, 3, 1) - days_from_civil(year, 2, 28) == 2;
+                january + day - 1 + if leap && day >= 60 {1} else {0}
+            }
+            Rule::Day(day) => january + day,
+            Rule::Weekday { month,  week,  weekday } => {
+                let first = days_from_civil(year, month, 1);
+                let next_month = match month {
+                    12 => days_from_civil(year+1, 1, 1),
+                    _ => days_from_civil(year, month+1, 1),
+                };
+                // 1970-01-01 was a Thursday
+                let mut date = first + (weekday - (first+4)).rem_euclid(7) + (week-1)*7;
+                while date >= next_month {
+                    date -= 7;
+                }
+                date
+            }
+        }
+    }
+}
+
+/// The daylight saving time part of a POSIX TZ string.
+#[derive(Clone, Copy, Debug)]
+struct Dst {
+    /// seconds east of UTC
+    offset: i64,
+    /// the date and the local standard time in seconds
+    starts: (Rule, i64),
+    /// the date and the local daylight saving time in seconds
+    ends: (Rule, i64),
+}
+
+/// A POSIX TZ string, which tz database files also end with to describe the future.
+#[derive(Clone, Copy, Debug)]
+struct Posix {
+    /// of standard time, in seconds east of UTC
+    offset: i64,
+    dst: Option<Dst>,
+}
+impl Posix {
+    fn parse(tz: &[u8]) -> Option<Self> {
+        let i = name(tz, 0)?;
+        let (i, west) = offset(tz, i)?;
+        if i == tz.len() {
+            return Some(Posix { offset: -west,  dst: None });
+        }
+        let i = name(tz, i)?;
+        let (i, dst_offset) = match offset(tz, i) {
+            Some((i, west)) => (i, -west),
+            None => (i, 3600 - west),
+        };
+        let (i, starts, ends) = match tz.get(i) {
+            // the US rules are the default
+            None => (i, (Rule::Weekday { month: 3,  week: 2,  weekday: 0 }, 7200),
+                        (Rule::Weekday { month: 11,  week: 1,  weekday: 0 }, 7200)),
+            Some(b',') => {
+                let (i, starts) = rule(tz, i+1)?;
+                if tz.get(i) != Some(&b',') {
+                    return None;
+                }
+                let (i, ends) = rule(tz, i+1)?;
+                (i, starts, ends)
+            }
+            Some(_) => return None,
+        };
+        match i == tz.len() {
+            true => Some(Posix { offset: -west,  dst: Some(Dst { offset: dst_offset,  starts,  ends }) }),
+            false => None,
+        }
+    }
+
+    fn offset_at(&self,  utc: i64) -> i64 {
+        let dst = match self.dst {
+            Some(dst) => dst,
+            None => return self.offset,
+        };
+        let (year, _, _) = civil_from_days((utc + self.offset).div_euclid(86400));
+        let starts = dst.starts.0.date(year)*86400 + dst.starts.1 - self.offset;
+        let ends = dst.ends.0.date(year)*86400 + dst.ends.1 - dst.offset;
+        // on the southern hemisphere daylight saving time spans New Year
+        let in_dst = match starts < ends {
+            true => starts <= utc  &&  utc < ends,
+            false => !(ends <= utc  &&  utc < starts),
+        };
+        if in_dst {dst.offset} else {self.offset}
+    }
+}
+
+/// Parse digits, returning where they end and their value.
+fn integer(tz: &[u8],  i: usize) -> Option<(usize, i64)> {
+    let length = tz[i.min(tz.len())..].iter().take_while(|b| b.is_ascii_digit() ).count();
+    if length == 0  ||  length > 6 {
+        return None;
+    }
+    let value = tz[i..i+length].iter().fold(0, |value, &digit| value*10 + (digit - b'0') as i64 );
+    Some((i+length, value))
+}
+
+/// Skip the name of a zone like CET or <+03>, returning where it ends.
+fn name(tz: &[u8],  i: usize) -> Option<usize> {
+    if tz.get(i) == Some(&b'<') {
+        let length = tz[i..].iter().position(|&b| b == b'>' )?;
+        return Some(i+length+1);
+    }
+    let length = tz[i.min(tz.len())..].iter().take_while(|b| b.is_ascii_alphabetic() ).count();
+    if length >= 3 {Some(i+length)} else {None}
+}
+
+/// Parse [+-]hh[:mm[:ss]], returning where it ends and the number of seconds.
+fn offset(tz: &[u8],  i: usize) -> Option<(usize, i64)> {
+    let (i, sign) = match tz.get(i) {
+        Some(b'-') => (i+1, -1),
+        Some(b'+') => (i+1, 1),
+        _ => (i, 1),
+    };
+    let (mut i, mut seconds) = integer(tz, i)?;
+    seconds *= 3600;
+    for unit in [60, 1] {
+        match tz.get(i) {
+            Some(b':') => {
+                let (end, value) = integer(tz, i+1)?;
+                i = end;
+                seconds += value*unit;
+            }
+            _ => break,
+        }
+    }
+    Some((i, sign*seconds))
+}
+
+/// Parse a date and an optional /time, returning where it ends.
+fn rule(tz: &[u8],  i: usize) -> Option<(usize, (Rule, i64))> {
+    let (i, rule) = match tz.get(i) {
+        Some(b'J') => {
+            let (i, day) = integer(tz, i+1)?;
+            (i, Rule::Julian(day))
+        }
+        Some(b'M') => {
+            let (i, month) = integer(tz, i+1)?;
+            let (i, week) = integer(tz, i.checked_add(1).filter(|&i| tz.get(i-1) == Some(&b'.') )?)?;
+            let (i, weekday) = integer(tz, i.checked_add(1).filter(|&i| tz.get(i-1) == Some(&b'.') )?)?;
+            if !(1..=12).contains(&month)  ||  !(1..=5).contains(&week)  ||  weekday > 6 {
+                return None;
+            }
+            (i, Rule::Weekday { month: month as u32,  week,  weekday })
+        }
+        _ => {
+            let (i, day) = integer(tz, i)?;
+            (i, Rule::Day(day))
+        }
+    };
+    match tz.get(i) {
+        Some(b'/') => {
+            let (i, time) = offset(tz, i+1)?;
+            Some((i, (rule, time)))
+        }
+        _ => Some((i, (rule, 7200))),
+    }
+}
+
+/// Read the big-endian signed integer of 4 or 8 bytes at `i`.
+fn signed(data: &[u8],  i: usize,  size: usize) -> Option<i64> {
+    let bytes = data.get(i..i+size)?;
+    let unsigned = bytes.iter().fold(0u64, |value, &b| value << 8 | b as u64 );
+    let shift = 64 - 8*size as u32;
+    Some(((unsigned << shift) as i64) >> shift)
+}
+
+/// The offsets of a time zone.
+#[derive(Clone, Debug)]
+pub struct Zone {
+    /// when the offset changes, in seconds since 1970 in UTC, and the offset in seconds after it
+    transitions: Vec<(i64, i64)>,
+    /// the offset before the first transition
+    initial: i64,
+    /// what happens after the last transition
+    rule: Option<Posix>,
+}
+impl Zone {
+    /// Find a time zone by name, such as `Europe/Oslo`, `UTC`, `+02:00` or `local`.
+    pub fn new(name: &[u8]) -> Result<Self, String> {
+        let fixed = |offset| Zone { transitions: Vec::new(),  initial: offset,  rule: None };
+        match name {
+            b"" => return Err("is empty".to_string()),
+            b"UTC" | b"utc" | b"Z" => return Ok(fixed(0)),
+            [b'+', ..] | [b'-', ..] => return match offset(name, 0) {
+                Some((end, seconds)) if end == name.len() => Ok(fixed(seconds)),
+                _ => Err("is not an offset like +02:00".to_string()),
+            },
+            b"local" => return match env::var_os("TZ") {
+                Some(tz) if !tz.is_empty() => {
+                    let tz = crate::args::os_to_bytes(tz);
+                    Zone::new(tz.strip_prefix(b":").unwrap_or(&tz))
+                }
+                _ => Zone::read(Path::new("/etc/localtime")),
+            },
+            _ => {}
+        }
+        let name_os = crate::args::bytes_to_os(name);
+        let path = Path::new(&name_os);
+        if path.is_absolute() {
+            return Zone::read(path);
+        }
+        if path.components().all(|component| matches!(component, Component::Normal(_)) ) {
+            let directory = env::var_os("TZDIR").unwrap_or_else(|| "/usr/share/zoneinfo".into() );
+            if let Ok(zone) = Zone::read(&Path::new(&directory).join(path)) {
+                return Ok(zone);
+            }
+        }
+        match Posix::parse(name) {
+            Some(posix) => Ok(Zone { transitions: Vec::new(),  initial: posix.offset,  rule: Some(posix) }),
+            None => Err("is not a known time zone".to_string()),
+        }
+    }
+
+    /// Read a TZif file from the tz database.
+    fn read(path: &Path) -> Result<Self, String> {
+        let data = fs::read(path).map_err(|e| format!("cannot read {}: {}", path.display(), e) )?;
+        Zone::parse_tzif(&data).ok_or_else(|| format!("{} is not a tz database file", path.display()) )
+    }
+
+    fn parse_tzif(data: &[u8]) -> Option<Self> {
+        let header = |at: usize| -> Option<[usize; 6]> {
+            if data.get(at..at+4)? != b"TZif" {
+                return None;
+            }
+            let mut counts = [0; 6];
+            for (i, count) in counts.iter_mut().enumerate() {
+                *count = signed(data, at+20+4*i, 4)? as u32 as usize;
+            }
+            Some(counts)
+        };
+        let mut counts = header(0)?;
+        let (mut at, size) = (0, 4);
+        let version_2 = *data.get(4)? >= b'2';
+        let block_length = |[utc, standard, leap, times, types, chars]: [usize; 6],  size: usize| {
+            times*(size+1) + types*6 + chars + leap*(size+4) + standard + utc
+        };
+        let size = if version_2 {
+            // skip the version 1 data, which only has 32-bit times
+            at = 44 + block_length(counts, size);
+            counts = header(at)?;
+            8
+        } else {
+            size
+        };
+        let [_, _, _, times, types, _] = counts;
+        if types == 0 {
+            return None;
+        }
+        let data_starts = at + 44;
+        let indexes_start = data_starts + times*size;
+        let types_start = indexes_start + times;
+        let type_offset = |index: usize| signed(data, types_start + 6*index, 4) ;
+        let mut transitions = Vec::with_capacity(times);
+        for i in 0..times {
+            let index = *data.get(indexes_start + i)? as usize;
+            if index >= types {
+                return None;
+            }
+            transitions.push((signed(data, data_starts + i*size, size)?, type_offset(index)?));
+        }
+        let footer_starts = data_starts + block_length(counts, size) + 1;
+        let rule = match data.get(footer_starts..) {
+            Some(footer) if version_2 => {
+                let length = footer.iter().position(|&b| b == b'\n' )?;
+                Posix::parse(&footer[..length])
+            }
+            _ => None,
+        };
+        Some(Zone { transitions,  initial: type_offset(0)?,  rule })
+    }
+
+    /// The offset from UTC in seconds at a time in seconds since 1970 in UTC.
+    fn offset_at(&self,  utc: i64) -> i64 {
+        if let Some(rule) = &self.rule {
+            if self.transitions.last().is_none_or(|&(last, _)| utc >= last ) {
+                return rule.offset_at(utc);
+            }
+        }
+        match self.transitions.partition_point(|&(at, _)| at <= utc ) {
+            0 => self.initial,
+            after => self.transitions[after-1].1,
+        }
+    }
+
+    /// Convert nanoseconds since 1970 in local time to UTC.
+    pub fn to_utc(&self,  local: i64) -> i64 {
+        let seconds = local.div_euclid(NANOSECONDS_PER_SECOND);
+        // offsets are less than a day, so a change near the time is between these
+        let before = self.offset_at(seconds - 86400);
+        let after = self.offset_at(seconds + 86400);
+        let valid = |offset| self.offset_at(seconds - offset) == offset ;
+        let offset = match (valid(before), valid(after)) {
+            // happens twice, and the first has the larger offset
+            (true, true) => before.max(after),
+            (true, false) => before,
+            (false, true) => after,
+            // skipped
+            (false, false) => before,
+        };
+        local - offset*NANOSECONDS_PER_SECOND
+    }
+}